@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Png,
+    Svg,
+    Csv,
+    Json,
+}
+
+/// Simulates prices on a market where transport between cities is costly.
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Scenario in JSON format, optionally gzip-compressed (`.gz`)
+    pub input: PathBuf,
+    /// File to save the results to
+    pub output: PathBuf,
+    /// Number of turns, overrides the one given in the scenario
+    #[arg(long)]
+    pub turns: Option<usize>,
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = Format::Png)]
+    pub format: Format,
+    /// Don't print anything on success
+    #[arg(long)]
+    pub quiet: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use crate::cli::Args;
+    use crate::cli::Format;
+
+    #[test]
+    fn positional_only() {
+        let args = Args::try_parse_from(["global_market", "in.json", "out.png"]).unwrap();
+        assert_eq!(args.input, PathBuf::from("in.json"));
+        assert_eq!(args.output, PathBuf::from("out.png"));
+        assert_eq!(args.turns, None);
+        assert_eq!(args.format, Format::Png);
+        assert!(!args.quiet);
+    }
+
+    #[test]
+    fn all_flags() {
+        let args = Args::try_parse_from([
+            "global_market",
+            "--turns",
+            "7",
+            "in.json",
+            "out.csv",
+            "--format",
+            "csv",
+            "--quiet",
+        ])
+        .unwrap();
+        assert_eq!(args.turns, Some(7));
+        assert_eq!(args.format, Format::Csv);
+        assert!(args.quiet);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Args::try_parse_from(["global_market", "in.json"]).is_err());
+        assert!(Args::try_parse_from(["global_market", "a", "b", "--format", "gif"]).is_err());
+        assert!(Args::try_parse_from(["global_market", "a", "b", "--turns", "x"]).is_err());
+    }
+}
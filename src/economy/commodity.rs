@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifier of a traded good. Markets of different commodities share the
+/// geography, but are cleared independently of each other.
+#[derive(
+    Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct Commodity(pub usize);
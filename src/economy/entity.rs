@@ -1,61 +1,1015 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use crate::economy::commodity::Commodity;
 use crate::economy::function::Demand;
+use crate::economy::function::FunctionAbstract;
 use crate::economy::function::Supply;
 use crate::economy::geography::CityId;
+use crate::economy::geography::Geography;
 use crate::economy::market::Market;
+use crate::economy::types::InnerValue;
+use crate::economy::types::Price;
+use crate::economy::types::Revenue;
+use crate::economy::types::Volume;
 
 use serde::{Deserialize, Serialize};
 
+/// Identifier given to a producer or a consumer in the scenario. Producers
+/// and consumers are numbered independently.
+pub type AgentId = usize;
+
+/// Participant of the market. Agent registers its demand or supply in the
+/// market and adjusts it after every turn.
+pub trait MarketAgent: Debug {
+    fn register(&self, market: &mut Market);
+    fn unregister(&self, market: &mut Market);
+
+    /// Reacts to the prices of the last turn. `turn` is the index of the
+    /// turn that is about to be simulated.
+    fn update(&mut self, market: &mut Market, turn: usize);
+
+    /// Identifier of the agent if it is a producer that has one.
+    fn producer_id(&self) -> Option<AgentId> {
+        None
+    }
+
+    /// Identifier of the agent if it is a consumer that has one.
+    fn consumer_id(&self) -> Option<AgentId> {
+        None
+    }
+
+    /// The agent, if it is a producer.
+    fn as_producer(&self) -> Option<&Producer> {
+        None
+    }
+
+    /// The agent, if it is a consumer.
+    fn as_consumer(&self) -> Option<&Consumer> {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+pub enum ProducerAlgorithm {
+    /// Producer offers its whole cost curve, so its production follows the
+    /// price within the same turn.
+    #[default]
+    Static,
+    /// Producer commits to a volume before the market is cleared. After each
+    /// turn the volume is moved by given fraction toward the volume at which
+    /// marginal cost equals the price received. With the fraction equal to 1
+    /// production settles only when supply reacts to price less than demand
+    /// does, smaller fractions damp the oscillations.
+    Regular(InnerValue),
+    /// Producer splits its supply between target cities, paying the cheapest
+    /// cost of transport to deliver it, possibly through other cities. After
+    /// each turn given fraction of the supply is moved to the targets with
+    /// the highest price net of transport. Targets in one price group have
+    /// equal net prices, so the split changes only between groups.
+    Advanced(InnerValue),
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Producer {
+    #[serde(default)]
+    id: Option<AgentId>,
     city: CityId,
     production_costs: Supply,
+    #[serde(default)]
+    commodity: Commodity,
+    #[serde(default)]
+    algorithm: ProducerAlgorithm,
+    #[serde(default)]
+    production: Option<Volume>,
+    #[serde(default)]
+    shares: BTreeMap<CityId, InnerValue>,
+    #[serde(default)]
+    max_output: Option<Volume>,
+    #[serde(default = "Revenue::zero")]
+    fixed_cost: Revenue,
+    #[serde(default)]
+    exited: bool,
+    /// Supplies registered directly in other cities, replacing the single
+    /// home city supply.
+    #[serde(default)]
+    splits: Vec<(CityId, Supply)>,
+    /// Learning by doing: supply at every price grows by this fraction per
+    /// unit produced so far, as if marginal costs fell with experience.
+    #[serde(default)]
+    learning_rate: Option<InnerValue>,
+    /// Volume produced in all turns so far, counted with a learning rate.
+    #[serde(default = "Volume::zero")]
+    experience: Volume,
 }
 
 impl Producer {
-    #[allow(dead_code)]
     pub fn new(city: CityId, production_costs: Supply) -> Producer {
         Producer {
+            id: None,
             city,
             production_costs,
+            commodity: Commodity::default(),
+            algorithm: ProducerAlgorithm::Static,
+            production: None,
+            shares: BTreeMap::new(),
+            max_output: None,
+            fixed_cost: Revenue::zero(),
+            exited: false,
+            splits: vec![],
+            learning_rate: None,
+            experience: Volume::zero(),
         }
     }
 
+    pub fn with_id(mut self, id: AgentId) -> Producer {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn with_commodity(mut self, commodity: Commodity) -> Producer {
+        self.commodity = commodity;
+        self
+    }
+
+    pub fn with_algorithm(mut self, algorithm: ProducerAlgorithm) -> Producer {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn with_production(mut self, production: Volume) -> Producer {
+        self.production = Some(production);
+        self
+    }
+
+    pub fn with_max_output(mut self, max_output: Volume) -> Producer {
+        self.max_output = Some(max_output);
+        self
+    }
+
+    pub fn with_fixed_cost(mut self, fixed_cost: Revenue) -> Producer {
+        self.fixed_cost = fixed_cost;
+        self
+    }
+
+    pub fn with_splits(mut self, splits: Vec<(CityId, Supply)>) -> Producer {
+        self.splits = splits;
+        self
+    }
+
+    pub fn with_learning_rate(mut self, rate: InnerValue) -> Producer {
+        self.learning_rate = Some(rate);
+        self
+    }
+
+    /// Splits the supply equally between given cities.
+    pub fn with_targets(mut self, targets: Vec<CityId>) -> Producer {
+        let share = 1. / targets.len() as InnerValue;
+        self.shares = targets.into_iter().map(|x| (x, share)).collect();
+        self
+    }
+
+    pub fn id(&self) -> Option<AgentId> {
+        self.id
+    }
+
     pub fn city(&self) -> CityId {
         self.city
     }
 
+    pub fn commodity(&self) -> Commodity {
+        self.commodity
+    }
+
+    /// Every city the producer refers to.
+    pub fn cities(&self) -> Vec<CityId> {
+        let mut cities = vec![self.city];
+        cities.extend(self.shares.keys());
+        cities.extend(self.splits.iter().map(|(city, _)| *city));
+        cities
+    }
+
     pub fn supply(&self) -> &Supply {
         &self.production_costs
     }
 
-    #[allow(dead_code)]
-    pub fn update(&mut self, _market: &mut Market) {
-        // Place left for possible extension.
+    pub fn production(&self) -> Option<Volume> {
+        self.production
+    }
+
+    pub fn shares(&self) -> &BTreeMap<CityId, InnerValue> {
+        &self.shares
+    }
+
+    pub fn max_output(&self) -> Option<Volume> {
+        self.max_output
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn experience(&self) -> Volume {
+        self.experience
+    }
+
+    /// Production costs lowered by experience and limited by the capacity
+    /// of the producer.
+    fn costs(&self) -> Supply {
+        let mut costs = self.production_costs.clone();
+        if let Some(rate) = self.learning_rate {
+            costs.scale_value(1. + rate * self.experience.float());
+        }
+        match self.max_output {
+            Some(max_output) => costs.capped(max_output),
+            None => costs,
+        }
+    }
+
+    /// Volume sold in the last turn over all cities the producer sells in.
+    fn sold(&self, market: &Market) -> Volume {
+        self.offers(market.geography())
+            .into_iter()
+            .filter_map(|(city, supply)| {
+                let price = market
+                    .commodity_data(self.commodity, city)?
+                    .producer_price()?;
+                Some(supply.value(price))
+            })
+            .fold(Volume::zero(), |acc, x| acc + x)
+    }
+
+    fn transport_cost(&self, geography: &Geography, target: CityId) -> Option<Price> {
+        if target == self.city {
+            return Some(Price::zero());
+        }
+        geography.shortest_cost(self.city, target)
+    }
+
+    /// Supplies registered in the market. Producer who committed to
+    /// a production volume sells it regardless of the price. Splits take
+    /// precedence over the algorithm.
+    pub fn offers(&self, geography: &Geography) -> Vec<(CityId, Supply)> {
+        if !self.splits.is_empty() {
+            return self.splits.clone();
+        }
+        match (self.algorithm, self.production) {
+            (ProducerAlgorithm::Regular(_), Some(production)) => {
+                let production = match self.max_output {
+                    Some(max_output) => production.min(max_output),
+                    None => production,
+                };
+                vec![(
+                    self.city,
+                    Supply::new(
+                        vec![(self.production_costs.function().min_arg(), production)].into_iter(),
+                    ),
+                )]
+            }
+            (ProducerAlgorithm::Advanced(_), _) if !self.shares.is_empty() => self
+                .shares
+                .iter()
+                .filter_map(|(target, share)| {
+                    self.transport_cost(geography, *target).map(|cost| {
+                        let mut supply = self.costs();
+                        supply.scale_value(*share).shift_right(cost);
+                        (*target, supply)
+                    })
+                })
+                .collect(),
+            _ => vec![(self.city, self.costs())],
+        }
+    }
+
+    /// Profit over variable costs of producing at given price in the home
+    /// city, before the fixed cost is paid.
+    pub fn operating_profit(&self, price: Price) -> Revenue {
+        self.costs().producer_surplus(price)
+    }
+
+    /// Earnings of the last turn, `(price - average cost) * volume` summed
+    /// over the cities the producer sells in, net of transport and the fixed
+    /// cost. Producer who left the market earns nothing.
+    pub fn profit(&self, market: &Market) -> Option<Revenue> {
+        if self.exited {
+            return Some(Revenue::zero());
+        }
+        let mut revenue = Revenue::zero();
+        let mut volume = Volume::zero();
+        for (city, supply) in self.offers(market.geography()) {
+            let price = market
+                .commodity_data(self.commodity, city)?
+                .producer_price()?;
+            let cost = if self.splits.is_empty() {
+                self.transport_cost(market.geography(), city)?
+            } else {
+                Price::zero()
+            };
+            let sold = supply.value(price);
+            revenue += (price - cost) * sold;
+            volume += sold;
+        }
+        let variable_cost = match self.costs().average_cost_at(volume) {
+            Some(average_cost) => average_cost * volume,
+            None => Revenue::zero(),
+        };
+        Some(revenue - variable_cost - self.fixed_cost)
+    }
+
+    /// Leaves the market when the operating profit does not cover the fixed
+    /// cost and comes back once it does. Returns whether the producer is in
+    /// the market.
+    fn update_presence(&mut self, market: &mut Market) -> bool {
+        let price = market
+            .commodity_data(self.commodity, self.city)
+            .and_then(|x| x.producer_price());
+        if let Some(price) = price {
+            let profitable = self.operating_profit(price) >= self.fixed_cost;
+            if self.exited && profitable {
+                self.exited = false;
+                market.add_producer(self);
+            } else if !self.exited && !profitable {
+                market.remove_producer(self);
+                self.exited = true;
+            }
+        }
+        !self.exited
+    }
+}
+
+impl MarketAgent for Producer {
+    fn register(&self, market: &mut Market) {
+        if !self.exited {
+            market.add_producer(self);
+        }
+    }
+
+    fn unregister(&self, market: &mut Market) {
+        if !self.exited {
+            market.remove_producer(self);
+        }
+    }
+
+    fn producer_id(&self) -> Option<AgentId> {
+        self.id
+    }
+
+    fn as_producer(&self) -> Option<&Producer> {
+        Some(self)
+    }
+
+    fn update(&mut self, market: &mut Market, _turn: usize) {
+        if !self.update_presence(market) {
+            return;
+        }
+        if self.learning_rate.is_some() {
+            let sold = self.sold(market);
+            market.remove_producer(self);
+            self.experience += sold;
+            market.add_producer(self);
+        }
+        match self.algorithm {
+            ProducerAlgorithm::Regular(rate) => {
+                let price = market
+                    .commodity_data(self.commodity, self.city)
+                    .and_then(|x| x.producer_price());
+                if let Some(price) = price {
+                    let target = self.costs().value(price);
+                    let production = match self.production {
+                        Some(production) => production + (target - production) * rate,
+                        None => target,
+                    };
+
+                    market.remove_producer(self);
+                    self.production = Some(production);
+                    market.add_producer(self);
+                }
+            }
+            ProducerAlgorithm::Advanced(rate) => {
+                let net_prices: BTreeMap<CityId, Price> = self
+                    .shares
+                    .keys()
+                    .filter_map(|target| {
+                        let price = market
+                            .commodity_data(self.commodity, *target)?
+                            .producer_price()?;
+                        let cost = self.transport_cost(market.geography(), *target)?;
+                        Some((*target, price - cost))
+                    })
+                    .collect();
+                let best_price = match net_prices.values().max() {
+                    Some(price) => *price,
+                    None => return,
+                };
+
+                let eps = Price::new(1e-6);
+                let best: Vec<CityId> = net_prices
+                    .iter()
+                    .filter(|(_, price)| best_price - **price < eps)
+                    .map(|(target, _)| *target)
+                    .collect();
+                if best.len() == net_prices.len() {
+                    return;
+                }
+
+                market.remove_producer(self);
+                let best_share = rate / best.len() as InnerValue;
+                for (target, share) in self.shares.iter_mut() {
+                    *share *= 1. - rate;
+                    if best.contains(target) {
+                        *share += best_share;
+                    }
+                }
+                market.add_producer(self);
+            }
+            ProducerAlgorithm::Static => {}
+        }
     }
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+pub enum ConsumerAlgorithm {
+    /// Consumer registers the same demand every turn.
+    #[default]
+    Static,
+    /// Consumer gets used to consuming. Demand is scaled by
+    /// `1 + responsiveness * last consumption / maximal consumption`, so it
+    /// rises after turns with low prices and falls after turns with high ones.
+    Habitual(InnerValue),
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Consumer {
+    #[serde(default)]
+    id: Option<AgentId>,
     city: CityId,
     usefulness: Demand,
+    #[serde(default)]
+    commodity: Commodity,
+    /// Multipliers of the demand, cycled by turn index.
+    #[serde(default)]
+    seasonality: Vec<InnerValue>,
+    #[serde(default)]
+    turn: usize,
+    #[serde(default)]
+    algorithm: ConsumerAlgorithm,
+    #[serde(default)]
+    consumption: Option<Volume>,
 }
 
 impl Consumer {
-    #[allow(dead_code)]
     pub fn new(city: CityId, usefulness: Demand) -> Consumer {
-        Consumer { city, usefulness }
+        Consumer {
+            id: None,
+            city,
+            usefulness,
+            commodity: Commodity::default(),
+            seasonality: vec![],
+            turn: 0,
+            algorithm: ConsumerAlgorithm::Static,
+            consumption: None,
+        }
+    }
+
+    pub fn with_algorithm(mut self, algorithm: ConsumerAlgorithm) -> Consumer {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn with_id(mut self, id: AgentId) -> Consumer {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn with_commodity(mut self, commodity: Commodity) -> Consumer {
+        self.commodity = commodity;
+        self
+    }
+
+    pub fn with_seasonality(mut self, seasonality: Vec<InnerValue>) -> Consumer {
+        self.seasonality = seasonality;
+        self
+    }
+
+    pub fn id(&self) -> Option<AgentId> {
+        self.id
     }
 
     pub fn city(&self) -> CityId {
         self.city
     }
 
+    pub fn commodity(&self) -> Commodity {
+        self.commodity
+    }
+
     pub fn demand(&self) -> &Demand {
         &self.usefulness
     }
 
-    #[allow(dead_code)]
-    pub fn update(&mut self, _market: &mut Market) {
-        // Place left for possible extension.
+    /// Volume bought in the last turn, tracked only by habitual consumers.
+    pub fn consumption(&self) -> Option<Volume> {
+        self.consumption
+    }
+
+    /// Demand registered in the market in the current turn.
+    pub fn current_demand(&self) -> Demand {
+        let mut demand = self.usefulness.clone();
+        if !self.seasonality.is_empty() {
+            demand.scale_value(self.seasonality[self.turn % self.seasonality.len()]);
+        }
+        if let (ConsumerAlgorithm::Habitual(responsiveness), Some(consumption)) =
+            (self.algorithm, self.consumption)
+        {
+            let maximal = self.usefulness.value(self.usefulness.function().min_arg());
+            if maximal > Volume::zero() {
+                demand.scale_value(1. + responsiveness * consumption.float() / maximal.float());
+            }
+        }
+        demand
+    }
+}
+
+impl MarketAgent for Consumer {
+    fn register(&self, market: &mut Market) {
+        market.add_consumer(self);
+    }
+
+    fn unregister(&self, market: &mut Market) {
+        market.remove_consumer(self);
+    }
+
+    fn consumer_id(&self) -> Option<AgentId> {
+        self.id
+    }
+
+    fn as_consumer(&self) -> Option<&Consumer> {
+        Some(self)
+    }
+
+    fn update(&mut self, market: &mut Market, turn: usize) {
+        let consumption = match self.algorithm {
+            ConsumerAlgorithm::Habitual(_) => market
+                .commodity_data(self.commodity, self.city)
+                .and_then(|x| x.price())
+                .map(|price| self.current_demand().value(price)),
+            ConsumerAlgorithm::Static => None,
+        };
+        if self.seasonality.is_empty() && consumption.is_none() {
+            self.turn = turn;
+            return;
+        }
+        market.remove_consumer(self);
+        self.turn = turn;
+        if consumption.is_some() {
+            self.consumption = consumption;
+        }
+        market.add_consumer(self);
+    }
+}
+
+/// Trader who holds inventory between turns. Speculator buys when the price
+/// falls below its moving-average estimate and sells when it rises above it,
+/// with the traded volume growing linearly over the given spread.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Speculator {
+    city: CityId,
+    #[serde(default)]
+    commodity: Commodity,
+    trade: Volume,
+    capacity: Volume,
+    spread: Price,
+    smoothing: InnerValue,
+    #[serde(default)]
+    estimate: Option<Price>,
+    #[serde(default = "Volume::zero")]
+    inventory: Volume,
+}
+
+impl Speculator {
+    /// Speculator trading at most `trade` per turn and storing at most
+    /// `capacity`. `smoothing` is the weight of the last price in the
+    /// estimate.
+    pub fn new(
+        city: CityId,
+        trade: Volume,
+        capacity: Volume,
+        spread: Price,
+        smoothing: InnerValue,
+    ) -> Speculator {
+        Speculator {
+            city,
+            commodity: Commodity::default(),
+            trade,
+            capacity,
+            spread,
+            smoothing,
+            estimate: None,
+            inventory: Volume::zero(),
+        }
+    }
+
+    pub fn with_commodity(mut self, commodity: Commodity) -> Speculator {
+        self.commodity = commodity;
+        self
+    }
+
+    pub fn city(&self) -> CityId {
+        self.city
+    }
+
+    pub fn commodity(&self) -> Commodity {
+        self.commodity
+    }
+
+    pub fn estimate(&self) -> Option<Price> {
+        self.estimate
+    }
+
+    pub fn inventory(&self) -> Volume {
+        self.inventory
+    }
+
+    /// Demand of buying and supply of selling placed in the market.
+    fn orders(&self) -> Option<(Demand, Supply)> {
+        let estimate = self.estimate?;
+        let buy = self.trade.min(self.capacity - self.inventory);
+        let sell = self.trade.min(self.inventory);
+        Some((
+            Demand::new(
+                vec![(estimate - self.spread, buy), (estimate, Volume::zero())].into_iter(),
+            ),
+            Supply::new(
+                vec![(estimate, Volume::zero()), (estimate + self.spread, sell)].into_iter(),
+            ),
+        ))
+    }
+}
+
+impl MarketAgent for Speculator {
+    fn register(&self, market: &mut Market) {
+        if let Some((demand, supply)) = self.orders() {
+            market.add_commodity_demand(self.commodity, self.city, &demand);
+            market.add_commodity_supply(self.commodity, self.city, &supply);
+        }
+    }
+
+    fn unregister(&self, market: &mut Market) {
+        if let Some((demand, supply)) = self.orders() {
+            market.remove_commodity_demand(self.commodity, self.city, &demand);
+            market.remove_commodity_supply(self.commodity, self.city, &supply);
+        }
+    }
+
+    fn update(&mut self, market: &mut Market, _turn: usize) {
+        let price = match market
+            .commodity_data(self.commodity, self.city)
+            .and_then(|x| x.price())
+        {
+            Some(price) => price,
+            None => return,
+        };
+
+        self.unregister(market);
+        if let Some((demand, supply)) = self.orders() {
+            self.inventory += demand.value(price) - supply.value(price);
+        }
+        self.estimate = Some(match self.estimate {
+            Some(estimate) => estimate + (price - estimate) * self.smoothing,
+            None => price,
+        });
+        self.register(market);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::economy::commodity::Commodity;
+    use crate::economy::entity::Consumer;
+    use crate::economy::entity::ConsumerAlgorithm;
+    use crate::economy::entity::MarketAgent;
+    use crate::economy::entity::Producer;
+    use crate::economy::entity::ProducerAlgorithm;
+    use crate::economy::entity::Speculator;
+    use crate::economy::function::FunctionAbstract;
+    use crate::economy::geography::City;
+    use crate::economy::geography::Connection;
+    use crate::economy::geography::Geography;
+    use crate::economy::market::Market;
+    use crate::economy::types::InnerValue;
+    use crate::economy::types::Price;
+    use crate::economy::types::Revenue;
+    use crate::economy::types::Volume;
+    use crate::util::testing::make_demand;
+    use crate::util::testing::make_supply;
+    use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_value;
+
+    fn single_node_market() -> Market {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+        market
+    }
+
+    #[test]
+    fn learning_producer_lowers_price() {
+        let mut market = single_node_market();
+        let mut producer =
+            Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).with_learning_rate(0.1);
+        market.add_producer(&producer);
+
+        let mut prices = vec![];
+        let mut volumes = vec![];
+        for turn in 0..5 {
+            market.simulate(1);
+            prices.push(market.prices()[&0].unwrap());
+            volumes.push(market.demand_volumes()[&0].unwrap());
+            producer.update(&mut market, turn + 1);
+        }
+        test_eq_arg(prices[0], Price::new(2.));
+        test_eq_value(
+            producer.experience(),
+            volumes.iter().fold(Volume::zero(), |acc, x| acc + *x),
+        );
+        for i in 1..prices.len() {
+            assert!(prices[i] < prices[i - 1]);
+            assert!(volumes[i] > volumes[i - 1]);
+        }
+    }
+
+    #[test]
+    fn regular_producer_settles() {
+        let mut market = single_node_market();
+        let mut producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+            .with_algorithm(ProducerAlgorithm::Regular(0.5))
+            .with_production(Volume::new(3.));
+        market.add_producer(&producer);
+
+        market.simulate(1);
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(1.));
+        producer.update(&mut market, 1);
+        test_eq_value(producer.production().unwrap(), Volume::new(2.));
+
+        for _ in 0..4 {
+            market.simulate(1);
+            producer.update(&mut market, 1);
+        }
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+        test_eq_value(producer.production().unwrap(), Volume::new(2.));
+        test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(2.));
+    }
+
+    #[test]
+    fn static_producer_unchanged() {
+        let mut market = single_node_market();
+        let mut producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+        market.add_producer(&producer);
+
+        market.simulate(1);
+        producer.update(&mut market, 1);
+        market.simulate(1);
+        assert_eq!(producer.production(), None);
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+    }
+
+    #[test]
+    fn advanced_producer_moves_to_higher_net_price() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "home".to_string()));
+        geography.add_city(City::new(1, "remote".to_string()));
+        geography.add_connection(Connection::new(0, 1, Price::new(1.)));
+
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 2.), (4., 0.)])));
+        market.add_consumer(&Consumer::new(1, make_demand(vec![(0., 8.), (8., 0.)])));
+        let mut producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+            .with_algorithm(ProducerAlgorithm::Advanced(0.5))
+            .with_targets(vec![0, 1]);
+        market.add_producer(&producer);
+
+        market.simulate(1);
+        let prices = market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(2.));
+        test_eq_arg(prices[&1].unwrap(), Price::new(6.));
+
+        producer.update(&mut market, 1);
+        let shares = producer.shares();
+        assert!((shares[&0] - 0.25).abs() < 1e-9);
+        assert!((shares[&1] - 0.75).abs() < 1e-9);
+
+        market.simulate(1);
+        let prices = market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(3.6));
+        test_eq_arg(prices[&1].unwrap(), Price::new(4.6));
+        test_eq_value(market.supply_volumes()[&1].unwrap(), Volume::new(2.7));
+
+        producer.update(&mut market, 1);
+        assert!((producer.shares()[&1] - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn advanced_producer_ships_through_other_city() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "home".to_string()));
+        geography.add_city(City::new(1, "transit".to_string()));
+        geography.add_city(City::new(2, "remote".to_string()));
+        geography.add_connection(Connection::new(0, 1, Price::new(1.)));
+        geography.add_connection(Connection::new(1, 2, Price::new(2.)));
+
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&Consumer::new(2, make_demand(vec![(0., 8.), (8., 0.)])));
+        let producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+            .with_algorithm(ProducerAlgorithm::Advanced(0.5))
+            .with_targets(vec![2]);
+        market.add_producer(&producer);
+
+        market.simulate(1);
+        test_eq_arg(market.prices()[&2].unwrap(), Price::new(5.5));
+        test_eq_value(market.supply_volumes()[&2].unwrap(), Volume::new(2.5));
+    }
+
+    #[test]
+    fn capacity_raises_price() {
+        let mut market = single_node_market();
+        let producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+        market.add_producer(&producer);
+        market.simulate(1);
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+
+        let mut market = single_node_market();
+        let producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+            .with_max_output(Volume::new(1.));
+        market.add_producer(&producer);
+        market.simulate(1);
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(3.));
+        test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(1.));
+    }
+
+    #[test]
+    fn unprofitable_producer_exits() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 8.), (8., 0.)])));
+        let mut cheap = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+        let mut expensive = Producer::new(0, make_supply(vec![(2., 0.), (6., 4.)]))
+            .with_fixed_cost(Revenue::new(1.));
+        market.add_producer(&cheap);
+        market.add_producer(&expensive);
+
+        market.simulate(1);
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(3.333333333));
+        cheap.update(&mut market, 1);
+        expensive.update(&mut market, 1);
+        assert!(!cheap.exited());
+        assert!(expensive.exited());
+
+        market.simulate(1);
+        test_eq_arg(market.prices()[&0].unwrap(), Price::new(4.));
+    }
+
+    #[test]
+    fn seasonal_demand_oscillates() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        let mut market = Market::new(geography, BTreeMap::new());
+        let mut consumer =
+            Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])).with_seasonality(vec![1., 2.]);
+        market.add_consumer(&consumer);
+        market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+
+        for turn in 0..4 {
+            market.simulate(1);
+            let expected = if turn % 2 == 0 { 2. } else { 2.666666666 };
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(expected));
+            consumer.update(&mut market, turn + 1);
+        }
+    }
+
+    #[test]
+    fn habitual_consumer_follows_price() {
+        fn run(supply: Vec<(InnerValue, InnerValue)>) -> Vec<InnerValue> {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            let mut consumer = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]))
+                .with_algorithm(ConsumerAlgorithm::Habitual(0.5));
+            market.add_consumer(&consumer);
+            market.add_producer(&Producer::new(0, make_supply(supply)));
+
+            let mut prices = vec![];
+            for turn in 0..10 {
+                market.simulate(1);
+                prices.push(market.prices()[&0].unwrap().float());
+                consumer.update(&mut market, turn + 1);
+            }
+            prices
+        }
+
+        let prices = run(vec![(0., 0.), (4., 4.)]);
+        assert!((prices[0] - 2.).abs() < 1e-6);
+        assert!((prices[1] - 2.222222222).abs() < 1e-6);
+        assert!(prices.windows(2).all(|x| x[0] <= x[1] + 1e-9));
+        assert!((prices[9] - 2.246211251).abs() < 1e-4);
+
+        let cheap = run(vec![(0., 0.), (1., 4.)]);
+        let expensive = run(vec![(2., 0.), (3., 4.)]);
+        assert!(cheap[9] / cheap[0] > expensive[9] / expensive[0]);
+    }
+
+    #[test]
+    fn speculator_trades_own_commodity() {
+        let bread = Commodity(1);
+        let mut market = Market::new(single_node_market().geography().clone(), BTreeMap::new());
+        market.add_consumer(
+            &Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])).with_commodity(bread),
+        );
+        market.add_producer(
+            &Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).with_commodity(bread),
+        );
+        market.simulate(1);
+
+        let mut speculator =
+            Speculator::new(0, Volume::new(1.), Volume::new(10.), Price::new(0.5), 0.2)
+                .with_commodity(bread);
+        speculator.update(&mut market, 1);
+        test_eq_arg(speculator.estimate().unwrap(), Price::new(2.));
+        let price = Price::new(1.5);
+        let demand = |commodity| {
+            market
+                .commodity_data(commodity, 0)
+                .unwrap()
+                .demand()
+                .value(price)
+        };
+        test_eq_value(demand(bread), Volume::new(3.5));
+        test_eq_value(demand(Commodity::default()), Volume::zero());
+    }
+
+    #[test]
+    fn speculator_dampens_swing() {
+        fn swing(speculator: Option<Speculator>) -> InnerValue {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            let consumer = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]))
+                .with_seasonality(vec![0.5, 1.5]);
+            consumer.register(&mut market);
+            Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).register(&mut market);
+            let mut agents: Vec<Box<dyn MarketAgent>> = vec![Box::new(consumer)];
+            if let Some(speculator) = speculator {
+                speculator.register(&mut market);
+                agents.push(Box::new(speculator));
+            }
+
+            let mut prices = vec![];
+            for turn in 0..20 {
+                market.simulate(1);
+                prices.push(market.prices()[&0].unwrap().float());
+                for agent in agents.iter_mut() {
+                    agent.update(&mut market, turn + 1);
+                }
+            }
+            (prices[19] - prices[18]).abs()
+        }
+
+        let speculator =
+            Speculator::new(0, Volume::new(1.), Volume::new(10.), Price::new(0.5), 0.2);
+        let base = swing(None);
+        assert!((base - 1.066666666).abs() < 1e-4);
+        assert!(swing(Some(speculator)) < base / 2.);
+    }
+
+    #[test]
+    fn producer_with_splits() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "first".to_string()));
+        geography.add_city(City::new(1, "second".to_string()));
+
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+        market.add_consumer(&Consumer::new(1, make_demand(vec![(0., 8.), (8., 0.)])));
+        let producer = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).with_splits(vec![
+            (0, make_supply(vec![(0., 0.), (4., 4.)])),
+            (1, make_supply(vec![(0., 0.), (8., 8.)])),
+        ]);
+        market.add_producer(&producer);
+
+        market.simulate(1);
+        let prices = market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(2.));
+        test_eq_arg(prices[&1].unwrap(), Price::new(4.));
+        test_eq_value(market.supply_volumes()[&1].unwrap(), Volume::new(4.));
+
+        market.remove_producer(&producer);
+        market.simulate(1);
+        for volume in market.supply_volumes().values() {
+            test_eq_value(volume.unwrap(), Volume::zero());
+        }
     }
 }
@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::economy::simulation::BuilderError;
+
+/// Error of loading, running or saving a simulation.
+#[derive(Debug)]
+pub enum SimError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// Scenario or results couldn't be converted from or to JSON.
+    Parse(serde_json::Error),
+    /// Scenario refers to cities that don't exist, or to target cities
+    /// a producer has no route to.
+    Validation(Vec<BuilderError>),
+    /// Geography doesn't allow the operation, e.g. has no cities to plot.
+    Geography(String),
+    /// Prices didn't settle in given number of turns.
+    NonConvergence(usize),
+    /// Drawing or encoding a plot failed.
+    Plot(String),
+    /// A CSV table of the scenario is missing or malformed.
+    Csv(String),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::Io(error) => write!(f, "{}", error),
+            SimError::Parse(error) => write!(f, "{}", error),
+            SimError::Validation(errors) => {
+                let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+                write!(f, "invalid scenario: {}", errors.join(", "))
+            }
+            SimError::Geography(reason) => write!(f, "{}", reason),
+            SimError::NonConvergence(turns) => {
+                write!(f, "prices did not settle in {} turns", turns)
+            }
+            SimError::Plot(reason) => write!(f, "could not plot: {}", reason),
+            SimError::Csv(reason) => write!(f, "invalid table {}", reason),
+        }
+    }
+}
+
+impl Error for SimError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SimError::Io(error) => Some(error),
+            SimError::Parse(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SimError {
+    fn from(error: io::Error) -> Self {
+        SimError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SimError {
+    fn from(error: serde_json::Error) -> Self {
+        SimError::Parse(error)
+    }
+}
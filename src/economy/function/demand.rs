@@ -1,14 +1,22 @@
 use serde::Deserialize;
 use serde::Serialize;
+use std::ops::{Add, Sub};
 
+use crate::economy::function::check_points;
 use crate::economy::function::supply::Supply;
 use crate::economy::function::ArgT;
+use crate::economy::function::CurveData;
 use crate::economy::function::FunctionAbstract;
+use crate::economy::function::FunctionError;
 use crate::economy::function::FunctionNullable;
 use crate::economy::function::ValueT;
+use crate::economy::function::DEFAULT_TOLERANCE;
 use crate::economy::market::MarketState;
+use crate::economy::types::InnerValue;
+use crate::economy::types::Revenue;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "CurveData")]
 pub struct Demand {
     function: FunctionNullable,
 }
@@ -20,7 +28,6 @@ impl Demand {
         }
     }
 
-    #[allow(dead_code)]
     pub fn new<I>(values: I) -> Demand
     where
         I: Iterator<Item = (ArgT, ValueT)>,
@@ -30,31 +37,108 @@ impl Demand {
         }
     }
 
+    /// Demand given by `(price, quantity)` rows of plain numbers, e.g.
+    /// read from a spreadsheet.
+    pub fn from_rows(rows: &[(InnerValue, InnerValue)]) -> Demand {
+        Demand::new(
+            rows.iter()
+                .map(|(price, quantity)| (ArgT::new(*price), ValueT::new(*quantity))),
+        )
+    }
+
+    /// Same as `new`, but rejects points with repeated prices or values
+    /// that aren't non-increasing.
+    pub fn try_new<I>(values: I) -> Result<Demand, FunctionError>
+    where
+        I: Iterator<Item = (ArgT, ValueT)>,
+    {
+        let mut points: Vec<(ArgT, ValueT)> = values.collect();
+        check_points(&mut points, false)?;
+        Ok(Demand::new(points.into_iter()))
+    }
+
     pub fn function(&self) -> &FunctionNullable {
         &self.function
     }
 
     pub fn intersect(&self, supply: &Supply) -> MarketState {
-        match self.function.intersect(supply.function()) {
-            Some((price, amount)) => MarketState::Equilibrium(price, amount, amount),
-            None => {
-                if self.function().right_value() > supply.function().right_value() {
-                    MarketState::UnderSupply
-                } else if self.function().left_value() < supply.function().left_value() {
-                    MarketState::OverSupply
-                } else {
-                    MarketState::Undefined
-                }
-            }
+        self.intersect_with_tolerance(supply, ArgT::new(DEFAULT_TOLERANCE))
+    }
+
+    /// Equilibrium with the price accurate to given tolerance, see
+    /// `FunctionNullable::equilibrium_state`.
+    pub fn intersect_with_tolerance(&self, supply: &Supply, tol: ArgT) -> MarketState {
+        self.function
+            .equilibrium_state_with_tolerance(supply.function(), tol)
+    }
+
+    /// Same as `intersect_with_tolerance`, with the crossings of the curves
+    /// already found by `intersections`.
+    pub fn intersect_with_crossings(
+        &self,
+        supply: &Supply,
+        tol: ArgT,
+        crossings: &[(ArgT, ValueT)],
+    ) -> MarketState {
+        self.function
+            .equilibrium_state_with_crossings(supply.function(), tol, crossings)
+    }
+
+    /// Prices and volumes at which the demand crosses or touches the supply.
+    /// Monotone curves cross at most once, apart from being equal over an
+    /// interval.
+    pub fn intersections(&self, supply: &Supply) -> Vec<(ArgT, ValueT)> {
+        self.function.intersections(supply.function())
+    }
+
+    /// Area under the demand above given price, up to the highest price
+    /// anyone is still willing to pay.
+    pub fn consumer_surplus(&self, price: ArgT) -> Revenue {
+        let max_arg = self.function.max_arg();
+        if price < max_arg {
+            self.function.integral(price, max_arg)
+        } else {
+            Revenue::zero()
         }
     }
 
-    #[allow(dead_code)]
     pub fn intervals(&self) -> Vec<(ArgT, ValueT)> {
         self.function.intervals()
     }
 }
 
+/// Arc cross-price elasticity of the demand for good A with respect to the
+/// price of good B, computed from the quantities of A observed before and
+/// after the price of B changed. Changes are taken relative to the midpoints,
+/// so the result doesn't depend on the direction of the change.
+///
+/// Positive elasticity means A and B are substitutes (A is bought more when
+/// B gets more expensive), negative means they are complements. The result
+/// is not finite when the price of B doesn't change.
+pub fn cross_price_elasticity(
+    price_b_before: ArgT,
+    price_b_after: ArgT,
+    qty_a_before: ValueT,
+    qty_a_after: ValueT,
+) -> InnerValue {
+    let qty_change =
+        (qty_a_after - qty_a_before).float() / ((qty_a_after + qty_a_before).float() / 2.);
+    let price_change =
+        (price_b_after - price_b_before).float() / ((price_b_after + price_b_before).float() / 2.);
+    qty_change / price_change
+}
+
+impl TryFrom<CurveData> for Demand {
+    type Error = String;
+
+    fn try_from(data: CurveData) -> Result<Self, Self::Error> {
+        if data.function.is_empty() {
+            return Ok(Demand::zero());
+        }
+        Demand::try_new(data.function.into_iter()).map_err(|x| format!("invalid demand: {}", x))
+    }
+}
+
 impl FunctionAbstract for Demand {
     fn value(&self, arg: ArgT) -> ValueT {
         self.function.value(arg)
@@ -90,8 +174,175 @@ impl FunctionAbstract for Demand {
         self
     }
 
+    fn scale_value(&mut self, factor: InnerValue) -> &mut Self {
+        self.function.scale_value(factor);
+        self
+    }
+
+    fn round_values(&mut self, unit: InnerValue) -> &mut Self {
+        self.function.round_values(unit);
+        self
+    }
+
     fn negate(&mut self) -> &mut Self {
         self.function.negate();
         self
     }
 }
+
+impl Add for &Demand {
+    type Output = Demand;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let mut res = self.clone();
+        res.add_function(rhs);
+        res
+    }
+}
+
+impl Sub for &Demand {
+    type Output = Demand;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut res = self.clone();
+        res.substract_function(rhs);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::economy::function::demand::cross_price_elasticity;
+    use crate::economy::function::Demand;
+    use crate::economy::function::FunctionAbstract;
+    use crate::economy::function::FunctionError;
+    use crate::economy::function::Supply;
+    use crate::economy::market::MarketState;
+    use crate::economy::types::Price;
+    use crate::economy::types::Volume;
+    use crate::util::testing::make_demand;
+    use crate::util::testing::make_supply;
+    use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_value;
+
+    #[test]
+    fn from_rows() {
+        let demand = Demand::from_rows(&[(0., 4.), (2., 1.), (4., 0.)]);
+        test_eq_value(demand.value(Price::new(1.)), Volume::new(2.5));
+        test_eq_value(demand.value(Price::new(3.)), Volume::new(0.5));
+        test_eq_value(demand.value(Price::new(5.)), Volume::zero());
+    }
+
+    #[test]
+    fn operators() {
+        let first = make_demand(vec![(0., 4.), (4., 0.)]);
+        let second = make_demand(vec![(0., 2.), (2., 0.)]);
+        let total = &first + &second;
+        test_eq_value(total.value(Price::new(1.)), Volume::new(4.));
+        test_eq_value(total.value(Price::new(3.)), Volume::new(1.));
+        let rest = &total - &second;
+        test_eq_value(rest.value(Price::new(1.)), first.value(Price::new(1.)));
+    }
+
+    #[test]
+    fn intersect_stable_of_two() {
+        /* supply bending backwards, crossing the demand at 2.4 and at 6.67 */
+        let demand = make_demand(vec![(0., 6.), (8., 2.)]);
+        let supply = Supply::new(
+            vec![(0., 0.), (4., 8.), (8., 0.)]
+                .into_iter()
+                .map(|(x, y)| (Price::new(x), Volume::new(y))),
+        );
+        let crossings = demand.intersections(&supply);
+        assert_eq!(crossings.len(), 2);
+        test_eq_arg(crossings[0].0, Price::new(2.4));
+        test_eq_value(crossings[0].1, Volume::new(4.8));
+        test_eq_arg(crossings[1].0, Price::new(20. / 3.));
+        test_eq_value(crossings[1].1, Volume::new(8. / 3.));
+        match demand.intersect(&supply) {
+            MarketState::Equilibrium(price, demand_volume, _, _) => {
+                assert_eq!(price, crossings[0].0);
+                test_eq_value(demand_volume, Volume::new(4.8));
+            }
+            state => panic!("unexpected {:?}", state),
+        }
+    }
+
+    #[test]
+    fn try_new_valid() {
+        let points = vec![(2., 1.), (0., 4.), (1., 2.), (3., 1.)];
+        let demand = Demand::try_new(
+            points
+                .into_iter()
+                .map(|(x, y)| (Price::new(x), Volume::new(y))),
+        )
+        .unwrap();
+        test_eq_value(demand.value(Price::new(0.5)), Volume::new(3.));
+    }
+
+    #[test]
+    fn try_new_invalid() {
+        let make = |points: Vec<(f64, f64)>| {
+            Demand::try_new(
+                points
+                    .into_iter()
+                    .map(|(x, y)| (Price::new(x), Volume::new(y))),
+            )
+            .map(|_| ())
+        };
+        assert_eq!(make(vec![]), Err(FunctionError::Empty));
+        assert_eq!(
+            make(vec![(0., 4.), (1., 2.), (2., 3.)]),
+            Err(FunctionError::NotMonotone(Price::new(2.)))
+        );
+        assert_eq!(
+            make(vec![(0., 4.), (1., 2.), (1., 1.)]),
+            Err(FunctionError::DuplicateArg(Price::new(1.)))
+        );
+    }
+
+    #[test]
+    fn cross_price_elasticity_substitutes() {
+        /* tea bought more often when coffee gets more expensive */
+        let elasticity = cross_price_elasticity(
+            Price::new(4.),
+            Price::new(6.),
+            Volume::new(9.),
+            Volume::new(11.),
+        );
+        assert!((elasticity - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cross_price_elasticity_complements() {
+        /* fewer cars bought when fuel gets more expensive */
+        let elasticity = cross_price_elasticity(
+            Price::new(2.),
+            Price::new(3.),
+            Volume::new(12.),
+            Volume::new(8.),
+        );
+        assert!((elasticity + 1.).abs() < 1e-9);
+        let reversed = cross_price_elasticity(
+            Price::new(3.),
+            Price::new(2.),
+            Volume::new(8.),
+            Volume::new(12.),
+        );
+        assert!((elasticity - reversed).abs() < 1e-9);
+    }
+
+    #[test]
+    fn intersect_flat_equal_curves() {
+        let demand = make_demand(vec![(0., 4.), (2., 4.)]);
+        let supply = make_supply(vec![(1., 4.), (3., 4.)]);
+        match demand.intersect(&supply) {
+            MarketState::Equilibrium(price, demand_volume, supply_volume, price_producer) => {
+                assert_eq!(price, price_producer);
+                test_eq_value(demand_volume, Volume::new(4.));
+                test_eq_value(supply_volume, Volume::new(4.));
+            }
+            state => panic!("unexpected {:?}", state),
+        }
+    }
+}
@@ -4,9 +4,17 @@ use std::cmp::max;
 use std::cmp::min;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Bound::Excluded;
 use std::ops::Bound::Included;
 use std::ops::Bound::Unbounded;
+use std::ops::{Add, Sub};
 
+use crate::economy::market::MarketState;
+use crate::economy::types::InnerValue;
+use crate::economy::types::Revenue;
+
+pub use demand::cross_price_elasticity;
 pub use demand::Demand;
 pub use supply::Supply;
 
@@ -17,6 +25,72 @@ mod supply;
 pub type ArgT = crate::economy::types::Price;
 pub type ValueT = crate::economy::types::Volume;
 
+/// Precision of intersections, unless given otherwise.
+pub const DEFAULT_TOLERANCE: InnerValue = 1e-6;
+
+/// Breakpoints closer than this, relative to their magnitude, are treated
+/// as one when interpolating.
+const MIN_RELATIVE_ARG_RANGE: InnerValue = 1e-12;
+
+/// Reason why points don't describe a demand or a supply.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FunctionError {
+    Empty,
+    /// More than one point is given for the price.
+    DuplicateArg(ArgT),
+    /// Value changes in the wrong direction at the price.
+    NotMonotone(ArgT),
+}
+
+impl fmt::Display for FunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionError::Empty => write!(f, "no points given"),
+            FunctionError::DuplicateArg(arg) => {
+                write!(f, "more than one point at price {}", arg.float())
+            }
+            FunctionError::NotMonotone(arg) => {
+                write!(
+                    f,
+                    "value changes in the wrong direction at price {}",
+                    arg.float()
+                )
+            }
+        }
+    }
+}
+
+/// Demand or supply as written in a scenario, before it is validated.
+#[derive(Deserialize)]
+struct CurveData {
+    function: Vec<(ArgT, ValueT)>,
+}
+
+/// Sorts the points by price and checks that their values never decrease,
+/// or never increase if `increasing` is false, up to `DEFAULT_TOLERANCE`.
+fn check_points(points: &mut [(ArgT, ValueT)], increasing: bool) -> Result<(), FunctionError> {
+    if points.is_empty() {
+        return Err(FunctionError::Empty);
+    }
+    points.sort_by_key(|(arg, _)| *arg);
+    let tol = ValueT::new(DEFAULT_TOLERANCE);
+    for pair in points.windows(2) {
+        let ((prev_arg, prev_value), (arg, value)) = (pair[0], pair[1]);
+        if prev_arg == arg {
+            return Err(FunctionError::DuplicateArg(arg));
+        }
+        let wrong = if increasing {
+            value + tol < prev_value
+        } else {
+            value > prev_value + tol
+        };
+        if wrong {
+            return Err(FunctionError::NotMonotone(arg));
+        }
+    }
+    Ok(())
+}
+
 pub trait FunctionAbstract {
     fn value(&self, arg: ArgT) -> ValueT;
 
@@ -29,6 +103,33 @@ pub trait FunctionAbstract {
     fn shift_right(&mut self, shift: ArgT) -> &mut Self;
     fn shift_left(&mut self, shift: ArgT) -> &mut Self;
 
+    /// Copy of the function shifted right, leaving the original unchanged.
+    fn shifted_right(&self, shift: ArgT) -> Self
+    where
+        Self: Clone,
+    {
+        let mut shifted = self.clone();
+        shifted.shift_right(shift);
+        shifted
+    }
+
+    /// Copy of the function shifted left, leaving the original unchanged.
+    fn shifted_left(&self, shift: ArgT) -> Self
+    where
+        Self: Clone,
+    {
+        let mut shifted = self.clone();
+        shifted.shift_left(shift);
+        shifted
+    }
+
+    fn scale_value(&mut self, factor: InnerValue) -> &mut Self;
+
+    /// Snaps every stored value to the nearest multiple of `unit`, values in
+    /// between are still interpolated. Does nothing unless `unit` is positive
+    /// and finite, as there are no multiples to snap to.
+    fn round_values(&mut self, unit: InnerValue) -> &mut Self;
+
     fn negate(&mut self) -> &mut Self;
 }
 
@@ -61,6 +162,18 @@ impl FunctionBase {
         }
     }
 
+    fn smooth(&self, window: usize) -> Self {
+        let points: Vec<(ArgT, ValueT)> = self.intervals();
+        let smoothed = (0..points.len()).map(|i| {
+            let neighbors = &points[i.saturating_sub(window)..min(i + window + 1, points.len())];
+            let total = neighbors
+                .iter()
+                .fold(ValueT::zero(), |acc, (_, value)| acc + *value);
+            (points[i].0, total / neighbors.len() as InnerValue)
+        });
+        FunctionBase::new(smoothed)
+    }
+
     fn lower_bound(&self, arg: ArgT) -> Option<(ArgT, ValueT)> {
         self.intervals
             .range((Unbounded, Included(arg)))
@@ -81,7 +194,50 @@ impl FunctionBase {
         args_self.chain(args_other).copied().collect()
     }
 
-    pub fn intersect(&self, other: &Self) -> Option<(ArgT, ValueT)> {
+    fn same_data_points(&self, other: &Self) -> bool {
+        self.intervals.keys().eq(other.intervals.keys())
+    }
+
+    /// Combines values of both functions pointwise. Both functions must have
+    /// the same data points, so the map is updated without being rebuilt.
+    fn combine_in_place<F>(&mut self, function: &Self, op: F) -> &mut Self
+    where
+        F: Fn(ValueT, ValueT) -> ValueT,
+    {
+        for (value, other) in self.intervals.values_mut().zip(function.intervals.values()) {
+            *value = op(*value, *other);
+        }
+        self.left_value = op(self.left_value, function.left_value);
+        self.right_value = op(self.right_value, function.right_value);
+        self
+    }
+
+    /// Combines values of both functions pointwise at the union of their
+    /// data points.
+    fn combine_rebuild<F>(&mut self, function: &Self, op: F) -> &mut Self
+    where
+        F: Fn(ValueT, ValueT) -> ValueT,
+    {
+        let args_combined = Self::combine_data_points(self, function);
+        let intervals: BTreeMap<ArgT, ValueT> = args_combined
+            .into_iter()
+            .map(|arg| (arg, op(self.value(arg), function.value(arg))))
+            .collect();
+
+        self.left_arg = min(self.left_arg, function.left_arg);
+        self.left_value = op(self.left_value, function.left_value);
+
+        self.right_arg = max(self.right_arg, function.right_arg);
+        self.right_value = op(self.right_value, function.right_value);
+
+        self.intervals = intervals;
+
+        self
+    }
+
+    /// Intersection found by bisection, with the argument accurate to given
+    /// tolerance or to the precision of floats, whichever is coarser.
+    pub fn intersect_with_tolerance(&self, other: &Self, tol: ArgT) -> Option<(ArgT, ValueT)> {
         // Functions might not intersect. Outside algorithms scope.
         if self.left_value > other.left_value && self.right_value > other.right_value {
             return None;
@@ -99,9 +255,11 @@ impl FunctionBase {
         let mut min = min(f_smaller.left_arg, f_greater.left_arg);
         let mut max = max(f_smaller.right_arg, f_greater.right_arg);
 
-        let eps = ArgT::new(1e-6);
-        while max - min > eps {
+        while max - min > tol {
             let mid = (min + max) / 2.;
+            if mid == min || mid == max {
+                break;
+            }
             let smaller_value = f_smaller.value(mid);
             let greater_value = f_greater.value(mid);
             if smaller_value < greater_value {
@@ -113,8 +271,35 @@ impl FunctionBase {
         Some((min, f_smaller.value(min)))
     }
 
+    /// Every point where the functions cross or touch, ordered by argument.
+    /// Both functions are linear between their data points, so crossings
+    /// are found exactly. If they are equal over an interval, only its
+    /// start is listed.
+    pub fn intersections(&self, other: &Self) -> Vec<(ArgT, ValueT)> {
+        let mut crossings = vec![];
+        let mut previous: Option<(ArgT, InnerValue)> = None;
+        for arg in self.combine_data_points(other) {
+            let diff = (self.value(arg) - other.value(arg)).float();
+            match previous {
+                Some((prev_arg, prev_diff)) if prev_diff * diff < 0. => {
+                    let range = (arg - prev_arg).float();
+                    let crossing = prev_arg + ArgT::new(range * prev_diff / (prev_diff - diff));
+                    crossings.push((crossing, self.value(crossing)));
+                }
+                Some((_, 0.)) => {}
+                _ => {
+                    if diff == 0. {
+                        crossings.push((arg, self.value(arg)));
+                    }
+                }
+            }
+            previous = Some((arg, diff));
+        }
+        crossings
+    }
+
     pub fn intervals(&self) -> Vec<(ArgT, ValueT)> {
-        let mut res = Vec::from_iter(self.intervals.clone().into_iter());
+        let mut res = Vec::from_iter(self.intervals.clone());
         res.sort_unstable_by_key(|x| x.0);
         res
     }
@@ -124,7 +309,6 @@ impl FunctionBase {
         **args.iter().min().unwrap()
     }
 
-    #[allow(dead_code)]
     pub fn min_value(&self) -> ValueT {
         let values = Vec::from_iter(self.intervals.values());
         **values.iter().min().unwrap()
@@ -139,17 +323,36 @@ impl FunctionBase {
         let values = Vec::from_iter(self.intervals.values());
         **values.iter().max().unwrap()
     }
+
+    pub fn integral(&self, from: ArgT, to: ArgT) -> Revenue {
+        if to < from {
+            return -self.integral(to, from);
+        }
+
+        let mut args: Vec<ArgT> = vec![from];
+        args.extend(
+            self.intervals
+                .range((Excluded(from), Excluded(to)))
+                .map(|x| *x.0),
+        );
+        args.push(to);
+
+        args.windows(2)
+            .map(|x| (x[1] - x[0]) * ((self.value(x[0]) + self.value(x[1])) / 2.))
+            .fold(Revenue::zero(), |acc, x| acc + x)
+    }
 }
 
 impl FunctionAbstract for FunctionBase {
     fn value(&self, arg: ArgT) -> ValueT {
         match (self.lower_bound(arg), self.upper_bound(arg)) {
             (Some((lower_arg, lower_val)), Some((upper_arg, upper_val))) => {
-                if lower_arg == upper_arg {
+                let arg_range = (upper_arg - lower_arg).float();
+                let magnitude = lower_arg.abs().max(upper_arg.abs()).float();
+                if arg_range <= MIN_RELATIVE_ARG_RANGE * magnitude {
                     lower_val
                 } else {
                     let arg_diff = (arg - lower_arg).float();
-                    let arg_range = (upper_arg - lower_arg).float();
                     let val_diff = (upper_val - lower_val).float();
                     let change = val_diff * (arg_diff / arg_range);
                     lower_val + ValueT::new(change)
@@ -157,7 +360,8 @@ impl FunctionAbstract for FunctionBase {
             }
             (Some((_, lower_val)), None) => lower_val,
             (None, Some((_, upper_val))) => upper_val,
-            (None, None) => unreachable!(),
+            /* no points at all, the function is constant */
+            (None, None) => self.left_value,
         }
     }
 
@@ -177,39 +381,19 @@ impl FunctionAbstract for FunctionBase {
     }
 
     fn add_function(&mut self, function: &Self) -> &mut Self {
-        let args_combined = Self::combine_data_points(self, function);
-        let intervals: BTreeMap<ArgT, ValueT> = args_combined
-            .into_iter()
-            .map(|arg| (arg, self.value(arg) + function.value(arg)))
-            .collect();
-
-        self.left_arg = min(self.left_arg, function.left_arg);
-        self.left_value += function.left_value;
-
-        self.right_arg = max(self.right_arg, function.right_arg);
-        self.right_value += function.right_value;
-
-        self.intervals = intervals;
-
-        self
+        if self.same_data_points(function) {
+            self.combine_in_place(function, |x, y| x + y)
+        } else {
+            self.combine_rebuild(function, |x, y| x + y)
+        }
     }
 
     fn substract_function(&mut self, function: &Self) -> &mut Self {
-        let args_combined = Self::combine_data_points(self, function);
-        let intervals: BTreeMap<ArgT, ValueT> = args_combined
-            .into_iter()
-            .map(|arg| (arg, self.value(arg) - function.value(arg)))
-            .collect();
-
-        self.left_arg = min(self.left_arg, function.left_arg);
-        self.left_value -= function.left_value;
-
-        self.right_arg = max(self.right_arg, function.right_arg);
-        self.right_value -= function.right_value;
-
-        self.intervals = intervals;
-
-        self
+        if self.same_data_points(function) {
+            self.combine_in_place(function, |x, y| x - y)
+        } else {
+            self.combine_rebuild(function, |x, y| x - y)
+        }
     }
 
     fn shift_right(&mut self, shift: ArgT) -> &mut Self {
@@ -227,6 +411,32 @@ impl FunctionAbstract for FunctionBase {
         self.shift_right(-shift)
     }
 
+    fn scale_value(&mut self, factor: InnerValue) -> &mut Self {
+        self.left_value = self.left_value * factor;
+        self.right_value = self.right_value * factor;
+        self.intervals = self
+            .intervals
+            .iter()
+            .map(|(k, v)| (*k, *v * factor))
+            .collect();
+        self
+    }
+
+    fn round_values(&mut self, unit: InnerValue) -> &mut Self {
+        if !unit.is_finite() || unit <= 0. {
+            return self;
+        }
+        let round = |value: ValueT| ValueT::new((value.float() / unit).round() * unit);
+        self.left_value = round(self.left_value);
+        self.right_value = round(self.right_value);
+        self.intervals = self
+            .intervals
+            .iter()
+            .map(|(k, v)| (*k, round(*v)))
+            .collect();
+        self
+    }
+
     fn negate(&mut self) -> &mut Self {
         self.left_arg = -self.left_arg;
         self.right_arg = -self.right_arg;
@@ -275,10 +485,104 @@ impl FunctionNullable {
     }
 
     pub fn intersect(&self, other: &Self) -> Option<(ArgT, ValueT)> {
+        self.intersect_with_tolerance(other, ArgT::new(DEFAULT_TOLERANCE))
+    }
+
+    /// Moving average of the data points: every value is replaced by the
+    /// average of itself and up to `window` points on each side, arguments
+    /// stay the same. Points near the ends average over the neighbors they
+    /// have, so the window is truncated there instead of padded.
+    pub fn smooth(&self, window: usize) -> Self {
+        Self {
+            function: self.function.as_ref().map(|x| x.smooth(window)),
+        }
+    }
+
+    pub fn intersect_with_tolerance(&self, other: &Self, tol: ArgT) -> Option<(ArgT, ValueT)> {
         self.function
             .as_ref()
             .zip(other.function.as_ref())
-            .and_then(|(x, y)| x.intersect(y))
+            .and_then(|(x, y)| x.intersect_with_tolerance(y, tol))
+    }
+
+    /// State of the market with `self` as the demand and `supply` as the
+    /// supply: the equilibrium if the curves cross, undersupply if demand
+    /// exceeds supply at any price, oversupply if supply exceeds demand at
+    /// any price, and undefined otherwise, e.g. when the curves only touch
+    /// outside of their data points.
+    pub fn equilibrium_state(&self, supply: &Self) -> MarketState {
+        self.equilibrium_state_with_tolerance(supply, ArgT::new(DEFAULT_TOLERANCE))
+    }
+
+    /// Same as `equilibrium_state`, with the price accurate to given
+    /// tolerance. If the curves cross more than once, the lowest price at
+    /// which demand falls below supply is chosen, as the only kind of
+    /// equilibrium prices return to.
+    pub fn equilibrium_state_with_tolerance(&self, supply: &Self, tol: ArgT) -> MarketState {
+        self.equilibrium_state_with_crossings(supply, tol, &self.intersections(supply))
+    }
+
+    /// Same as `equilibrium_state_with_tolerance`, with the crossings of the
+    /// curves already found by `intersections`.
+    pub fn equilibrium_state_with_crossings(
+        &self,
+        supply: &Self,
+        tol: ArgT,
+        crossings: &[(ArgT, ValueT)],
+    ) -> MarketState {
+        if let Some(price) = self.stable_intersection(supply, crossings) {
+            let amount = self.value(price);
+            return MarketState::Equilibrium(price, amount, amount, price);
+        }
+        match self.intersect_with_tolerance(supply, tol) {
+            Some((price, amount)) => MarketState::Equilibrium(price, amount, amount, price),
+            None => {
+                if self.right_value() > supply.right_value() {
+                    MarketState::UnderSupply
+                } else if self.left_value() < supply.left_value() {
+                    MarketState::OverSupply
+                } else {
+                    MarketState::Undefined
+                }
+            }
+        }
+    }
+
+    /// Stable crossing of the demand with the supply, where demand exceeds
+    /// supply below the price and falls short of it above. Only looked for
+    /// when the curves cross several times; a single crossing is left to the
+    /// bisection.
+    fn stable_intersection(&self, supply: &Self, crossings: &[(ArgT, ValueT)]) -> Option<ArgT> {
+        let crossings: Vec<ArgT> = crossings.iter().map(|(price, _)| *price).collect();
+        if crossings.len() < 2 {
+            return None;
+        }
+        let min_arg = self.min_arg().min(supply.min_arg());
+        let max_arg = self.max_arg().max(supply.max_arg());
+        let excess = |price: ArgT| self.value(price) - supply.value(price);
+        (0..crossings.len())
+            .find(|i| {
+                let below = match i.checked_sub(1) {
+                    Some(j) => (crossings[j] + crossings[*i]) / 2.,
+                    None => crossings[*i].min(min_arg) - ArgT::new(1.),
+                };
+                let above = match crossings.get(i + 1) {
+                    Some(next) => (crossings[*i] + *next) / 2.,
+                    None => crossings[*i].max(max_arg) + ArgT::new(1.),
+                };
+                excess(below) > ValueT::zero() && excess(above) < ValueT::zero()
+            })
+            .map(|i| crossings[i])
+    }
+
+    /// All crossings of the functions, see `FunctionBase::intersections`.
+    /// Empty if any of them is zero.
+    pub fn intersections(&self, other: &Self) -> Vec<(ArgT, ValueT)> {
+        self.function
+            .as_ref()
+            .zip(other.function.as_ref())
+            .map(|(x, y)| x.intersections(y))
+            .unwrap_or_default()
     }
 
     pub fn intervals(&self) -> Vec<(ArgT, ValueT)> {
@@ -288,6 +592,13 @@ impl FunctionNullable {
             .unwrap_or_default()
     }
 
+    /// Arguments at which the function changes its slope.
+    pub fn data_points(&self) -> impl Iterator<Item = ArgT> + '_ {
+        self.function
+            .iter()
+            .flat_map(|x| x.intervals.keys().copied())
+    }
+
     pub fn min_arg(&self) -> ArgT {
         self.function
             .as_ref()
@@ -295,7 +606,6 @@ impl FunctionNullable {
             .unwrap_or_else(ArgT::zero)
     }
 
-    #[allow(dead_code)]
     pub fn min_value(&self) -> ValueT {
         self.function
             .as_ref()
@@ -330,6 +640,21 @@ impl FunctionNullable {
             .map(|x| x.right_value)
             .unwrap_or_else(ValueT::zero)
     }
+
+    /// Area under the function between two arguments. Outside of its data
+    /// points the function is treated as constant.
+    pub fn integral(&self, from: ArgT, to: ArgT) -> Revenue {
+        self.function
+            .as_ref()
+            .map(|x| x.integral(from, to))
+            .unwrap_or_else(Revenue::zero)
+    }
+
+    /// Argument at which the function reaches given value, if there is one.
+    pub fn inverse(&self, value: ValueT) -> Option<ArgT> {
+        let constant = Self::new(vec![(self.min_arg(), value)].into_iter());
+        self.intersect(&constant).map(|(arg, _)| arg)
+    }
 }
 
 impl FunctionAbstract for FunctionNullable {
@@ -388,12 +713,42 @@ impl FunctionAbstract for FunctionNullable {
         self
     }
 
+    fn scale_value(&mut self, factor: InnerValue) -> &mut Self {
+        self.function.as_mut().map(|x| x.scale_value(factor));
+        self
+    }
+
+    fn round_values(&mut self, unit: InnerValue) -> &mut Self {
+        self.function.as_mut().map(|x| x.round_values(unit));
+        self
+    }
+
     fn negate(&mut self) -> &mut Self {
         self.function.as_mut().map(|x| x.negate());
         self
     }
 }
 
+impl Add for &FunctionNullable {
+    type Output = FunctionNullable;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let mut res = self.clone();
+        res.add_function(rhs);
+        res
+    }
+}
+
+impl Sub for &FunctionNullable {
+    type Output = FunctionNullable;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut res = self.clone();
+        res.substract_function(rhs);
+        res
+    }
+}
+
 impl Serialize for FunctionNullable {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -425,6 +780,7 @@ mod tests {
     use super::*;
     use crate::util::testing::make_function;
     use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_revenue;
     use crate::util::testing::test_eq_value;
 
     #[cfg(test)]
@@ -453,6 +809,20 @@ mod tests {
             test_eq_value(fun.value(ArgT::new(15.)), ValueT::new(10.));
         }
 
+        #[test]
+        fn near_coincident_args() {
+            let fun = make_function(vec![(1., 3.), (1. + 1e-15, 7.), (2., 8.)]);
+            test_eq_value(fun.value(ArgT::new(1. + 5e-16)), ValueT::new(3.));
+            test_eq_value(fun.value(ArgT::new(1.5)), ValueT::new(7.5));
+        }
+
+        #[test]
+        fn small_magnitude_args() {
+            let fun = make_function(vec![(0., 0.), (1e-13, 1.), (2e-13, 3.)]);
+            test_eq_value(fun.value(ArgT::new(5e-14)), ValueT::new(0.5));
+            test_eq_value(fun.value(ArgT::new(1.5e-13)), ValueT::new(2.));
+        }
+
         #[test]
         fn outside_access_1() {
             let fun = make_function(vec![(1., 3.), (2., 2.)]);
@@ -524,6 +894,21 @@ mod tests {
             test_eq_value(fun.value(ArgT::new(8.)), ValueT::new(12.4));
         }
 
+        #[test]
+        fn add_operator() {
+            let fun = make_function(vec![(1., 4.), (3., 6.), (9., 9.)]);
+            let other = make_function(vec![(1., 5.), (6., 7.), (9., 10.)]);
+            let sum = &fun + &other;
+            test_eq_value(sum.value(ArgT::new(2.)), ValueT::new(10.4));
+            test_eq_value(sum.value(ArgT::new(4.)), ValueT::new(12.7));
+            test_eq_value(sum.value(ArgT::new(8.)), ValueT::new(17.5));
+            test_eq_value(fun.value(ArgT::new(2.)), ValueT::new(5.));
+
+            let difference = &sum - &other;
+            test_eq_value(difference.value(ArgT::new(2.)), ValueT::new(5.));
+            test_eq_value(difference.value(ArgT::new(8.)), ValueT::new(8.5));
+        }
+
         #[test]
         fn shift_left_1() {
             let mut fun = make_function(vec![(3., 4.), (5., 6.)]);
@@ -535,6 +920,37 @@ mod tests {
             test_eq_value(fun.value(ArgT::new(5.)), ValueT::new(6.));
         }
 
+        #[test]
+        fn scale_value_1() {
+            let mut fun = make_function(vec![(1., 4.), (3., 6.)]);
+            fun.scale_value(0.5);
+            test_eq_value(fun.value(ArgT::new(0.)), ValueT::new(2.));
+            test_eq_value(fun.value(ArgT::new(2.)), ValueT::new(2.5));
+            test_eq_value(fun.value(ArgT::new(4.)), ValueT::new(3.));
+        }
+
+        #[test]
+        fn round_values_1() {
+            let mut fun = make_function(vec![(0., 3.4), (2., 1.6), (4., 0.3)]);
+            fun.round_values(1.);
+            test_eq_value(fun.value(ArgT::new(0.)), ValueT::new(3.));
+            test_eq_value(fun.value(ArgT::new(1.)), ValueT::new(2.5));
+            test_eq_value(fun.value(ArgT::new(2.)), ValueT::new(2.));
+            test_eq_value(fun.value(ArgT::new(3.)), ValueT::new(1.));
+            test_eq_value(fun.value(ArgT::new(5.)), ValueT::new(0.));
+        }
+
+        #[test]
+        fn round_values_invalid_unit() {
+            let mut fun = make_function(vec![(0., 3.4), (2., 1.6), (4., 0.3)]);
+            for unit in [0., -1., InnerValue::INFINITY, InnerValue::NAN] {
+                fun.round_values(unit);
+            }
+            assert_eq!(fun.value(ArgT::new(0.)), ValueT::new(3.4));
+            assert_eq!(fun.value(ArgT::new(2.)), ValueT::new(1.6));
+            assert_eq!(fun.value(ArgT::new(4.)), ValueT::new(0.3));
+        }
+
         #[test]
         fn shift_right_1() {
             let mut fun = make_function(vec![(3., 4.), (5., 6.)]);
@@ -545,12 +961,125 @@ mod tests {
             test_eq_value(fun.value(ArgT::new(7.)), ValueT::new(6.));
             test_eq_value(fun.value(ArgT::new(9.)), ValueT::new(6.));
         }
+
+        #[test]
+        fn smooth_spike() {
+            let mut points: Vec<(InnerValue, InnerValue)> = (0..=10)
+                .map(|x| (x as InnerValue, x as InnerValue))
+                .collect();
+            points[5].1 = 20.;
+            let fun = make_function(points);
+            let smoothed = fun.smooth(1);
+            assert_eq!(
+                smoothed.data_points().collect::<Vec<ArgT>>(),
+                fun.data_points().collect::<Vec<ArgT>>()
+            );
+            test_eq_value(smoothed.value(ArgT::new(5.)), ValueT::new(10.));
+            test_eq_value(smoothed.value(ArgT::new(4.)), ValueT::new(9.));
+            test_eq_value(smoothed.value(ArgT::new(6.)), ValueT::new(11.));
+            test_eq_value(smoothed.value(ArgT::new(2.)), ValueT::new(2.));
+            test_eq_value(smoothed.value(ArgT::new(0.)), ValueT::new(0.5));
+            test_eq_value(smoothed.value(ArgT::new(10.)), ValueT::new(9.5));
+            test_eq_value(fun.smooth(0).value(ArgT::new(5.)), ValueT::new(20.));
+            assert!(FunctionNullable::zero().smooth(2).intervals().is_empty());
+        }
+
+        #[test]
+        fn shifted_copies() {
+            let fun = make_function(vec![(3., 4.), (5., 6.)]);
+            let left = fun.shifted_left(ArgT::new(2.));
+            let right = fun.shifted_right(ArgT::new(2.));
+            test_eq_value(fun.value(ArgT::new(4.)), ValueT::new(5.));
+            test_eq_value(left.value(ArgT::new(2.)), ValueT::new(5.));
+            test_eq_value(right.value(ArgT::new(6.)), ValueT::new(5.));
+
+            let demand = Demand::from_rows(&[(0., 4.), (4., 0.)]);
+            let shifted = demand.shifted_right(ArgT::new(1.));
+            test_eq_value(demand.value(ArgT::new(1.)), ValueT::new(3.));
+            test_eq_value(shifted.value(ArgT::new(1.)), ValueT::new(4.));
+            let supply = Supply::from_rows(&[(0., 0.), (4., 4.)]);
+            let shifted = supply.shifted_left(ArgT::new(1.));
+            test_eq_value(supply.value(ArgT::new(1.)), ValueT::new(1.));
+            test_eq_value(shifted.value(ArgT::new(1.)), ValueT::new(2.));
+        }
+    }
+
+    #[cfg(test)]
+    mod incremental {
+        use super::*;
+
+        fn base(values: Vec<(InnerValue, InnerValue)>) -> FunctionBase {
+            FunctionBase::new(
+                values
+                    .into_iter()
+                    .map(|(x, y)| (ArgT::new(x), ValueT::new(y))),
+            )
+        }
+
+        #[test]
+        fn same_points_equivalent() {
+            let points: Vec<InnerValue> = (0..64).map(|x| x as InnerValue / 4.).collect();
+            let other = base(points.iter().map(|x| (*x, (x * 3.).sin() + 2.)).collect());
+            let mut incremental = base(points.iter().map(|x| (*x, *x)).collect());
+            let mut rebuilt = incremental.clone();
+
+            for i in 0..1000 {
+                if i % 3 == 2 {
+                    incremental.substract_function(&other);
+                    rebuilt.combine_rebuild(&other, |x, y| x - y);
+                } else {
+                    incremental.add_function(&other);
+                    rebuilt.combine_rebuild(&other, |x, y| x + y);
+                }
+            }
+
+            assert_eq!(incremental.intervals(), rebuilt.intervals());
+            assert_eq!(incremental.left_value, rebuilt.left_value);
+            assert_eq!(incremental.right_value, rebuilt.right_value);
+        }
+
+        #[test]
+        fn different_points_fall_back() {
+            let mut fun = base(vec![(1., 4.), (3., 6.)]);
+            fun.add_function(&base(vec![(1., 1.), (2., 3.), (3., 1.)]));
+            assert_eq!(fun.intervals().len(), 3);
+            test_eq_value(fun.value(ArgT::new(2.)), ValueT::new(8.));
+        }
     }
 
     #[cfg(test)]
     mod intersection {
         use super::*;
 
+        #[test]
+        fn equilibrium_state_outcomes() {
+            let demand = make_function(vec![(0., 4.), (4., 0.)]);
+            let supply = make_function(vec![(0., 0.), (4., 4.)]);
+            match demand.equilibrium_state(&supply) {
+                MarketState::Equilibrium(price, demand_volume, supply_volume, price_producer) => {
+                    test_eq_arg(price, ArgT::new(2.));
+                    test_eq_arg(price_producer, ArgT::new(2.));
+                    test_eq_value(demand_volume, ValueT::new(2.));
+                    test_eq_value(supply_volume, ValueT::new(2.));
+                }
+                state => panic!("unexpected {:?}", state),
+            }
+
+            let scarce = make_function(vec![(0., 0.), (4., 1.)]);
+            assert!(make_function(vec![(0., 8.), (4., 2.)])
+                .equilibrium_state(&scarce)
+                .is_shortage());
+
+            let plenty = make_function(vec![(0., 5.), (4., 9.)]);
+            assert!(demand.equilibrium_state(&plenty).is_glut());
+
+            /* neither curve exceeds the other, without crossing */
+            assert!(matches!(
+                FunctionNullable::zero().equilibrium_state(&FunctionNullable::zero()),
+                MarketState::Undefined
+            ));
+        }
+
         #[test]
         fn basic_1() {
             let fun_1 = make_function(vec![(3., 4.), (5., 6.)]);
@@ -677,4 +1206,51 @@ mod tests {
             test_eq_value(val, ValueT::new(0.));
         }
     }
+
+    #[cfg(test)]
+    mod integration {
+        use super::*;
+
+        #[test]
+        fn basic_1() {
+            let fun = make_function(vec![(0., 0.), (4., 4.)]);
+            test_eq_revenue(fun.integral(ArgT::new(0.), ArgT::new(2.)), Revenue::new(2.));
+            test_eq_revenue(fun.integral(ArgT::new(0.), ArgT::new(4.)), Revenue::new(8.));
+        }
+
+        #[test]
+        fn basic_2() {
+            let fun = make_function(vec![(0., 2.), (1., 4.), (3., 0.)]);
+            test_eq_revenue(fun.integral(ArgT::new(0.), ArgT::new(3.)), Revenue::new(7.));
+            test_eq_revenue(
+                fun.integral(ArgT::new(0.5), ArgT::new(2.)),
+                Revenue::new(4.75),
+            );
+        }
+
+        #[test]
+        fn reversed_1() {
+            let fun = make_function(vec![(0., 0.), (4., 4.)]);
+            test_eq_revenue(
+                fun.integral(ArgT::new(2.), ArgT::new(0.)),
+                Revenue::new(-2.),
+            );
+        }
+
+        #[test]
+        fn outside_1() {
+            let fun = make_function(vec![(1., 2.), (3., 4.)]);
+            test_eq_revenue(
+                fun.integral(ArgT::new(0.), ArgT::new(5.)),
+                Revenue::new(16.),
+            );
+        }
+
+        #[test]
+        fn inverse_1() {
+            let fun = make_function(vec![(0., 4.), (4., 0.)]);
+            test_eq_arg(fun.inverse(ValueT::new(1.)).unwrap(), ArgT::new(3.));
+            assert_eq!(fun.inverse(ValueT::new(5.)), None);
+        }
+    }
 }
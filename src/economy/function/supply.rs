@@ -1,14 +1,21 @@
 use serde::Deserialize;
 use serde::Serialize;
+use std::ops::{Add, Sub};
 
+use crate::economy::function::check_points;
 use crate::economy::function::demand::Demand;
 use crate::economy::function::ArgT;
+use crate::economy::function::CurveData;
 use crate::economy::function::FunctionAbstract;
+use crate::economy::function::FunctionError;
 use crate::economy::function::FunctionNullable;
 use crate::economy::function::ValueT;
 use crate::economy::market::MarketState;
+use crate::economy::types::InnerValue;
+use crate::economy::types::Revenue;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "CurveData")]
 pub struct Supply {
     function: FunctionNullable,
 }
@@ -20,7 +27,6 @@ impl Supply {
         }
     }
 
-    #[allow(dead_code)]
     pub fn new<I>(values: I) -> Supply
     where
         I: Iterator<Item = (ArgT, ValueT)>,
@@ -30,21 +36,119 @@ impl Supply {
         }
     }
 
+    /// Supply given by `(price, quantity)` rows of plain numbers, e.g.
+    /// read from a spreadsheet.
+    pub fn from_rows(rows: &[(InnerValue, InnerValue)]) -> Supply {
+        Supply::new(
+            rows.iter()
+                .map(|(price, quantity)| (ArgT::new(*price), ValueT::new(*quantity))),
+        )
+    }
+
+    /// Same as `new`, but rejects points with repeated prices or values
+    /// that aren't non-decreasing.
+    pub fn try_new<I>(values: I) -> Result<Supply, FunctionError>
+    where
+        I: Iterator<Item = (ArgT, ValueT)>,
+    {
+        let mut points: Vec<(ArgT, ValueT)> = values.collect();
+        check_points(&mut points, true)?;
+        Ok(Supply::new(points.into_iter()))
+    }
+
     pub fn function(&self) -> &FunctionNullable {
         &self.function
     }
 
-    #[allow(dead_code)]
     pub fn intersect(&self, demand: &Demand) -> MarketState {
-        demand.intersect(self)
+        demand.function().equilibrium_state(&self.function)
+    }
+
+    /// Area under the supply below given price, starting from the lowest
+    /// price anyone is willing to produce at.
+    pub fn producer_surplus(&self, price: ArgT) -> Revenue {
+        let min_arg = self.function.min_arg();
+        if min_arg < price {
+            self.function.integral(min_arg, price)
+        } else {
+            Revenue::zero()
+        }
+    }
+
+    /// Lowest price at which given volume would be supplied. Volume exceeding
+    /// the supply at any price can not be produced.
+    pub fn marginal_cost(&self, volume: ValueT) -> Option<ArgT> {
+        if volume <= self.function.left_value() {
+            Some(self.function.min_arg())
+        } else if volume > self.function.right_value() {
+            None
+        } else {
+            self.function.inverse(volume)
+        }
+    }
+
+    /// Average variable cost of producing given volume, the area to the left
+    /// of the supply divided by the volume.
+    pub fn average_cost_at(&self, volume: ValueT) -> Option<ArgT> {
+        if volume <= ValueT::zero() {
+            return None;
+        }
+        let price = self.marginal_cost(volume)?;
+        let cost = price * volume - self.producer_surplus(price);
+        Some(cost / volume)
+    }
+
+    /// Average variable cost curve, oriented like the supply: its value at
+    /// a price is the volume whose average cost equals that price. Values
+    /// are exact at the volumes of supply's data points and interpolated in
+    /// between. As the volume goes to zero, the average cost goes to the
+    /// lowest price anyone produces at, so the curve starts there with the
+    /// volume supplied at that price.
+    pub fn average_cost(&self) -> FunctionNullable {
+        if self.function.intervals().is_empty() {
+            return FunctionNullable::zero();
+        }
+        let mut points = vec![(self.function.min_arg(), self.function.left_value())];
+        for (_, volume) in self.function.intervals() {
+            if volume > points.last().unwrap().1 {
+                points.push((self.average_cost_at(volume).unwrap(), volume));
+            }
+        }
+        FunctionNullable::new(points.into_iter())
+    }
+
+    /// Supply that never exceeds given volume. Above the price at which the
+    /// cap is reached the supply stays flat.
+    pub fn capped(&self, max: ValueT) -> Supply {
+        if self.function.right_value() <= max {
+            return self.clone();
+        }
+        let cap_arg = self.marginal_cost(max).unwrap();
+        let values = self
+            .function
+            .intervals()
+            .into_iter()
+            .filter(|(arg, _)| *arg < cap_arg)
+            .chain(std::iter::once((cap_arg, max)));
+        Supply::new(values)
     }
 
-    #[allow(dead_code)]
     pub fn intervals(&self) -> Vec<(ArgT, ValueT)> {
         self.function.intervals()
     }
 }
 
+impl TryFrom<CurveData> for Supply {
+    type Error = String;
+
+    fn try_from(data: CurveData) -> Result<Self, Self::Error> {
+        if data.function.is_empty() {
+            return Ok(Supply::zero());
+        }
+        Supply::try_new(data.function.into_iter()).map_err(|x| format!("invalid supply: {}", x))
+    }
+}
+
 impl FunctionAbstract for Supply {
     fn value(&self, arg: ArgT) -> ValueT {
         self.function.value(arg)
@@ -80,8 +184,109 @@ impl FunctionAbstract for Supply {
         self
     }
 
+    fn scale_value(&mut self, factor: InnerValue) -> &mut Self {
+        self.function.scale_value(factor);
+        self
+    }
+
+    fn round_values(&mut self, unit: InnerValue) -> &mut Self {
+        self.function.round_values(unit);
+        self
+    }
+
     fn negate(&mut self) -> &mut Self {
         self.function.negate();
         self
     }
 }
+
+impl Add for &Supply {
+    type Output = Supply;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let mut res = self.clone();
+        res.add_function(rhs);
+        res
+    }
+}
+
+impl Sub for &Supply {
+    type Output = Supply;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut res = self.clone();
+        res.substract_function(rhs);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::economy::function::FunctionAbstract;
+    use crate::economy::function::FunctionError;
+    use crate::economy::function::Supply;
+    use crate::economy::types::Price;
+    use crate::economy::types::Volume;
+    use crate::util::testing::make_supply;
+    use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_value;
+
+    #[test]
+    fn operators() {
+        let first = make_supply(vec![(0., 0.), (4., 4.)]);
+        let second = make_supply(vec![(2., 0.), (4., 2.)]);
+        let total = &first + &second;
+        test_eq_value(total.value(Price::new(1.)), Volume::new(1.));
+        test_eq_value(total.value(Price::new(3.)), Volume::new(4.));
+        let rest = &total - &second;
+        test_eq_value(rest.value(Price::new(3.)), first.value(Price::new(3.)));
+    }
+
+    #[test]
+    fn try_new_monotonicity() {
+        let make = |points: Vec<(f64, f64)>| {
+            Supply::try_new(
+                points
+                    .into_iter()
+                    .map(|(x, y)| (Price::new(x), Volume::new(y))),
+            )
+        };
+        let supply = make(vec![(0., 0.), (1., 1.), (2., 1.)]).unwrap();
+        test_eq_value(supply.value(Price::new(0.5)), Volume::new(0.5));
+        assert!(make(vec![(0., 0.), (1., 1. - 1e-9)]).is_ok());
+        assert_eq!(
+            make(vec![(0., 0.), (1., 2.), (2., 1.)]).map(|_| ()),
+            Err(FunctionError::NotMonotone(Price::new(2.)))
+        );
+    }
+
+    #[test]
+    fn average_cost_linear() {
+        /* marginal cost 1 + q, so average cost 1 + q / 2 */
+        let supply = make_supply(vec![(1., 0.), (5., 4.)]);
+        let average_cost = supply.average_cost();
+        test_eq_value(average_cost.value(Price::new(1.)), Volume::zero());
+        test_eq_value(average_cost.value(Price::new(2.)), Volume::new(2.));
+        test_eq_value(average_cost.value(Price::new(3.)), Volume::new(4.));
+        test_eq_arg(
+            supply.average_cost_at(Volume::new(4.)).unwrap(),
+            Price::new(3.),
+        );
+        assert!(supply.average_cost_at(Volume::zero()).is_none());
+    }
+
+    #[test]
+    fn average_cost_piecewise() {
+        let supply = make_supply(vec![(1., 0.), (2., 1.), (3., 3.), (5., 4.)]);
+        let average_cost = supply.average_cost();
+        for volume in [1., 3., 4.] {
+            let price = supply.average_cost_at(Volume::new(volume)).unwrap();
+            test_eq_value(average_cost.value(price), Volume::new(volume));
+        }
+        /* total cost of 3 units is 1.5 + 2 * 2.5, so they cost 13 / 6 on average */
+        test_eq_arg(
+            supply.average_cost_at(Volume::new(3.)).unwrap(),
+            Price::new(13. / 6.),
+        );
+    }
+}
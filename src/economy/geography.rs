@@ -1,6 +1,9 @@
 use std::collections::BTreeMap;
 
+use super::types::InnerValue;
 use super::types::Price;
+use super::types::Volume;
+use crate::util::data_structure::weighted_graph::WeightedGraph;
 use serde::{Deserialize, Serialize};
 
 pub type CityId = usize;
@@ -9,22 +12,48 @@ pub type CityId = usize;
 pub struct City {
     pub id: CityId,
     pub name: String,
+    #[serde(default)]
+    pub population: Option<u64>,
+    /// Tag of the region the city belongs to.
+    #[serde(default)]
+    pub region: Option<String>,
 }
 
 impl City {
-    #[allow(dead_code)]
     pub fn new(id: CityId, name: String) -> City {
-        City { id, name }
+        City {
+            id,
+            name,
+            population: None,
+            region: None,
+        }
+    }
+
+    pub fn with_population(mut self, population: u64) -> City {
+        self.population = Some(population);
+        self
+    }
+
+    pub fn with_region(mut self, region: String) -> City {
+        self.region = Some(region);
+        self
     }
 
     pub fn id(&self) -> CityId {
         self.id
     }
 
-    #[allow(dead_code)]
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    pub fn population(&self) -> Option<u64> {
+        self.population
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -32,6 +61,9 @@ pub struct Connection {
     id_from: CityId,
     id_to: CityId,
     cost: Price,
+    /// Largest volume that can be shipped along the connection in one turn.
+    #[serde(default)]
+    capacity: Option<Volume>,
 }
 
 impl Connection {
@@ -40,9 +72,15 @@ impl Connection {
             id_from,
             id_to,
             cost,
+            capacity: None,
         }
     }
 
+    pub fn with_capacity(mut self, capacity: Volume) -> Connection {
+        self.capacity = Some(capacity);
+        self
+    }
+
     pub fn id_from(&self) -> CityId {
         self.id_from
     }
@@ -54,14 +92,27 @@ impl Connection {
     pub fn cost(&self) -> Price {
         self.cost
     }
+
+    pub fn capacity(&self) -> Option<Volume> {
+        self.capacity
+    }
 }
 
-#[derive(Clone, Debug)]
+/// Geography is stored as a list of cities and a list of connections, each
+/// of them listed once, although kept in both directions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(into = "GeographyData", try_from = "GeographyData")]
 pub struct Geography {
     pub cities: BTreeMap<CityId, City>,
     pub connections: BTreeMap<CityId, Vec<Connection>>,
 }
 
+impl Default for Geography {
+    fn default() -> Self {
+        Geography::new()
+    }
+}
+
 impl Geography {
     pub fn new() -> Geography {
         Geography {
@@ -78,7 +129,8 @@ impl Geography {
     pub fn add_connection(&mut self, connection: Connection) {
         let id_from = connection.id_from();
         let id_to = connection.id_to();
-        let rev_connection = Connection::new(id_to, id_from, connection.cost());
+        let mut rev_connection = Connection::new(id_to, id_from, connection.cost());
+        rev_connection.capacity = connection.capacity();
 
         self.connections.get_mut(&id_from).unwrap().push(connection);
         self.connections
@@ -94,4 +146,241 @@ impl Geography {
     pub fn connections(&self) -> Vec<&Vec<Connection>> {
         Vec::from_iter(self.connections.values())
     }
+
+    /// Cost of the direct connection between two cities, the cheapest one
+    /// if there are several. Connections work both ways.
+    pub fn connection_cost(&self, from: CityId, to: CityId) -> Option<Price> {
+        self.connection(from, to).map(|x| x.cost())
+    }
+
+    /// Cheapest direct connection between two cities.
+    pub fn connection(&self, from: CityId, to: CityId) -> Option<&Connection> {
+        self.connections
+            .get(&from)?
+            .iter()
+            .filter(|x| x.id_to() == to)
+            .min_by_key(|x| x.cost())
+    }
+
+    /// Cheapest cost of transport between two cities, possibly through
+    /// other cities. None if they are not connected.
+    pub fn shortest_cost(&self, from: CityId, to: CityId) -> Option<Price> {
+        self.graph()
+            .shortest_paths(from)
+            .get(&to)
+            .map(|x| Price::new(*x))
+    }
+
+    /// Largest cost of transport between two cities. None if some cities
+    /// are not connected or there are no cities.
+    pub fn diameter(&self) -> Option<Price> {
+        self.path_costs()?.into_iter().max()
+    }
+
+    /// Average cost of transport between two different cities. None if some
+    /// cities are not connected or there are fewer than two of them.
+    pub fn average_path_cost(&self) -> Option<Price> {
+        let pairs = self.cities.len() * self.cities.len().saturating_sub(1) / 2;
+        if pairs == 0 {
+            return None;
+        }
+        /* costs of cities to themselves are zero and don't change the sum */
+        let total = self
+            .path_costs()?
+            .into_iter()
+            .fold(Price::zero(), |acc, x| acc + x);
+        Some(total / pairs as InnerValue)
+    }
+
+    /// Cheapest costs of transport between every pair of cities, each pair
+    /// listed once, including every city with itself. None if some cities
+    /// are not connected.
+    fn path_costs(&self) -> Option<Vec<Price>> {
+        let graph = self.graph();
+        let mut costs = vec![];
+        for from in self.cities.keys() {
+            let distances = graph.shortest_paths(*from);
+            if distances.len() != self.cities.len() {
+                return None;
+            }
+            costs.extend(distances.range(from..).map(|(_, x)| Price::new(*x)));
+        }
+        Some(costs)
+    }
+
+    fn graph(&self) -> WeightedGraph<CityId> {
+        let mut graph = WeightedGraph::new();
+        for (id, connections) in &self.connections {
+            graph.add_node(*id);
+            for connection in connections {
+                /* every connection is stored in both directions */
+                if connection.id_from() < connection.id_to() {
+                    graph.add_edge(
+                        connection.id_from(),
+                        connection.id_to(),
+                        connection.cost().float(),
+                    );
+                }
+            }
+        }
+        graph
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct GeographyData {
+    cities: Vec<City>,
+    connections: Vec<Connection>,
+}
+
+impl From<Geography> for GeographyData {
+    fn from(geography: Geography) -> Self {
+        GeographyData {
+            cities: geography.cities.into_values().collect(),
+            connections: geography
+                .connections
+                .into_values()
+                .flatten()
+                .filter(|x| x.id_from() < x.id_to())
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<GeographyData> for Geography {
+    type Error = String;
+
+    fn try_from(data: GeographyData) -> Result<Self, Self::Error> {
+        let mut geography = Geography::new();
+        for city in data.cities {
+            geography.add_city(city);
+        }
+        for connection in data.connections {
+            for id in [connection.id_from(), connection.id_to()] {
+                if !geography.cities.contains_key(&id) {
+                    return Err(format!("connection to unknown city {}", id));
+                }
+            }
+            geography.add_connection(connection);
+        }
+        Ok(geography)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::economy::geography::City;
+    use crate::economy::geography::CityId;
+    use crate::economy::geography::Connection;
+    use crate::economy::geography::Geography;
+    use crate::economy::types::Price;
+    use crate::util::testing::test_eq_arg;
+
+    #[test]
+    fn shortest_cost() {
+        let mut geography = Geography::new();
+        for id in 0..4 {
+            geography.add_city(City::new(id, format!("city {}", id)));
+        }
+        geography.add_connection(Connection::new(0, 1, Price::new(5.)));
+        geography.add_connection(Connection::new(0, 2, Price::new(1.)));
+        geography.add_connection(Connection::new(2, 1, Price::new(2.)));
+
+        test_eq_arg(geography.shortest_cost(0, 1).unwrap(), Price::new(3.));
+        test_eq_arg(geography.shortest_cost(1, 1).unwrap(), Price::zero());
+        assert!(geography.shortest_cost(0, 3).is_none());
+    }
+
+    #[test]
+    fn diameter_chain() {
+        let mut geography = Geography::new();
+        assert!(geography.diameter().is_none());
+        for id in 0..4 {
+            geography.add_city(City::new(id, format!("city {}", id)));
+        }
+        geography.add_connection(Connection::new(0, 1, Price::new(1.)));
+        geography.add_connection(Connection::new(1, 2, Price::new(2.)));
+        assert!(geography.diameter().is_none());
+        assert!(geography.average_path_cost().is_none());
+
+        geography.add_connection(Connection::new(2, 3, Price::new(3.)));
+        test_eq_arg(geography.diameter().unwrap(), Price::new(6.));
+        /* 1 + 3 + 6 + 2 + 5 + 3 over 6 pairs */
+        test_eq_arg(geography.average_path_cost().unwrap(), Price::new(20. / 6.));
+    }
+
+    #[test]
+    fn diameter_clique() {
+        let mut geography = Geography::new();
+        for id in 0..4 {
+            geography.add_city(City::new(id, format!("city {}", id)));
+        }
+        for from in 0..4 {
+            for to in from + 1..4 {
+                geography.add_connection(Connection::new(from, to, Price::new(2.)));
+            }
+        }
+        test_eq_arg(geography.diameter().unwrap(), Price::new(2.));
+        test_eq_arg(geography.average_path_cost().unwrap(), Price::new(2.));
+    }
+
+    #[test]
+    fn connection_cost() {
+        let mut geography = Geography::new();
+        for id in 0..3 {
+            geography.add_city(City::new(id, format!("city {}", id)));
+        }
+        geography.add_connection(Connection::new(0, 1, Price::new(5.)));
+        geography.add_connection(Connection::new(1, 2, Price::new(2.)));
+        geography.add_connection(Connection::new(2, 1, Price::new(1.)));
+
+        assert_eq!(geography.connection_cost(0, 1), Some(Price::new(5.)));
+        assert_eq!(geography.connection_cost(1, 0), Some(Price::new(5.)));
+        assert_eq!(geography.connection_cost(1, 2), Some(Price::new(1.)));
+        assert_eq!(geography.connection_cost(0, 2), None);
+        assert_eq!(geography.connection_cost(0, 7), None);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let mut geography = Geography::new();
+        for id in 0..5 {
+            geography.add_city(City::new(id, format!("city {}", id)));
+        }
+        geography.add_connection(Connection::new(0, 1, Price::new(5.)));
+        geography.add_connection(Connection::new(1, 2, Price::new(5.)));
+        geography.add_connection(Connection::new(0, 2, Price::new(5.)));
+        geography.add_connection(Connection::new(1, 3, Price::new(100.)));
+        geography.add_connection(Connection::new(0, 4, Price::new(100.)));
+        geography.add_connection(Connection::new(2, 3, Price::new(100.)));
+        geography.add_connection(Connection::new(4, 3, Price::new(5.)));
+
+        let json = serde_json::to_value(&geography).unwrap();
+        assert_eq!(json["cities"].as_array().unwrap().len(), 5);
+        assert_eq!(json["connections"].as_array().unwrap().len(), 7);
+
+        let loaded: Geography = serde_json::from_value(json).unwrap();
+        let names = |x: &Geography| -> Vec<(CityId, String)> {
+            x.cities()
+                .iter()
+                .map(|x| (x.id(), x.name().clone()))
+                .collect()
+        };
+        let edges = |x: &Geography| -> Vec<(CityId, CityId, Price)> {
+            x.connections()
+                .into_iter()
+                .flatten()
+                .map(|x| (x.id_from(), x.id_to(), x.cost()))
+                .collect()
+        };
+        assert_eq!(names(&loaded), names(&geography));
+        let mut loaded_edges = edges(&loaded);
+        let mut original_edges = edges(&geography);
+        loaded_edges.sort();
+        original_edges.sort();
+        assert_eq!(loaded_edges, original_edges);
+
+        let invalid = r#"{"cities": [], "connections": [{"id_from": 0, "id_to": 1, "cost": 1.0}]}"#;
+        assert!(serde_json::from_str::<Geography>(invalid).is_err());
+    }
 }
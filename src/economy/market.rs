@@ -1,23 +1,76 @@
+use crate::economy::commodity::Commodity;
 use crate::economy::entity::Consumer;
 use crate::economy::entity::Producer;
 use crate::economy::function::Demand;
 use crate::economy::function::FunctionAbstract;
+use crate::economy::function::FunctionNullable;
 use crate::economy::function::Supply;
+use crate::economy::function::DEFAULT_TOLERANCE;
 use crate::economy::geography::CityId;
 use crate::economy::geography::Geography;
+use crate::economy::snapshot::CitySnapshot;
+use crate::economy::snapshot::MarketSnapshot;
+use crate::economy::solver::IntersectionSolver;
+use crate::economy::solver::SolverStrategy;
+use crate::economy::welfare::WelfareReport;
+use crate::util::data_structure::directed_graph::DirectedGraph;
+use dashmap::mapref::one::Ref;
+use dashmap::mapref::one::RefMut;
 use dashmap::DashMap;
 use rayon::prelude::*;
-use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
+use super::types::InnerValue;
 use super::types::Price;
+use super::types::Revenue;
 use super::types::Volume;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum MarketState {
     Undefined,
     UnderSupply,
     OverSupply,
-    Equilibrium(Price, Volume, Volume),
+    /// Price paid by consumers, demand volume, supply volume and price
+    /// received by producers. Both prices are equal unless a tax or a subsidy
+    /// is imposed, the difference being the tax or the subsidy per unit.
+    Equilibrium(Price, Volume, Volume, Price),
+}
+
+impl MarketState {
+    pub fn is_equilibrium(&self) -> bool {
+        matches!(self, MarketState::Equilibrium(..))
+    }
+
+    /// Demand exceeds supply at every price.
+    pub fn is_shortage(&self) -> bool {
+        matches!(self, MarketState::UnderSupply)
+    }
+
+    /// Supply exceeds demand at every price.
+    pub fn is_glut(&self) -> bool {
+        matches!(self, MarketState::OverSupply)
+    }
+}
+
+impl fmt::Display for MarketState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarketState::Undefined => write!(f, "undefined"),
+            MarketState::UnderSupply => write!(f, "undersupply"),
+            MarketState::OverSupply => write!(f, "oversupply"),
+            MarketState::Equilibrium(price, volume, _, _) => {
+                write!(
+                    f,
+                    "equilibrium @ {:.2}, q={:.2}",
+                    price.float(),
+                    volume.float()
+                )
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -25,6 +78,13 @@ pub struct CityData {
     demand: Demand,
     supply: Supply,
     state: MarketState,
+    price_floor: Option<Price>,
+    price_ceiling: Option<Price>,
+    /// Tax per unit, negative for a subsidy.
+    tax: Price,
+    monopoly: Option<Supply>,
+    /// Whether the city changed since its group was last solved.
+    dirty: bool,
 }
 
 impl CityData {
@@ -33,6 +93,11 @@ impl CityData {
             demand: Demand::zero(),
             supply: Supply::zero(),
             state: MarketState::Undefined,
+            price_floor: None,
+            price_ceiling: None,
+            tax: Price::zero(),
+            monopoly: None,
+            dirty: true,
         }
     }
 
@@ -42,10 +107,12 @@ impl CityData {
 
     fn add_demand(&mut self, demand: &Demand) {
         self.demand.add_function(demand);
+        self.dirty = true;
     }
 
     fn substract_demand(&mut self, demand: &Demand) {
         self.demand.substract_function(demand);
+        self.dirty = true;
     }
 
     pub fn supply(&self) -> &Supply {
@@ -54,10 +121,12 @@ impl CityData {
 
     fn add_supply(&mut self, supply: &Supply) {
         self.supply.add_function(supply);
+        self.dirty = true;
     }
 
     fn substract_supply(&mut self, supply: &Supply) {
         self.supply.substract_function(supply);
+        self.dirty = true;
     }
 
     pub fn state(&self) -> &MarketState {
@@ -68,8 +137,165 @@ impl CityData {
         self.state = state;
     }
 
+    /// Sets the state from outside of the solver, so the city has to be
+    /// solved again.
+    fn reset_state(&mut self, state: MarketState) {
+        self.state = state;
+        self.dirty = true;
+    }
+
+    pub fn price_floor(&self) -> Option<Price> {
+        self.price_floor
+    }
+
+    pub fn price_ceiling(&self) -> Option<Price> {
+        self.price_ceiling
+    }
+
+    fn set_price_bound(&mut self, floor: Option<Price>, ceiling: Option<Price>) {
+        self.price_floor = floor;
+        self.price_ceiling = ceiling;
+        self.dirty = true;
+    }
+
+    pub fn tax(&self) -> Price {
+        self.tax
+    }
+
+    fn set_tax(&mut self, tax: Price) {
+        self.tax = tax;
+        self.dirty = true;
+    }
+
+    /// Subsidy per unit received by producers, zero if none is paid.
+    pub fn subsidy(&self) -> Price {
+        max(-self.tax, Price::zero())
+    }
+
+    /// Total amount spent on the subsidy, defined only in an equilibrium.
+    pub fn subsidy_cost(&self) -> Option<Revenue> {
+        Some(self.subsidy() * self.supply_volume()?)
+    }
+
+    pub fn monopoly(&self) -> Option<&Supply> {
+        self.monopoly.as_ref()
+    }
+
+    fn set_monopoly(&mut self, costs: Supply) {
+        self.monopoly = Some(costs);
+        self.dirty = true;
+    }
+
+    /// State of the city when a single producer with given marginal costs
+    /// sets the price maximizing its profit. Profit of selling volume `q` at
+    /// price `p` equals `(p - mc(q)) * q` increased by the savings on units
+    /// produced below the marginal cost.
+    fn monopoly_state(&self, costs: &Supply) -> MarketState {
+        const SEARCH_STEPS: usize = 1024;
+
+        let demand = self.demand.function();
+        let min_price = demand.min_arg();
+        let max_price = demand.max_arg();
+        if min_price >= max_price {
+            return MarketState::Undefined;
+        }
+
+        let profit = |price: Price| -> Option<Revenue> {
+            let volume = demand.value(price);
+            costs
+                .marginal_cost(volume)
+                .map(|cost| (price - cost) * volume + costs.producer_surplus(cost))
+        };
+
+        let step = (max_price - min_price) / SEARCH_STEPS as InnerValue;
+        let best = (0..=SEARCH_STEPS)
+            .map(|i| min_price + step * i as InnerValue)
+            .filter_map(|price| profit(price).map(|x| (x, price)))
+            .max_by_key(|x| x.0);
+
+        // Refine the best found price between its neighbouring samples.
+        let (mut low, mut high) = match best {
+            Some((_, price)) => (max(price - step, min_price), min(price + step, max_price)),
+            None => return MarketState::UnderSupply,
+        };
+        let eps = Price::new(1e-6);
+        while high - low > eps {
+            let mid_low = low + (high - low) / 3.;
+            let mid_high = high - (high - low) / 3.;
+            if profit(mid_low) < profit(mid_high) {
+                low = mid_low;
+            } else {
+                high = mid_high;
+            }
+        }
+
+        let volume = demand.value(low);
+        MarketState::Equilibrium(low, volume, volume, low)
+    }
+
+    /// Whether a tax or a price bound is imposed in the city.
+    pub fn has_policy(&self) -> bool {
+        self.tax != Price::zero() || self.price_floor.is_some() || self.price_ceiling.is_some()
+    }
+
+    /// Traded volume together with the intersection of city's own supply and
+    /// demand, if less than at the intersection is traded.
+    fn restricted_volume(&self) -> Option<(Volume, (Price, Volume))> {
+        let (volume_demand, volume_supply) = match self.state {
+            MarketState::Equilibrium(_, volume_demand, volume_supply, _) => {
+                (volume_demand, volume_supply)
+            }
+            _ => return None,
+        };
+        let volume = min(volume_demand, volume_supply);
+
+        let free = self.demand.function().intersect(self.supply.function())?;
+        if volume >= free.1 {
+            return None;
+        }
+        Some((volume, free))
+    }
+
+    /// Range of volumes not traded because of the policy imposed in the city,
+    /// `None` at a free market equilibrium. Measured against the city's own
+    /// supply and demand, so it holds only for a city that doesn't trade.
+    fn deadweight_loss_range(&self) -> Option<(Volume, Volume)> {
+        if !self.has_policy() {
+            return None;
+        }
+        self.restricted_volume()
+            .map(|(volume, (_, volume_free))| (volume, volume_free))
+    }
+
+    /// Welfare lost compared to the intersection of city's own supply and
+    /// demand, due to smaller volume being traded. As the range, holds only
+    /// for a city that doesn't trade.
+    fn deadweight_loss(&self) -> Revenue {
+        let (volume, price_free) = match self.restricted_volume() {
+            Some((volume, (price_free, _))) => (volume, price_free),
+            None => return Revenue::zero(),
+        };
+
+        let demand = self.demand.function();
+        let supply = self.supply.function();
+        match (supply.inverse(volume), demand.inverse(volume)) {
+            (Some(price_low), Some(price_high)) => {
+                supply.integral(price_low, price_free) - (price_free - price_low) * volume
+                    + demand.integral(price_free, price_high)
+                    - (price_high - price_free) * volume
+            }
+            _ => Revenue::zero(),
+        }
+    }
+
+    fn bound_price(&self, price: Price) -> Price {
+        let price = self.price_floor.map_or(price, |floor| max(price, floor));
+        self.price_ceiling
+            .map_or(price, |ceiling| min(price, ceiling))
+    }
+
     pub fn price(&self) -> Option<Price> {
-        if let MarketState::Equilibrium(price, _, _) = self.state {
+        if let MarketState::Equilibrium(price, _, _, _) = self.state {
             Some(price)
         } else {
             None
@@ -77,7 +303,7 @@ impl CityData {
     }
 
     pub fn demand_volume(&self) -> Option<Volume> {
-        if let MarketState::Equilibrium(_, volume, _) = self.state {
+        if let MarketState::Equilibrium(_, volume, _, _) = self.state {
             Some(volume)
         } else {
             None
@@ -85,255 +311,1181 @@ impl CityData {
     }
 
     pub fn supply_volume(&self) -> Option<Volume> {
-        if let MarketState::Equilibrium(_, _, volume) = self.state {
+        if let MarketState::Equilibrium(_, _, volume, _) = self.state {
             Some(volume)
         } else {
             None
         }
     }
+
+    /// Supply minus demand, positive for a glut and negative for a shortage.
+    /// In an equilibrium it is taken at the prevailing price, so it is
+    /// non-zero only under a binding price control. Without an equilibrium
+    /// it is the smallest imbalance over all prices: at the lowest price for
+    /// an oversupply and at the highest one for an undersupply.
+    pub fn excess_supply(&self) -> Option<Volume> {
+        let price = match self.state {
+            MarketState::Equilibrium(_, demand, supply, _) => return Some(supply - demand),
+            MarketState::OverSupply => min(
+                self.demand.function().min_arg(),
+                self.supply.function().min_arg() + self.tax,
+            ),
+            MarketState::UnderSupply => max(
+                self.demand.function().max_arg(),
+                self.supply.function().max_arg() + self.tax,
+            ),
+            MarketState::Undefined => return None,
+        };
+        Some(self.supply.value(price - self.tax) - self.demand.value(price))
+    }
+
+    pub fn producer_price(&self) -> Option<Price> {
+        if let MarketState::Equilibrium(_, _, _, price) = self.state {
+            Some(price)
+        } else {
+            None
+        }
+    }
+
+    /// Demand and supply volumes if consumers paid given price, regardless
+    /// of the solved state. Producers receive the price lowered by the tax.
+    pub fn volumes_at(&self, price: Price) -> (Volume, Volume) {
+        (
+            self.demand.value(price),
+            self.supply.value(price - self.tax),
+        )
+    }
+}
+
+/// Aggregates of one price group, as built by the solver.
+#[derive(Clone, Debug)]
+pub struct GroupEquilibrium {
+    /// Cities of the group with their prices relative to the group base.
+    pub cities: Vec<(CityId, Price)>,
+    pub demand: Demand,
+    pub supply: Supply,
+    /// Intersection of the aggregates, with prices of the group base.
+    pub state: MarketState,
 }
 
 #[derive(Debug)]
 pub struct Market {
     geography: Geography,
-    cities: DashMap<CityId, CityData>,
+    /// Data of every city for each traded commodity. The default commodity
+    /// is always present, others are added by their first agent.
+    commodities: BTreeMap<Commodity, DashMap<CityId, CityData>>,
+    price_history: Option<Vec<BTreeMap<CityId, Option<Price>>>>,
+    /// Total volume traded after every update, recorded along with prices.
+    volume_history: Option<Vec<Volume>>,
+    /// Groups of the last solution, skipped while they stay the same and
+    /// none of their cities changes.
+    last_groups: BTreeMap<Commodity, BTreeMap<CityId, Vec<(CityId, Price)>>>,
+    solved_groups: usize,
+    /// Bases of groups whose curves crossed more than once in the last
+    /// update, for each commodity.
+    multiple_equilibria: Vec<(Commodity, CityId)>,
+    /// Range to which equilibrium prices of groups are clamped.
+    price_search_bounds: Option<(Price, Price)>,
+    solver: Box<dyn SolverStrategy>,
 }
 
 impl Market {
+    /// Market with given starting prices. Cities without one start with no
+    /// price at all, as `MarketState::Undefined`; they get one in the first
+    /// update of prices like any other city, and everything reading the
+    /// results has to accept cities without a price.
     pub fn new(geography: Geography, prices: BTreeMap<CityId, Price>) -> Market {
-        let cities: DashMap<CityId, CityData> = geography
+        let cities = Market::empty_cities(&geography);
+        let mut market = Market::from_cities(geography, cities);
+        market.reset_to(prices);
+        market
+    }
+
+    fn from_cities(geography: Geography, cities: DashMap<CityId, CityData>) -> Market {
+        Market {
+            geography,
+            commodities: BTreeMap::from([(Commodity::default(), cities)]),
+            price_history: None,
+            volume_history: None,
+            last_groups: BTreeMap::new(),
+            solved_groups: 0,
+            multiple_equilibria: vec![],
+            price_search_bounds: None,
+            solver: Box::new(IntersectionSolver),
+        }
+    }
+
+    fn empty_cities(geography: &Geography) -> DashMap<CityId, CityData> {
+        geography
             .cities()
             .into_iter()
-            .map(|x| {
-                (x.id(), {
-                    let mut data = CityData::new();
-                    prices
-                        .get(&x.id())
-                        .map(|x| MarketState::Equilibrium(*x, Volume::zero(), Volume::zero()))
-                        .into_iter()
-                        .for_each(|x| data.set_state(x));
-                    data
-                })
-            })
-            .collect();
-        Market { geography, cities }
+            .map(|x| (x.id(), CityData::new()))
+            .collect()
     }
 
     pub fn geography(&self) -> &Geography {
         &self.geography
     }
 
+    /// Commodities traded in the market, the default one included.
+    pub fn commodities(&self) -> impl Iterator<Item = Commodity> + '_ {
+        self.commodities.keys().copied()
+    }
+
+    /// Data of all cities for the default commodity.
     pub fn cities(&self) -> &DashMap<CityId, CityData> {
-        &self.cities
+        &self.commodities[&Commodity::default()]
+    }
+
+    /// Data of all cities for given commodity, `None` if nobody trades it.
+    pub fn commodity_cities(&self, commodity: Commodity) -> Option<&DashMap<CityId, CityData>> {
+        self.commodities.get(&commodity)
+    }
+
+    /// Data of all cities for given commodity, added to the market if it
+    /// is not traded yet.
+    fn commodity_cities_mut(&mut self, commodity: Commodity) -> &DashMap<CityId, CityData> {
+        let geography = &self.geography;
+        self.commodities
+            .entry(commodity)
+            .or_insert_with(|| Market::empty_cities(geography))
+    }
+
+    fn commodity_data_mut(
+        &mut self,
+        commodity: Commodity,
+        id: CityId,
+    ) -> RefMut<'_, CityId, CityData> {
+        self.commodity_cities_mut(commodity).get_mut(&id).unwrap()
+    }
+
+    pub fn city_data(&self, id: CityId) -> Option<Ref<'_, CityId, CityData>> {
+        self.cities().get(&id)
+    }
+
+    pub fn commodity_data(
+        &self,
+        commodity: Commodity,
+        id: CityId,
+    ) -> Option<Ref<'_, CityId, CityData>> {
+        self.commodity_cities(commodity)?.get(&id)
+    }
+
+    /// Data of all cities, ordered by their ids.
+    pub fn iter_cities(&self) -> impl Iterator<Item = (CityId, Ref<'_, CityId, CityData>)> {
+        self.geography
+            .cities
+            .keys()
+            .map(|id| (*id, self.cities().get(id).unwrap()))
     }
 
     pub fn add_producer(&mut self, prod: &Producer) {
-        self.cities
-            .get_mut(&prod.city())
-            .unwrap()
-            .add_supply(prod.supply())
+        for (city, supply) in prod.offers(&self.geography) {
+            self.commodity_cities_mut(prod.commodity())
+                .get_mut(&city)
+                .unwrap()
+                .add_supply(&supply)
+        }
     }
 
-    #[allow(dead_code)]
     pub fn remove_producer(&mut self, prod: &Producer) {
-        self.cities
-            .get_mut(&prod.city())
-            .unwrap()
-            .substract_supply(prod.supply())
+        for (city, supply) in prod.offers(&self.geography) {
+            self.commodity_cities_mut(prod.commodity())
+                .get_mut(&city)
+                .unwrap()
+                .substract_supply(&supply)
+        }
+    }
+
+    /// Sets price controls in given city. Prices computed by the market are
+    /// clamped to the bound, so a binding floor results in a surplus and
+    /// a binding ceiling in a shortage.
+    pub fn set_price_bound(&mut self, city: CityId, floor: Option<Price>, ceiling: Option<Price>) {
+        self.set_commodity_price_bound(Commodity::default(), city, floor, ceiling)
+    }
+
+    /// Same as `set_price_bound`, for given commodity.
+    pub fn set_commodity_price_bound(
+        &mut self,
+        commodity: Commodity,
+        city: CityId,
+        floor: Option<Price>,
+        ceiling: Option<Price>,
+    ) {
+        self.commodity_data_mut(commodity, city)
+            .set_price_bound(floor, ceiling)
+    }
+
+    /// Puts the city under control of a single producer with given marginal
+    /// costs. Such city doesn't trade with its neighbours and its own
+    /// supply is ignored.
+    pub fn set_monopoly(&mut self, city: CityId, producer_supply: &Supply) {
+        self.set_commodity_monopoly(Commodity::default(), city, producer_supply)
+    }
+
+    /// Same as `set_monopoly`, for given commodity.
+    pub fn set_commodity_monopoly(
+        &mut self,
+        commodity: Commodity,
+        city: CityId,
+        producer_supply: &Supply,
+    ) {
+        self.commodity_data_mut(commodity, city)
+            .set_monopoly(producer_supply.clone())
+    }
+
+    /// Imposes a per-unit tax in given city. Consumers pay the market price
+    /// while producers receive the market price lowered by the tax.
+    pub fn set_tax(&mut self, city: CityId, tax: Price) {
+        self.set_commodity_tax(Commodity::default(), city, tax)
+    }
+
+    /// Same as `set_tax`, for given commodity.
+    pub fn set_commodity_tax(&mut self, commodity: Commodity, city: CityId, tax: Price) {
+        self.commodity_data_mut(commodity, city).set_tax(tax)
+    }
+
+    /// Keeps equilibrium prices of price groups between `min` and `max`,
+    /// guarding against runaway prices of pathological curves. Prices found
+    /// outside of the range are clamped to it.
+    ///
+    /// Panics if `min` is greater than `max`.
+    pub fn set_price_search_bounds(&mut self, min: Price, max: Price) {
+        assert!(
+            min <= max,
+            "price search bounds are reversed: min {} is greater than max {}",
+            min.float(),
+            max.float()
+        );
+        self.price_search_bounds = Some((min, max));
+        self.commodities
+            .values()
+            .flat_map(|cities| cities.iter_mut())
+            .for_each(|mut city| city.dirty = true);
+    }
+
+    /// Replaces the method of solving price groups, by default the
+    /// intersection of their curves.
+    pub fn set_solver(&mut self, solver: Box<dyn SolverStrategy>) {
+        self.solver = solver;
+        self.commodities
+            .values()
+            .flat_map(|cities| cities.iter_mut())
+            .for_each(|mut city| city.dirty = true);
+    }
+
+    /// Pays producers in given city a per-unit subsidy. Producers receive
+    /// the market price raised by the subsidy, so more is traded at a lower
+    /// price than without it. Replaces the tax imposed in the city.
+    pub fn set_subsidy(&mut self, city: CityId, subsidy: Price) {
+        self.set_tax(city, -subsidy)
+    }
+
+    /// Same as `set_subsidy`, for given commodity.
+    pub fn set_commodity_subsidy(&mut self, commodity: Commodity, city: CityId, subsidy: Price) {
+        self.set_commodity_tax(commodity, city, -subsidy)
     }
 
     pub fn add_consumer(&mut self, cons: &Consumer) {
-        self.cities
+        self.commodity_cities_mut(cons.commodity())
             .get_mut(&cons.city())
             .unwrap()
-            .add_demand(cons.demand())
+            .add_demand(&cons.current_demand())
     }
 
-    #[allow(dead_code)]
     pub fn remove_consumer(&mut self, cons: &Consumer) {
-        self.cities
+        self.commodity_cities_mut(cons.commodity())
             .get_mut(&cons.city())
             .unwrap()
-            .substract_demand(cons.demand())
+            .substract_demand(&cons.current_demand())
+    }
+
+    /// Adds demand in given city that doesn't belong to any consumer.
+    pub fn add_demand(&mut self, city: CityId, demand: &Demand) {
+        self.add_commodity_demand(Commodity::default(), city, demand)
+    }
+
+    /// Same as `add_demand`, for given commodity.
+    pub fn add_commodity_demand(&mut self, commodity: Commodity, city: CityId, demand: &Demand) {
+        self.commodity_data_mut(commodity, city).add_demand(demand)
+    }
+
+    pub fn remove_demand(&mut self, city: CityId, demand: &Demand) {
+        self.remove_commodity_demand(Commodity::default(), city, demand)
+    }
+
+    pub fn remove_commodity_demand(&mut self, commodity: Commodity, city: CityId, demand: &Demand) {
+        self.commodity_data_mut(commodity, city)
+            .substract_demand(demand)
+    }
+
+    /// Adds supply in given city that doesn't belong to any producer.
+    pub fn add_supply(&mut self, city: CityId, supply: &Supply) {
+        self.add_commodity_supply(Commodity::default(), city, supply)
+    }
+
+    /// Same as `add_supply`, for given commodity.
+    pub fn add_commodity_supply(&mut self, commodity: Commodity, city: CityId, supply: &Supply) {
+        self.commodity_data_mut(commodity, city).add_supply(supply)
+    }
+
+    pub fn remove_supply(&mut self, city: CityId, supply: &Supply) {
+        self.remove_commodity_supply(Commodity::default(), city, supply)
+    }
+
+    pub fn remove_commodity_supply(&mut self, commodity: Commodity, city: CityId, supply: &Supply) {
+        self.commodity_data_mut(commodity, city)
+            .substract_supply(supply)
+    }
+
+    /// Perturbs the demand in given city, to be called between turns. Values
+    /// of `delta` are added to the city's aggregate demand, so a drop in
+    /// demand is modelled by negative volumes.
+    pub fn apply_demand_shock(&mut self, city: CityId, delta: &Demand) {
+        self.add_demand(city, delta)
+    }
+
+    /// Same as `apply_demand_shock`, for given commodity.
+    pub fn apply_commodity_demand_shock(
+        &mut self,
+        commodity: Commodity,
+        city: CityId,
+        delta: &Demand,
+    ) {
+        self.add_commodity_demand(commodity, city, delta)
+    }
+
+    /// Perturbs the supply in given city, to be called between turns. Values
+    /// of `delta` are added to the city's aggregate supply, so a disruption
+    /// is modelled by negative volumes.
+    pub fn apply_supply_shock(&mut self, city: CityId, delta: &Supply) {
+        self.add_supply(city, delta)
+    }
+
+    /// Same as `apply_supply_shock`, for given commodity.
+    pub fn apply_commodity_supply_shock(
+        &mut self,
+        commodity: Commodity,
+        city: CityId,
+        delta: &Supply,
+    ) {
+        self.add_commodity_supply(commodity, city, delta)
     }
 
-    #[allow(dead_code)]
     pub fn prices(&self) -> BTreeMap<CityId, Option<Price>> {
-        self.cities.iter().map(|x| (*x.key(), x.price())).collect()
+        self.commodity_prices(Commodity::default())
+    }
+
+    /// Prices of given commodity, empty if nobody trades it.
+    pub fn commodity_prices(&self, commodity: Commodity) -> BTreeMap<CityId, Option<Price>> {
+        self.commodity_cities(commodity)
+            .into_iter()
+            .flat_map(|cities| cities.iter().map(|x| (*x.key(), x.price())))
+            .collect()
     }
 
-    #[allow(dead_code)]
     pub fn demand_volumes(&self) -> BTreeMap<CityId, Option<Volume>> {
-        self.cities
-            .iter()
-            .map(|x| (*x.key(), x.demand_volume()))
+        self.commodity_demand_volumes(Commodity::default())
+    }
+
+    /// Demand volumes of given commodity, empty if nobody trades it.
+    pub fn commodity_demand_volumes(
+        &self,
+        commodity: Commodity,
+    ) -> BTreeMap<CityId, Option<Volume>> {
+        self.commodity_cities(commodity)
+            .into_iter()
+            .flat_map(|cities| cities.iter().map(|x| (*x.key(), x.demand_volume())))
             .collect()
     }
 
-    #[allow(dead_code)]
     pub fn supply_volumes(&self) -> BTreeMap<CityId, Option<Volume>> {
-        self.cities
+        self.commodity_supply_volumes(Commodity::default())
+    }
+
+    /// Supply volumes of given commodity, empty if nobody trades it.
+    pub fn commodity_supply_volumes(
+        &self,
+        commodity: Commodity,
+    ) -> BTreeMap<CityId, Option<Volume>> {
+        self.commodity_cities(commodity)
+            .into_iter()
+            .flat_map(|cities| cities.iter().map(|x| (*x.key(), x.supply_volume())))
+            .collect()
+    }
+
+    /// Bases of the groups of the default commodity whose demand and supply
+    /// crossed more than once in the last update. Their prices are at the
+    /// stable crossing, but other equilibria exist.
+    pub fn multiple_equilibria(&self) -> Vec<CityId> {
+        self.multiple_equilibria
             .iter()
-            .map(|x| (*x.key(), x.supply_volume()))
+            .filter(|(commodity, _)| *commodity == Commodity::default())
+            .map(|(_, base)| *base)
             .collect()
     }
 
-    fn calculate_groups_dfs(
-        &self,
-        pos: CityId,
-        group_id: CityId,
-        group_diff: Price,
-        groups: &mut BTreeMap<CityId, (CityId, Price)>,
-    ) {
-        if groups.contains_key(&pos) {
-            return;
-        }
-        groups.insert(pos, (group_id, group_diff));
+    /// Prices keyed by city names. If several cities share a name, the one
+    /// with the highest id is listed.
+    pub fn prices_by_name(&self) -> BTreeMap<String, Option<Price>> {
+        self.by_name(self.prices())
+    }
 
-        let connections = self.geography.connections();
-        for conn in connections[pos] {
-            let id_from = conn.id_from();
-            let id_to = conn.id_to();
-            let cost = conn.cost();
+    /// Demand volumes keyed by city names, as in `prices_by_name`.
+    pub fn demand_volumes_by_name(&self) -> BTreeMap<String, Option<Volume>> {
+        self.by_name(self.demand_volumes())
+    }
 
-            let (price_from, price_to) = match (
-                self.cities.get(&id_from).unwrap().state(),
-                self.cities.get(&id_to).unwrap().state(),
-            ) {
-                (
-                    MarketState::Equilibrium(price_from, _, _),
-                    MarketState::Equilibrium(price_to, _, _),
-                ) => (*price_from, *price_to),
-                (MarketState::OverSupply, MarketState::Equilibrium(price_to, _, _)) => {
-                    (Price::min(), *price_to)
-                }
-                (MarketState::UnderSupply, MarketState::Equilibrium(price_to, _, _)) => {
-                    (Price::max(), *price_to)
-                }
-                (MarketState::Equilibrium(price_from, _, _), MarketState::OverSupply) => {
-                    (*price_from, Price::min())
-                }
-                (MarketState::Equilibrium(price_from, _, _), MarketState::UnderSupply) => {
-                    (*price_from, Price::max())
+    /// Supply volumes keyed by city names, as in `prices_by_name`.
+    pub fn supply_volumes_by_name(&self) -> BTreeMap<String, Option<Volume>> {
+        self.by_name(self.supply_volumes())
+    }
+
+    fn by_name<T>(&self, mut values: BTreeMap<CityId, T>) -> BTreeMap<String, T> {
+        self.geography
+            .cities()
+            .into_iter()
+            .filter_map(|city| Some((city.name().clone(), values.remove(&city.id())?)))
+            .collect()
+    }
+
+    /// Aggregated curves of the groups of the default commodity, as split
+    /// in the last update of prices. Curves reflect the current demands and
+    /// supplies, so they differ from the solved ones if anything changed
+    /// since then. Monopolies are listed too, although they are solved on
+    /// their own.
+    pub fn group_equilibria(&self) -> Vec<GroupEquilibrium> {
+        let groups = match self.last_groups.get(&Commodity::default()) {
+            Some(groups) => groups,
+            None => return vec![],
+        };
+        groups
+            .values()
+            .filter(|group| !group.is_empty())
+            .map(|group| {
+                let (demand, supply) = Market::aggregate_group(self.cities(), group);
+                let state = demand.intersect(&supply);
+                GroupEquilibrium {
+                    cities: group.clone(),
+                    demand,
+                    supply,
+                    state,
                 }
-                (MarketState::UnderSupply, MarketState::OverSupply) => (Price::max(), Price::min()),
-                (MarketState::OverSupply, MarketState::UnderSupply) => (Price::min(), Price::max()),
-                _ => (Price::new(0.), Price::new(0.)),
-            };
+            })
+            .collect()
+    }
 
-            if (price_from - price_to).abs() >= cost {
-                self.calculate_groups_dfs(
-                    id_to,
-                    group_id,
-                    group_diff + cost * (if price_to > price_from { 1. } else { -1. }),
-                    groups,
-                )
+    /// Aggregate demand minus aggregate supply of the default commodity in
+    /// the price group based in given city, in prices of that city. Groups
+    /// are split as in the last update of prices, so the zero of the curve
+    /// is the equilibrium the solver looked for, and a negative slope there
+    /// means the equilibrium is stable. `None` if the city isn't a group base.
+    pub fn excess_demand_curve(&self, group_base: CityId) -> Option<FunctionNullable> {
+        self.commodity_excess_demand_curve(Commodity::default(), group_base)
+    }
+
+    /// Same as `excess_demand_curve`, for given commodity.
+    pub fn commodity_excess_demand_curve(
+        &self,
+        commodity: Commodity,
+        group_base: CityId,
+    ) -> Option<FunctionNullable> {
+        let group = self
+            .last_groups
+            .get(&commodity)?
+            .get(&group_base)
+            .filter(|group| !group.is_empty())?;
+        let (demand, supply) = Market::aggregate_group(self.commodity_cities(commodity)?, group);
+        let mut excess = demand.function().clone();
+        excess.substract_function(supply.function());
+        Some(excess)
+    }
+
+    /// Sum of demands of given cities. If all of them belong to one price
+    /// group, demands are expressed in prices of the first listed city,
+    /// shifted by the price differences within the group. Otherwise they are
+    /// summed as they are. `None` if any of the cities doesn't exist.
+    pub fn region_demand(&self, cities: &[CityId]) -> Option<Demand> {
+        let groups = self.calculate_groups(self.cities());
+        let group = groups
+            .values()
+            .find(|group| cities.iter().all(|x| group.iter().any(|(id, _)| id == x)));
+        let diffs: Vec<Price> = match (group, cities.first()) {
+            (Some(group), Some(first)) => {
+                let diff = |city: &CityId| group.iter().find(|(id, _)| id == city).unwrap().1;
+                cities.iter().map(|x| diff(x) - diff(first)).collect()
             }
+            _ => vec![Price::zero(); cities.len()],
+        };
+
+        let mut demand = Demand::zero();
+        for (city, diff) in cities.iter().zip(diffs) {
+            let city_demand = self.city_data(*city)?.demand().shifted_left(diff);
+            demand.add_function(&city_demand);
         }
+        Some(demand)
     }
 
-    fn calculate_groups(&self) -> BTreeMap<CityId, Vec<(CityId, Price)>> {
-        // Map id -> (group_id, price_compared_to_groups_base).
-        let mut groups: BTreeMap<CityId, (CityId, Price)> = BTreeMap::new();
-        for entry in &self.cities {
-            let i = entry.key();
-            self.calculate_groups_dfs(*i, *i, Price::new(0.), &mut groups);
+    /// Sum of supplies of all cities, ignoring transport costs and taxes.
+    /// Describes the market only if it is a single price group.
+    pub fn total_supply(&self) -> Supply {
+        let mut supply = Supply::zero();
+        for city in self.cities().iter() {
+            supply.add_function(city.supply());
         }
+        supply
+    }
 
-        // Map group_id -> [(id, price_compared_to_groups_base)].
-        let mut group_lists: BTreeMap<CityId, Vec<(CityId, Price)>> =
-            self.cities.iter().map(|x| (*x.key(), vec![])).collect();
-        for city in groups {
-            group_lists
-                .get_mut(&city.1 .0)
-                .unwrap()
-                .push((city.0, city.1 .1));
+    /// Sum of demands of all cities, ignoring transport costs. Describes the
+    /// market only if it is a single price group.
+    pub fn total_demand(&self) -> Demand {
+        let mut demand = Demand::zero();
+        for city in self.cities().iter() {
+            demand.add_function(city.demand());
         }
-        group_lists
+        demand
     }
 
-    fn update_prices(&mut self) {
-        let group_lists = self.calculate_groups();
-
-        group_lists.par_iter().for_each(|group| {
-            let (demand, supply) = group
-                .1
-                .par_iter()
-                .map(|(city_id, price_diff)| {
-                    let city = &self.cities.get(city_id).unwrap();
-                    let mut city_demand = city.demand().clone();
-                    let mut city_supply = city.supply().clone();
-                    city_demand.shift_left(*price_diff);
-                    city_supply.shift_left(*price_diff);
-                    (city_demand, city_supply)
-                })
-                .reduce(
-                    || (Demand::zero(), Supply::zero()),
-                    |(mut demand_1, mut supply_1), (demand_2, supply_2)| {
-                        demand_1.add_function(&demand_2);
-                        supply_1.add_function(&supply_2);
-                        (demand_1, supply_1)
-                    },
-                );
+    /// Volume exported from the city, negative for net importers. Defined
+    /// only for cities in an equilibrium.
+    pub fn net_position(&self, city: CityId) -> Option<Volume> {
+        let city = self.city_data(city)?;
+        Some(city.supply_volume()? - city.demand_volume()?)
+    }
 
-            let state_global = demand.intersect(&supply);
+    /// Volumes shipped in the last turn as `(from, to, volume)`. Goods flow
+    /// within price groups, along connections whose cost equals the price
+    /// difference of their ends. For a group with cycles one spanning tree
+    /// is used, which doesn't change the total cost of transport.
+    pub fn trade_flows(&self) -> Vec<(CityId, CityId, Volume)> {
+        let groups = match self.last_groups.get(&Commodity::default()) {
+            Some(groups) => groups,
+            None => return vec![],
+        };
+        let tol = Price::new(DEFAULT_TOLERANCE);
+
+        let mut flows = vec![];
+        for group in groups.values().filter(|group| group.len() > 1) {
+            let diffs: BTreeMap<CityId, Price> = group.iter().copied().collect();
+
+            // Cities in the order of a breadth-first search with their parents.
+            let mut tree: Vec<(CityId, Option<CityId>)> = vec![(group[0].0, None)];
+            let mut visited: BTreeSet<CityId> = BTreeSet::from([group[0].0]);
+            let mut next = 0;
+            while let Some((pos, _)) = tree.get(next).copied() {
+                next += 1;
+                for conn in self.geography.connections[&pos].iter() {
+                    let id_to = conn.id_to();
+                    let diff = match diffs.get(&id_to) {
+                        Some(diff) if !visited.contains(&id_to) => *diff,
+                        _ => continue,
+                    };
+                    if ((diff - diffs[&pos]).abs() - conn.cost()).abs() <= tol {
+                        visited.insert(id_to);
+                        tree.push((id_to, Some(pos)));
+                    }
+                }
+            }
 
-            for (city_id, price_diff) in group.1 {
-                let mut city_state = self.cities.get_mut(city_id).unwrap();
-                let new_state = match state_global {
-                    MarketState::Equilibrium(price, _, _) => {
-                        let price_local = price + *price_diff;
-                        let demand = city_state.demand().value(price_local);
-                        let supply = city_state.supply().value(price_local);
-                        MarketState::Equilibrium(price_local, demand, supply)
+            // Surplus of every subtree is shipped to its parent.
+            let mut surplus: BTreeMap<CityId, Volume> = group
+                .iter()
+                .map(|(id, _)| (*id, self.net_position(*id).unwrap_or_else(Volume::zero)))
+                .collect();
+            for (city, parent) in tree.iter().rev() {
+                if let Some(parent) = parent {
+                    let volume = surplus[city];
+                    *surplus.get_mut(parent).unwrap() += volume;
+                    if volume > Volume::zero() {
+                        flows.push((*city, *parent, volume));
+                    } else if volume < Volume::zero() {
+                        flows.push((*parent, *city, Volume::zero() - volume));
                     }
-                    state => state,
-                };
-                city_state.set_state(new_state);
+                }
             }
-        });
+        }
+        flows
     }
 
-    pub fn simulate(&mut self, turns: u32) {
-        for _ in 0..turns {
-            self.update_prices();
+    /// Cost of shipping the trade flows of the last turn.
+    pub fn transport_cost(&self) -> Revenue {
+        let mut cost = Revenue::zero();
+        for (from, to, volume) in self.trade_flows() {
+            cost += self.geography.connection_cost(from, to).unwrap() * volume;
         }
+        cost
     }
 
-    #[allow(dead_code)]
-    pub fn reset_prices(&mut self) {
-        self.cities
-            .iter_mut()
-            .for_each(|mut city| city.set_state(MarketState::Undefined));
+    /// Trade flows of the last turn that reach the capacity of their
+    /// connection, as `(from, to, volume)`. Capacities aren't imposed by the
+    /// solver, so the volume may exceed it.
+    pub fn saturated_connections(&self) -> Vec<(CityId, CityId, Volume)> {
+        let tol = Volume::new(DEFAULT_TOLERANCE);
+        self.trade_flows()
+            .into_iter()
+            .filter(|(from, to, volume)| {
+                self.geography
+                    .connection(*from, *to)
+                    .and_then(|x| x.capacity())
+                    .is_some_and(|capacity| *volume + tol >= capacity)
+            })
+            .collect()
     }
-}
 
-#[cfg(test)]
-pub mod tests {
-    use crate::economy::entity::Consumer;
-    use crate::economy::entity::Producer;
-    use crate::economy::function::Demand;
-    use crate::economy::function::Supply;
-    use crate::economy::geography::City;
-    use crate::economy::geography::CityId;
-    use crate::economy::geography::Connection;
-    use crate::economy::geography::Geography;
-    use crate::economy::market::CityData;
-    use crate::economy::market::Market;
-    use crate::economy::market::MarketState;
-    use crate::economy::types::InnerValue;
-    use crate::economy::types::Price;
-    use crate::economy::types::Volume;
-    use crate::util::testing::make_demand;
-    use crate::util::testing::make_supply;
-    use crate::util::testing::test_eq_arg;
-    use crate::util::testing::test_eq_value;
+    /// Volume consumed in the city per inhabitant. Defined only for cities
+    /// in an equilibrium with known, non-zero population.
+    pub fn per_capita_consumption(&self, city: CityId) -> Option<f64> {
+        let volume = self.city_data(city)?.demand_volume();
+        self.per_capita(city, volume)
+    }
 
-    use dashmap::DashMap;
-    use std::collections::BTreeMap;
+    fn per_capita(&self, city: CityId, volume: Option<Volume>) -> Option<f64> {
+        let population = self.geography.cities.get(&city)?.population()?;
+        if population == 0 {
+            return None;
+        }
+        Some(volume?.float() / population as f64)
+    }
 
-    fn generate_cities(
-        geography: &Geography,
+    /// State of every city for each traded commodity, ordered by the
+    /// commodity and then by the id of the city.
+    pub fn snapshot(&self) -> MarketSnapshot {
+        let mut cities: Vec<CitySnapshot> = self
+            .commodities
+            .iter()
+            .flat_map(|(commodity, cities)| {
+                cities.iter().map(|x| CitySnapshot {
+                    id: *x.key(),
+                    commodity: *commodity,
+                    price: x.price(),
+                    demand_volume: x.demand_volume(),
+                    supply_volume: x.supply_volume(),
+                    per_capita_consumption: self.per_capita(*x.key(), x.demand_volume()),
+                    state: *x.state(),
+                })
+            })
+            .collect();
+        cities.sort_unstable_by_key(|x| (x.commodity, x.id));
+        MarketSnapshot { cities }
+    }
+
+    /// Starts recording prices of all cities and the total traded volume
+    /// after every update.
+    pub fn enable_history(&mut self) {
+        if self.price_history.is_none() {
+            self.price_history = Some(vec![]);
+            self.volume_history = Some(vec![]);
+        }
+    }
+
+    pub fn price_history(&self) -> &[BTreeMap<CityId, Option<Price>>] {
+        self.price_history.as_deref().unwrap_or_default()
+    }
+
+    /// Volume traded in all cities in each recorded turn, indexed the same
+    /// as `price_history`. Where a price bound binds, only the smaller of
+    /// demand and supply is traded. Cities without an equilibrium don't
+    /// trade.
+    pub fn volume_history(&self) -> &[Volume] {
+        self.volume_history.as_deref().unwrap_or_default()
+    }
+
+    /// Whether the city was alone in its price group in the last update, so
+    /// that it traded only its own supply and demand.
+    fn is_isolated(&self, commodity: Commodity, city: CityId) -> bool {
+        self.last_groups.get(&commodity).is_none_or(|groups| {
+            !groups
+                .values()
+                .any(|group| group.len() > 1 && group.iter().any(|(id, _)| *id == city))
+        })
+    }
+
+    /// Range of volumes not traded because of the policy imposed in the city,
+    /// `None` at a free market equilibrium. Defined only for a city alone in
+    /// its price group: with trade the reference volume depends on the whole
+    /// group, which isn't solved without the policy.
+    pub fn deadweight_loss_range(&self, city: CityId) -> Option<(Volume, Volume)> {
+        self.commodity_deadweight_loss_range(Commodity::default(), city)
+    }
+
+    /// Same as `deadweight_loss_range`, for given commodity.
+    pub fn commodity_deadweight_loss_range(
+        &self,
+        commodity: Commodity,
+        city: CityId,
+    ) -> Option<(Volume, Volume)> {
+        if !self.is_isolated(commodity, city) {
+            return None;
+        }
+        self.commodity_data(commodity, city)?
+            .deadweight_loss_range()
+    }
+
+    pub fn welfare_report(&self) -> WelfareReport {
+        let mut consumer_surplus = Revenue::zero();
+        let mut producer_surplus = Revenue::zero();
+        let mut tax_revenue = Revenue::zero();
+        let mut deadweight_loss = Revenue::zero();
+
+        for city in self.cities().iter() {
+            if let MarketState::Equilibrium(price, _, volume, price_producer) = *city.state() {
+                consumer_surplus += city.demand().consumer_surplus(price);
+                producer_surplus += city.supply().producer_surplus(price_producer);
+                tax_revenue += city.tax() * volume;
+                if city.has_policy() && self.is_isolated(Commodity::default(), *city.key()) {
+                    deadweight_loss += city.deadweight_loss();
+                }
+            }
+        }
+
+        WelfareReport {
+            consumer_surplus,
+            producer_surplus,
+            tax_revenue,
+            total: consumer_surplus + producer_surplus + tax_revenue,
+            deadweight_loss,
+        }
+    }
+
+    /// Coefficient of variation of prices in cities being in an equilibrium.
+    /// Undefined when less than two cities are solved or their mean price is
+    /// zero.
+    pub fn price_dispersion(&self) -> Option<f64> {
+        let prices: Vec<InnerValue> = self
+            .cities()
+            .iter()
+            .filter_map(|x| x.price())
+            .map(|x| x.float())
+            .collect();
+        if prices.len() < 2 {
+            return None;
+        }
+
+        let count = prices.len() as InnerValue;
+        let mean = prices.iter().sum::<InnerValue>() / count;
+        if mean == 0. {
+            return None;
+        }
+        let variance = prices
+            .iter()
+            .map(|x| (x - mean).powi(2))
+            .sum::<InnerValue>()
+            / count;
+        Some(variance.sqrt() / mean.abs())
+    }
+
+    /// Mean of prices paid by consumers weighted by the volumes they bought.
+    /// As in `traded_volume`, a group sells the smaller of its demand and
+    /// supply, so demand left unmet by a binding price bound is not counted.
+    /// Undefined when nothing is bought.
+    pub fn weighted_average_price(&self) -> Option<Price> {
+        let groups = self.last_groups.get(&Commodity::default())?;
+        let mut spending = Revenue::zero();
+        let mut volume = Volume::zero();
+        for group in groups.values() {
+            let mut group_spending = Revenue::zero();
+            let mut demand = Volume::zero();
+            let mut supply = Volume::zero();
+            for (id, _) in group {
+                if let Some(MarketState::Equilibrium(price, city_demand, city_supply, _)) =
+                    self.city_data(*id).map(|x| *x.state())
+                {
+                    group_spending += price * city_demand;
+                    demand += city_demand;
+                    supply += city_supply;
+                }
+            }
+            if demand == Volume::zero() {
+                continue;
+            }
+            let traded = min(demand, supply);
+            spending += group_spending * (traded.float() / demand.float());
+            volume += traded;
+        }
+        if volume == Volume::zero() {
+            None
+        } else {
+            Some(spending / volume)
+        }
+    }
+
+    /// Connections along which goods flow at current prices, labelled with
+    /// the price difference between their ends. Connections of a city with
+    /// a monopoly are left out, as it doesn't trade.
+    fn trade_links(&self, cities: &DashMap<CityId, CityData>) -> DirectedGraph<CityId, Price> {
+        let mut graph = DirectedGraph::new();
+        for (pos, connections) in &self.geography.connections {
+            graph.add_node(*pos);
+            for conn in connections {
+                let id_from = conn.id_from();
+                let id_to = conn.id_to();
+                let cost = conn.cost();
+
+                if cities.get(&id_from).unwrap().monopoly().is_some()
+                    || cities.get(&id_to).unwrap().monopoly().is_some()
+                {
+                    continue;
+                }
+
+                let (price_from, price_to) = match (
+                    cities.get(&id_from).unwrap().state(),
+                    cities.get(&id_to).unwrap().state(),
+                ) {
+                    (
+                        MarketState::Equilibrium(price_from, _, _, _),
+                        MarketState::Equilibrium(price_to, _, _, _),
+                    ) => (*price_from, *price_to),
+                    (MarketState::OverSupply, MarketState::Equilibrium(price_to, _, _, _)) => {
+                        (Price::min(), *price_to)
+                    }
+                    (MarketState::UnderSupply, MarketState::Equilibrium(price_to, _, _, _)) => {
+                        (Price::max(), *price_to)
+                    }
+                    (MarketState::Equilibrium(price_from, _, _, _), MarketState::OverSupply) => {
+                        (*price_from, Price::min())
+                    }
+                    (MarketState::Equilibrium(price_from, _, _, _), MarketState::UnderSupply) => {
+                        (*price_from, Price::max())
+                    }
+                    (MarketState::UnderSupply, MarketState::OverSupply) => {
+                        (Price::max(), Price::min())
+                    }
+                    (MarketState::OverSupply, MarketState::UnderSupply) => {
+                        (Price::min(), Price::max())
+                    }
+                    _ => (Price::new(0.), Price::new(0.)),
+                };
+
+                if (price_from - price_to).abs() >= cost {
+                    graph.add_edge(
+                        id_from,
+                        id_to,
+                        cost * (if price_to > price_from { 1. } else { -1. }),
+                    );
+                }
+            }
+        }
+        graph
+    }
+
+    fn calculate_groups(
+        &self,
+        cities: &DashMap<CityId, CityData>,
+    ) -> BTreeMap<CityId, Vec<(CityId, Price)>> {
+        // Map id -> (group_id, price_compared_to_groups_base).
+        let links = self.trade_links(cities);
+        let mut groups: BTreeMap<CityId, (CityId, Price)> = BTreeMap::new();
+        for entry in cities {
+            let start = *entry.key();
+            if groups.contains_key(&start) {
+                continue;
+            }
+            for (city, link) in links.dfs_tree(start) {
+                let diff = match link {
+                    Some((parent, cost)) => groups[&parent].1 + *cost,
+                    None => Price::zero(),
+                };
+                groups.insert(city, (start, diff));
+            }
+        }
+
+        // Map group_id -> [(id, price_compared_to_groups_base)].
+        let mut group_lists: BTreeMap<CityId, Vec<(CityId, Price)>> =
+            cities.iter().map(|x| (*x.key(), vec![])).collect();
+        for city in groups {
+            group_lists
+                .get_mut(&city.1 .0)
+                .unwrap()
+                .push((city.0, city.1 .1));
+        }
+        group_lists
+    }
+
+    /// Whether the group was solved last time and none of its cities changed
+    /// since then.
+    fn is_group_clean(
+        &self,
+        commodity: Commodity,
+        cities: &DashMap<CityId, CityData>,
+        group: (&CityId, &Vec<(CityId, Price)>),
+    ) -> bool {
+        self.last_groups
+            .get(&commodity)
+            .and_then(|groups| groups.get(group.0))
+            == Some(group.1)
+            && group
+                .1
+                .iter()
+                .all(|(city_id, _)| !cities.get(city_id).unwrap().dirty)
+    }
+
+    /// Sum of demands and supplies of the group, with prices of every city
+    /// expressed relative to the group base. Curves are evaluated in place
+    /// at the union of their shifted data points instead of being cloned.
+    /// Values are summed sequentially in the order of the group, so the sum
+    /// doesn't depend on thread scheduling and solutions are reproducible.
+    fn aggregate_group<'a>(
+        cities: &'a DashMap<CityId, CityData>,
+        group: &[(CityId, Price)],
+    ) -> (Demand, Supply) {
+        let cities: Vec<(Ref<'a, CityId, CityData>, Price)> = group
+            .iter()
+            .map(|(city_id, price_diff)| (cities.get(city_id).unwrap(), *price_diff))
+            .collect();
+
+        let demand_points: BTreeSet<Price> = cities
+            .iter()
+            .flat_map(|(city, diff)| {
+                city.demand()
+                    .function()
+                    .data_points()
+                    .map(move |x| x - *diff)
+            })
+            .collect();
+        let supply_points: BTreeSet<Price> = cities
+            .iter()
+            .flat_map(|(city, diff)| {
+                let shift = city.tax() - *diff;
+                city.supply()
+                    .function()
+                    .data_points()
+                    .map(move |x| x + shift)
+            })
+            .collect();
+
+        let demand = if demand_points.is_empty() {
+            Demand::zero()
+        } else {
+            Demand::new(demand_points.into_iter().map(|x| {
+                let value = cities
+                    .iter()
+                    .map(|(city, diff)| city.demand().value(x + *diff))
+                    .fold(Volume::zero(), |acc, v| acc + v);
+                (x, value)
+            }))
+        };
+        let supply = if supply_points.is_empty() {
+            Supply::zero()
+        } else {
+            Supply::new(supply_points.into_iter().map(|x| {
+                let value = cities
+                    .iter()
+                    .map(|(city, diff)| city.supply().value(x + *diff - city.tax()))
+                    .fold(Volume::zero(), |acc, v| acc + v);
+                (x, value)
+            }))
+        };
+        (demand, supply)
+    }
+
+    fn update_prices(&mut self) {
+        let mut solved_groups = 0;
+        let mut last_groups = BTreeMap::new();
+        let mut multiple_equilibria = vec![];
+        for (commodity, cities) in &self.commodities {
+            let group_lists = self.calculate_groups(cities);
+            let changed_groups: Vec<(&CityId, &Vec<(CityId, Price)>)> = group_lists
+                .iter()
+                .filter(|group| {
+                    !group.1.is_empty() && !self.is_group_clean(*commodity, cities, *group)
+                })
+                .collect();
+
+            /* clean groups keep their warnings from the last update */
+            multiple_equilibria.extend(
+                self.multiple_equilibria
+                    .iter()
+                    .filter(|(x, base)| {
+                        x == commodity
+                            && !changed_groups
+                                .iter()
+                                .any(|(changed_base, _)| *changed_base == base)
+                    })
+                    .copied(),
+            );
+            let ambiguous: Vec<CityId> = changed_groups
+                .par_iter()
+                .filter(|group| {
+                    Market::solve_group(
+                        cities,
+                        group.1,
+                        self.solver.as_ref(),
+                        self.price_search_bounds,
+                    )
+                })
+                .map(|group| *group.0)
+                .collect();
+            multiple_equilibria.extend(ambiguous.into_iter().map(|base| (*commodity, base)));
+
+            solved_groups += changed_groups.len();
+            cities.iter_mut().for_each(|mut city| city.dirty = false);
+            last_groups.insert(*commodity, group_lists);
+        }
+        self.solved_groups = solved_groups;
+        self.last_groups = last_groups;
+        multiple_equilibria.sort_unstable();
+        self.multiple_equilibria = multiple_equilibria;
+
+        let prices = self.prices();
+        if let Some(history) = self.price_history.as_mut() {
+            history.push(prices);
+        }
+        let volume = self.traded_volume();
+        if let Some(history) = self.volume_history.as_mut() {
+            history.push(volume);
+        }
+    }
+
+    /// Volume of the default commodity that changed hands in the last update.
+    /// Goods move freely within a price group, so each group trades the
+    /// smaller of its demand and supply; they differ only when a price bound
+    /// binds and the excess is left unsold.
+    fn traded_volume(&self) -> Volume {
+        let groups = match self.last_groups.get(&Commodity::default()) {
+            Some(groups) => groups,
+            None => return Volume::zero(),
+        };
+        let mut traded = Volume::zero();
+        for group in groups.values() {
+            let mut demand = Volume::zero();
+            let mut supply = Volume::zero();
+            for (id, _) in group {
+                if let Some(city) = self.city_data(*id) {
+                    demand += city.demand_volume().unwrap_or_else(Volume::zero);
+                    supply += city.supply_volume().unwrap_or_else(Volume::zero);
+                }
+            }
+            traded += min(demand, supply);
+        }
+        traded
+    }
+
+    /// Solves the group and sets the states of its cities. Returns whether
+    /// the aggregated curves crossed more than once.
+    fn solve_group(
+        cities: &DashMap<CityId, CityData>,
+        group: &[(CityId, Price)],
+        solver: &dyn SolverStrategy,
+        bounds: Option<(Price, Price)>,
+    ) -> bool {
+        if let [(city_id, _)] = group {
+            let monopoly_state = {
+                let city = cities.get(city_id).unwrap();
+                city.monopoly().map(|costs| city.monopoly_state(costs))
+            };
+            if let Some(state) = monopoly_state {
+                cities.get_mut(city_id).unwrap().set_state(state);
+                return false;
+            }
+        }
+
+        let (demand, supply) = Market::aggregate_group(cities, group);
+        let crossings = demand.intersections(&supply);
+        let ambiguous = crossings.len() > 1;
+
+        let start = group.iter().find_map(|(city_id, price_diff)| {
+            cities
+                .get(city_id)
+                .unwrap()
+                .price()
+                .map(|x| x - *price_diff)
+        });
+        let state_global = solver.solve(&demand, &supply, start, &crossings);
+
+        for (city_id, price_diff) in group {
+            let mut city_state = cities.get_mut(city_id).unwrap();
+            let new_state = match state_global {
+                MarketState::Equilibrium(price, _, _, _) => {
+                    let price = match bounds {
+                        Some((min, max)) => price.clamp(min, max),
+                        None => price,
+                    };
+                    let price_local = city_state.bound_price(price + *price_diff);
+                    let price_producer = price_local - city_state.tax();
+                    let demand = city_state.demand().value(price_local);
+                    let supply = city_state.supply().value(price_producer);
+                    MarketState::Equilibrium(price_local, demand, supply, price_producer)
+                }
+                state => state,
+            };
+            city_state.set_state(new_state);
+        }
+        ambiguous
+    }
+
+    pub fn simulate(&mut self, turns: u32) {
+        for _ in 0..turns {
+            self.update_prices();
+        }
+    }
+
+    pub fn reset_prices(&mut self) {
+        self.commodities
+            .values()
+            .flat_map(|cities| cities.iter_mut())
+            .for_each(|mut city| city.reset_state(MarketState::Undefined));
+    }
+
+    /// Sets listed cities to an equilibrium at given prices of the default
+    /// commodity, with no volume traded yet. Remaining cities are left
+    /// without a price.
+    pub fn reset_to(&mut self, prices: BTreeMap<CityId, Price>) {
+        self.cities().iter_mut().for_each(|mut city| {
+            let state = prices
+                .get(city.key())
+                .map(|x| MarketState::Equilibrium(*x, Volume::zero(), Volume::zero(), *x))
+                .unwrap_or(MarketState::Undefined);
+            city.reset_state(state)
+        });
+    }
+
+    /// Sets cities to states saved in the snapshot. Cities missing in the
+    /// snapshot, or of commodities nobody trades yet, are left as they are.
+    pub fn restore(&mut self, snapshot: &MarketSnapshot) {
+        for city in &snapshot.cities {
+            let data = self
+                .commodity_cities(city.commodity)
+                .and_then(|cities| cities.get_mut(&city.id));
+            if let Some(mut data) = data {
+                data.reset_state(city.state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::economy::commodity::Commodity;
+    use crate::economy::entity::Consumer;
+    use crate::economy::entity::Producer;
+    use crate::economy::function::Demand;
+    use crate::economy::function::FunctionAbstract;
+    use crate::economy::function::Supply;
+    use crate::economy::geography::City;
+    use crate::economy::geography::CityId;
+    use crate::economy::geography::Connection;
+    use crate::economy::geography::Geography;
+    use crate::economy::market::CityData;
+    use crate::economy::market::Market;
+    use crate::economy::market::MarketState;
+    use crate::economy::solver::SolverStrategy;
+    use crate::economy::solver::TatonnementSolver;
+    use crate::economy::types::InnerValue;
+    use crate::economy::types::Price;
+    use crate::economy::types::Revenue;
+    use crate::economy::types::Volume;
+    use crate::util::testing::make_demand;
+    use crate::util::testing::make_supply;
+    use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_arg_rel;
+    use crate::util::testing::test_eq_revenue;
+    use crate::util::testing::test_eq_value;
+
+    use dashmap::DashMap;
+    use std::collections::BTreeMap;
+
+    fn generate_cities(
+        geography: &Geography,
         prices_vec: Vec<(CityId, InnerValue)>,
     ) -> DashMap<CityId, CityData> {
         let prices: BTreeMap<CityId, InnerValue> = prices_vec.into_iter().collect();
@@ -341,25 +1493,54 @@ pub mod tests {
             .cities
             .iter()
             .map(|x| {
-                let demand = Demand::zero();
-                let supply = Supply::zero();
-                let state = MarketState::Equilibrium(
-                    Price::new(prices[&x.0]),
+                let mut data = CityData::new();
+                data.set_state(MarketState::Equilibrium(
+                    Price::new(prices[x.0]),
                     Volume::zero(),
                     Volume::zero(),
-                );
-                (
-                    *x.0,
-                    CityData {
-                        demand,
-                        supply,
-                        state,
-                    },
-                )
+                    Price::new(prices[x.0]),
+                ));
+                (*x.0, data)
             })
             .collect()
     }
 
+    fn three_node_market() -> Market {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city 0".to_string()));
+        geography.add_city(City::new(1, "city 1".to_string()));
+        geography.add_city(City::new(2, "city 2".to_string()));
+        geography.add_connection(Connection::new(0, 1, Price::new(2.)));
+        geography.add_connection(Connection::new(1, 2, Price::new(1.)));
+
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&Consumer::new(
+            0,
+            make_demand(vec![(0., 8.), (1., 7.), (3., 3.), (5., 1.)]),
+        ));
+        market.add_producer(&Producer::new(
+            0,
+            make_supply(vec![(0., 2.), (1., 3.), (3., 7.), (5., 8.)]),
+        ));
+        market.add_consumer(&Consumer::new(
+            1,
+            make_demand(vec![(3., 8.), (4., 6.), (5., 3.), (7., 2.)]),
+        ));
+        market.add_producer(&Producer::new(
+            1,
+            make_supply(vec![(2., 1.), (4., 3.), (5., 5.), (6., 6.)]),
+        ));
+        market.add_consumer(&Consumer::new(
+            2,
+            make_demand(vec![(5., 6.), (6., 5.), (7., 3.), (9., 1.)]),
+        ));
+        market.add_producer(&Producer::new(
+            2,
+            make_supply(vec![(3., 1.), (6., 3.), (8., 5.), (10., 6.)]),
+        ));
+        market
+    }
+
     #[cfg(test)]
     pub mod groups {
         use super::*;
@@ -392,6 +1573,16 @@ pub mod tests {
             }
         }
 
+        #[test]
+        pub fn no_nodes() {
+            let mut market = Market::new(Geography::new(), BTreeMap::new());
+            assert!(market.calculate_groups(market.cities()).is_empty());
+            market.simulate(2);
+            assert!(market.prices().is_empty());
+            assert!(market.group_equilibria().is_empty());
+            assert_eq!(market.weighted_average_price(), None);
+        }
+
         #[test]
         pub fn two_nodes_two_groups() {
             let mut geography = Geography::new();
@@ -401,10 +1592,10 @@ pub mod tests {
 
             let cities = generate_cities(&geography, vec![(0, 5.), (1, 7.)]);
 
-            let market = Market { geography, cities };
-            let groups = market.calculate_groups();
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
 
-            assert_eq!(groups.iter().filter(|(_, v)| v.len() != 0).count(), 2);
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 2);
             test_groups(&market, &groups);
         }
 
@@ -417,10 +1608,10 @@ pub mod tests {
 
             let cities = generate_cities(&geography, vec![(0, 5.), (1, 25.)]);
 
-            let market = Market { geography, cities };
-            let groups = market.calculate_groups();
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
 
-            assert_eq!(groups.iter().filter(|(_, v)| v.len() != 0).count(), 1);
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 1);
             test_groups(&market, &groups);
         }
 
@@ -433,10 +1624,10 @@ pub mod tests {
 
             let cities = generate_cities(&geography, vec![(0, 0.), (1, 20.)]);
 
-            let market = Market { geography, cities };
-            let groups = market.calculate_groups();
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
 
-            assert_eq!(groups.iter().filter(|(_, v)| v.len() != 0).count(), 1);
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 1);
             test_groups(&market, &groups);
         }
 
@@ -453,10 +1644,10 @@ pub mod tests {
 
             let cities = generate_cities(&geography, vec![(0, 5.), (1, 25.), (2, 30.)]);
 
-            let market = Market { geography, cities };
-            let groups = market.calculate_groups();
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
 
-            assert_eq!(groups.iter().filter(|(_, v)| v.len() != 0).count(), 2);
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 2);
             test_groups(&market, &groups);
         }
 
@@ -473,10 +1664,10 @@ pub mod tests {
 
             let cities = generate_cities(&geography, vec![(0, 5.), (1, 25.), (2, 45.)]);
 
-            let market = Market { geography, cities };
-            let groups = market.calculate_groups();
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
 
-            assert_eq!(groups.iter().filter(|(_, v)| v.len() != 0).count(), 1);
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 1);
             test_groups(&market, &groups);
         }
 
@@ -502,12 +1693,37 @@ pub mod tests {
                 vec![(0, 5.), (1, 25.), (2, 45.), (3, 20.), (4, 10.)],
             );
 
-            let market = Market { geography, cities };
-            let groups = market.calculate_groups();
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
 
-            assert_eq!(groups.iter().filter(|(_, v)| v.len() != 0).count(), 2);
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 2);
             test_groups(&market, &groups);
         }
+
+        #[test]
+        pub fn long_chain_one_group() {
+            const CITIES_CNT: usize = 100_000;
+
+            let mut geography = Geography::new();
+            for i in 0..CITIES_CNT {
+                geography.add_city(City::new(i, String::new()));
+            }
+            for i in 1..CITIES_CNT {
+                geography.add_connection(Connection::new(i - 1, i, Price::new(1.)));
+            }
+
+            let cities = generate_cities(
+                &geography,
+                (0..CITIES_CNT).map(|i| (i, 2. * i as InnerValue)).collect(),
+            );
+
+            let market = Market::from_cities(geography, cities);
+            let groups = market.calculate_groups(market.cities());
+
+            assert_eq!(groups.iter().filter(|(_, v)| !v.is_empty()).count(), 1);
+            let group = groups.values().find(|v| !v.is_empty()).unwrap();
+            assert_eq!(group.len(), CITIES_CNT);
+        }
     }
 
     #[cfg(test)]
@@ -544,54 +1760,362 @@ pub mod tests {
         }
 
         #[test]
-        fn single_node_2() {
+        fn total_supply_and_demand() {
             let mut geography = Geography::new();
             geography.add_city(City::new(0, "city".to_string()));
-
-            let city_consumption = Consumer::new(0, make_demand(vec![(1., 5.), (5., 0.)]));
-            let city_production =
-                Producer::new(0, make_supply(vec![(0., 0.), (2., 1.), (4., 4.), (6., 6.)]));
-
             let mut market = Market::new(geography, BTreeMap::new());
-            market.add_consumer(&city_consumption);
-            market.add_producer(&city_production);
-
-            market.update_prices();
-            let prices = market.prices();
-            let demands = market.demand_volumes();
-            let supplies = market.supply_volumes();
-            test_eq_arg(prices[&0].unwrap(), Price::new(3.));
-            test_eq_value(demands[&0].unwrap(), Volume::new(2.5));
-            test_eq_value(supplies[&0].unwrap(), Volume::new(2.5));
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+            test_eq_value(market.total_demand().value(Price::new(1.)), Volume::new(3.));
+            test_eq_value(market.total_supply().value(Price::new(1.)), Volume::new(1.));
 
-            market.update_prices();
-            let prices = market.prices();
-            let demands = market.demand_volumes();
-            let supplies = market.supply_volumes();
-            test_eq_arg(prices[&0].unwrap(), Price::new(3.));
-            test_eq_value(demands[&0].unwrap(), Volume::new(2.5));
-            test_eq_value(supplies[&0].unwrap(), Volume::new(2.5));
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city 0".to_string()));
+            geography.add_city(City::new(1, "city 1".to_string()));
+            geography.add_connection(Connection::new(0, 1, Price::new(4.)));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(
+                0,
+                make_demand(vec![(0., 6.), (1., 5.), (2., 3.), (3., 2.), (4., 0.)]),
+            ));
+            market.add_producer(&Producer::new(
+                0,
+                make_supply(vec![(1., 0.), (2., 1.), (3., 3.), (5., 4.)]),
+            ));
+            market.add_consumer(&Consumer::new(
+                1,
+                make_demand(vec![(5., 9.), (7., 7.), (8., 4.), (9., 2.), (11., 1.)]),
+            ));
+            market.add_producer(&Producer::new(
+                1,
+                make_supply(vec![(6., 0.), (8., 2.), (9., 5.), (10., 6.)]),
+            ));
+            test_eq_value(
+                market.total_demand().value(Price::new(2.5)),
+                Volume::new(11.5),
+            );
+            test_eq_value(
+                market.total_demand().value(Price::new(8.5)),
+                Volume::new(3.),
+            );
+            test_eq_value(
+                market.total_supply().value(Price::new(5.5)),
+                Volume::new(4.),
+            );
+            test_eq_value(
+                market.total_supply().value(Price::new(8.5)),
+                Volume::new(7.5),
+            );
         }
 
         #[test]
-        fn single_node_3() {
+        fn two_commodities() {
             let mut geography = Geography::new();
-            geography.add_city(City::new(0, "city".to_string()));
+            geography.add_city(City::new(0, "city 0".to_string()));
+            geography.add_city(City::new(1, "city 1".to_string()));
+            geography.add_connection(Connection::new(0, 1, Price::new(1.)));
 
-            let city_consumption = Consumer::new(0, make_demand(vec![(3., 4.), (5., 1.)]));
-            let city_production =
-                Producer::new(0, make_supply(vec![(0., 1.), (2., 2.), (3., 6.), (5., 8.)]));
+            let bread = Commodity(1);
+            let agents = |commodity: Commodity| {
+                let scale = if commodity == bread { 2. } else { 1. };
+                (
+                    vec![
+                        Consumer::new(0, make_demand(vec![(0., 4. * scale), (4. * scale, 0.)]))
+                            .with_commodity(commodity),
+                        Consumer::new(1, make_demand(vec![(0., 8. * scale), (8. * scale, 0.)]))
+                            .with_commodity(commodity),
+                    ],
+                    vec![
+                        Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+                            .with_commodity(commodity),
+                        Producer::new(1, make_supply(vec![(0., 0.), (4., 4.)]))
+                            .with_commodity(commodity),
+                    ],
+                )
+            };
+            let register = |market: &mut Market, commodity: Commodity| {
+                let (consumers, producers) = agents(commodity);
+                consumers.iter().for_each(|x| market.add_consumer(x));
+                producers.iter().for_each(|x| market.add_producer(x));
+            };
 
-            let mut market = Market::new(geography, BTreeMap::new());
-            market.add_consumer(&city_consumption);
-            market.add_producer(&city_production);
+            let mut market = Market::new(geography.clone(), BTreeMap::new());
+            register(&mut market, Commodity::default());
+            register(&mut market, bread);
+            market.simulate(3);
+            assert_eq!(
+                market.commodities().collect::<Vec<_>>(),
+                vec![Commodity::default(), bread]
+            );
+            assert!(market.commodity_prices(Commodity(2)).is_empty());
 
-            market.update_prices();
+            for commodity in [Commodity::default(), bread] {
+                let mut separate = Market::new(geography.clone(), BTreeMap::new());
+                register(&mut separate, commodity);
+                separate.simulate(3);
+
+                let prices = market.commodity_prices(commodity);
+                for (id, price) in separate.commodity_prices(commodity) {
+                    test_eq_arg(prices[&id].unwrap(), price.unwrap());
+                }
+            }
             let prices = market.prices();
-            let demands = market.demand_volumes();
-            let supplies = market.supply_volumes();
             test_eq_arg(prices[&0].unwrap(), Price::new(2.5));
-            test_eq_value(demands[&0].unwrap(), Volume::new(4.));
+            test_eq_arg(prices[&1].unwrap(), Price::new(3.5));
+            let prices = market.commodity_prices(bread);
+            test_eq_arg(prices[&0].unwrap(), Price::new(7.5));
+            test_eq_arg(prices[&1].unwrap(), Price::new(8.5));
+        }
+
+        #[test]
+        fn second_commodity_policies() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            let bread = Commodity(1);
+            for commodity in [Commodity::default(), bread] {
+                market.add_consumer(
+                    &Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]))
+                        .with_commodity(commodity),
+                );
+                market.add_producer(
+                    &Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+                        .with_commodity(commodity),
+                );
+            }
+
+            market.set_commodity_price_bound(bread, 0, Some(Price::new(3.)), None);
+            market.simulate(1);
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+            test_eq_arg(market.commodity_prices(bread)[&0].unwrap(), Price::new(3.));
+            test_eq_value(
+                market.commodity_demand_volumes(bread)[&0].unwrap(),
+                Volume::new(1.),
+            );
+            test_eq_value(
+                market.commodity_supply_volumes(bread)[&0].unwrap(),
+                Volume::new(3.),
+            );
+
+            let snapshot = market.snapshot();
+            assert_eq!(snapshot.cities.len(), 2);
+            test_eq_arg(snapshot.city(0).unwrap().price.unwrap(), Price::new(2.));
+            let city = snapshot.commodity_city(bread, 0).unwrap();
+            test_eq_arg(city.price.unwrap(), Price::new(3.));
+
+            market.set_commodity_price_bound(bread, 0, None, None);
+            market.set_commodity_tax(bread, 0, Price::new(2.));
+            market.apply_commodity_demand_shock(bread, 0, &make_demand(vec![(0., 4.), (4., 0.)]));
+            market.simulate(1);
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+            /* 8 - 2p = p - 2 */
+            test_eq_arg(
+                market.commodity_prices(bread)[&0].unwrap(),
+                Price::new(10. / 3.),
+            );
+
+            market.restore(&snapshot);
+            assert!(market
+                .commodity_data(bread, 0)
+                .unwrap()
+                .state()
+                .is_equilibrium());
+            test_eq_arg(market.commodity_prices(bread)[&0].unwrap(), Price::new(3.));
+        }
+
+        #[test]
+        fn single_node_multiple_equilibria() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_demand(0, &make_demand(vec![(0., 6.), (8., 2.)]));
+            market.add_supply(0, &make_supply(vec![(0., 0.), (4., 8.)]));
+            market.update_prices();
+            assert!(market.multiple_equilibria().is_empty());
+
+            /* supply bending backwards above the price of 4 */
+            market.add_supply(0, &make_supply(vec![(4., 0.), (8., -8.)]));
+            market.update_prices();
+            assert_eq!(market.multiple_equilibria(), vec![0]);
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.4));
+
+            /* the group isn't solved again, but the warning stays */
+            market.update_prices();
+            assert_eq!(market.solved_groups, 0);
+            assert_eq!(market.multiple_equilibria(), vec![0]);
+        }
+
+        #[test]
+        fn single_node_supply_shock() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+            market.simulate(2);
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+
+            market.apply_supply_shock(0, &make_supply(vec![(0., -1.), (4., -1.)]));
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.5));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(1.5));
+
+            market.apply_demand_shock(0, &make_demand(vec![(0., 1.), (4., 1.)]));
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(3.));
+        }
+
+        #[test]
+        fn region_demand() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city 0".to_string()));
+            geography.add_city(City::new(1, "city 1".to_string()));
+            geography.add_city(City::new(2, "city 2".to_string()));
+            geography.add_connection(Connection::new(0, 1, Price::new(1.)));
+
+            let demand_0 = make_demand(vec![(0., 4.), (4., 0.)]);
+            let demand_1 = make_demand(vec![(0., 8.), (8., 0.)]);
+            let demand_2 = make_demand(vec![(0., 6.), (6., 0.)]);
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_demand(0, &demand_0);
+            market.add_demand(1, &demand_1);
+            market.add_demand(2, &demand_2);
+            market.add_supply(0, &make_supply(vec![(0., 0.), (4., 4.)]));
+            market.add_supply(1, &make_supply(vec![(0., 0.), (4., 4.)]));
+            market.add_supply(2, &make_supply(vec![(0., 0.), (4., 4.)]));
+            market.simulate(3);
+
+            /* cities 0 and 1 trade, so the price in 1 is higher by the cost */
+            let prices = market.prices();
+            let diff = prices[&1].unwrap() - prices[&0].unwrap();
+            test_eq_arg(diff, Price::new(1.));
+            let region = market.region_demand(&[0, 1]).unwrap();
+            for x in [0., 1., 2.5, 5.] {
+                let price = Price::new(x);
+                test_eq_value(
+                    region.value(price),
+                    demand_0.value(price) + demand_1.value(price + diff),
+                );
+            }
+
+            /* cities 0 and 2 don't, so their demands are summed as they are */
+            let region = market.region_demand(&[0, 2]).unwrap();
+            for x in [0., 1., 2.5, 5.] {
+                let price = Price::new(x);
+                test_eq_value(
+                    region.value(price),
+                    demand_0.value(price) + demand_2.value(price),
+                );
+            }
+
+            assert!(market.region_demand(&[0, 7]).is_none());
+            assert!(market.region_demand(&[7]).is_none());
+        }
+
+        #[test]
+        fn per_capita_consumption() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()).with_population(4));
+            geography.add_city(City::new(1, "town".to_string()).with_population(1));
+            geography.add_city(City::new(2, "village".to_string()));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            for city in 0..3 {
+                market.add_consumer(&Consumer::new(city, make_demand(vec![(0., 4.), (4., 0.)])));
+                market.add_producer(&Producer::new(city, make_supply(vec![(0., 0.), (4., 4.)])));
+            }
+            market.update_prices();
+
+            let demands = market.demand_volumes();
+            test_eq_value(demands[&0].unwrap(), demands[&1].unwrap());
+            assert!((market.per_capita_consumption(0).unwrap() - 0.5).abs() < 1e-5);
+            assert!((market.per_capita_consumption(1).unwrap() - 2.).abs() < 1e-5);
+            assert!(market.per_capita_consumption(2).is_none());
+            assert!(market.per_capita_consumption(3).is_none());
+
+            let snapshot = market.snapshot();
+            assert_eq!(
+                snapshot.city(0).unwrap().per_capita_consumption,
+                market.per_capita_consumption(0)
+            );
+        }
+
+        fn single_node_at_scale(scale: InnerValue) -> Price {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_demand(0, &make_demand(vec![(0., 4.), (4. * scale, 0.)]));
+            market.add_supply(0, &make_supply(vec![(0., 0.), (3. * scale, 4.)]));
+            market.update_prices();
+            market.prices()[&0].unwrap()
+        }
+
+        #[test]
+        fn single_node_large_prices() {
+            let scale = 1e12;
+            let price = single_node_at_scale(scale);
+            test_eq_arg_rel(price, Price::new(12. / 7. * scale));
+        }
+
+        #[test]
+        fn single_node_small_prices() {
+            let scale = 1e-9;
+            let price = single_node_at_scale(scale);
+            test_eq_arg_rel(price, Price::new(12. / 7. * scale));
+        }
+
+        #[test]
+        fn single_node_2() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(1., 5.), (5., 0.)]));
+            let city_production =
+                Producer::new(0, make_supply(vec![(0., 0.), (2., 1.), (4., 4.), (6., 6.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+
+            market.update_prices();
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let supplies = market.supply_volumes();
+            test_eq_arg(prices[&0].unwrap(), Price::new(3.));
+            test_eq_value(demands[&0].unwrap(), Volume::new(2.5));
+            test_eq_value(supplies[&0].unwrap(), Volume::new(2.5));
+
+            market.update_prices();
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let supplies = market.supply_volumes();
+            test_eq_arg(prices[&0].unwrap(), Price::new(3.));
+            test_eq_value(demands[&0].unwrap(), Volume::new(2.5));
+            test_eq_value(supplies[&0].unwrap(), Volume::new(2.5));
+            test_eq_revenue(city_production.profit(&market).unwrap(), Revenue::new(2.75));
+        }
+
+        #[test]
+        fn single_node_3() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(3., 4.), (5., 1.)]));
+            let city_production =
+                Producer::new(0, make_supply(vec![(0., 1.), (2., 2.), (3., 6.), (5., 8.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+
+            market.update_prices();
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let supplies = market.supply_volumes();
+            test_eq_arg(prices[&0].unwrap(), Price::new(2.5));
+            test_eq_value(demands[&0].unwrap(), Volume::new(4.));
             test_eq_value(supplies[&0].unwrap(), Volume::new(4.));
 
             market.update_prices();
@@ -603,6 +2127,34 @@ pub mod tests {
             test_eq_value(supplies[&0].unwrap(), Volume::new(4.));
         }
 
+        #[test]
+        fn two_nodes_by_name() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "Gdańsk".to_string()));
+            geography.add_city(City::new(1, "Kraków".to_string()));
+            geography.add_connection(Connection::new(0, 1, Price::new(10.)));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+            market.add_consumer(&Consumer::new(1, make_demand(vec![(0., 6.), (6., 0.)])));
+            market.add_producer(&Producer::new(1, make_supply(vec![(0., 0.), (6., 6.)])));
+            market.update_prices();
+
+            let prices = market.prices_by_name();
+            assert_eq!(prices.len(), 2);
+            test_eq_arg(prices["Gdańsk"].unwrap(), Price::new(2.));
+            test_eq_arg(prices["Kraków"].unwrap(), Price::new(3.));
+            test_eq_value(
+                market.demand_volumes_by_name()["Gdańsk"].unwrap(),
+                Volume::new(2.),
+            );
+            test_eq_value(
+                market.supply_volumes_by_name()["Kraków"].unwrap(),
+                Volume::new(3.),
+            );
+        }
+
         #[test]
         fn two_nodes_1() {
             let mut geography = Geography::new();
@@ -663,6 +2215,37 @@ pub mod tests {
             test_eq_arg(prices[&1].unwrap(), Price::new(7.769230769));
             test_eq_value(demands[&1].unwrap(), Volume::new(4.6923078));
             test_eq_value(supplies[&1].unwrap(), Volume::new(1.7692307));
+
+            let equilibria = market.group_equilibria();
+            assert_eq!(equilibria.len(), 1);
+            let group = &equilibria[0];
+            let price = match group.state {
+                MarketState::Equilibrium(price, _, _, _) => price,
+                _ => panic!("group without an equilibrium"),
+            };
+            test_eq_value(group.demand.value(price), group.supply.value(price));
+            for (city_id, price_diff) in &group.cities {
+                test_eq_arg(price + *price_diff, prices[city_id].unwrap());
+            }
+
+            let export = market.net_position(0).unwrap();
+            let import = market.net_position(1).unwrap();
+            test_eq_value(export, Volume::new(2.9230768));
+            test_eq_value(import, Volume::new(-2.9230771));
+            test_eq_value(export + import, Volume::zero());
+
+            test_eq_arg(
+                market.weighted_average_price().unwrap(),
+                Price::new(
+                    (3.769230769 * 0.46153855 + 7.769230769 * 4.6923078) / (0.46153855 + 4.6923078),
+                ),
+            );
+
+            let flows = market.trade_flows();
+            assert_eq!(flows.len(), 1);
+            assert_eq!((flows[0].0, flows[0].1), (0, 1));
+            test_eq_value(flows[0].2, export);
+            test_eq_revenue(market.transport_cost(), Price::new(4.) * export);
         }
 
         #[test]
@@ -692,10 +2275,8 @@ pub mod tests {
             market_base.add_producer(&city_0_production);
             market_base.add_consumer(&city_1_consumption);
             market_base.add_producer(&city_1_production);
-            let mut market = Market {
-                geography: market_base.geography,
-                cities: market_base.cities,
-            };
+            let cities = market_base.cities().clone();
+            let mut market = Market::from_cities(market_base.geography, cities);
 
             market.update_prices();
             let prices = market.prices();
@@ -779,5 +2360,680 @@ pub mod tests {
             test_eq_arg(price_1, Price::new(4.6249999));
             test_eq_arg(price_2, Price::new(5.6249999));
         }
+
+        #[test]
+        fn single_node_custom_solver() {
+            /// Settles every group at the price of 1.
+            #[derive(Debug)]
+            struct FixedPrice;
+
+            impl SolverStrategy for FixedPrice {
+                fn solve(
+                    &self,
+                    demand: &Demand,
+                    supply: &Supply,
+                    _start: Option<Price>,
+                    _crossings: &[(Price, Volume)],
+                ) -> MarketState {
+                    let price = Price::new(1.);
+                    MarketState::Equilibrium(price, demand.value(price), supply.value(price), price)
+                }
+            }
+
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+
+            market.set_solver(Box::new(FixedPrice));
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(1.));
+            test_eq_value(market.demand_volumes()[&0].unwrap(), Volume::new(3.));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(1.));
+        }
+
+        #[test]
+        fn single_node_tatonnement() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+            market.set_solver(Box::new(TatonnementSolver::new(0.25)));
+
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+            test_eq_value(market.demand_volumes()[&0].unwrap(), Volume::new(2.));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(2.));
+
+            market.reset_to(BTreeMap::from([(0, Price::new(3.5))]));
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(2.));
+        }
+
+        #[test]
+        fn single_node_tatonnement_oscillating() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+            market.set_solver(Box::new(TatonnementSolver::new(1.)));
+
+            /* the price jumps between 3.5 and 0.5 forever */
+            market.reset_to(BTreeMap::from([(0, Price::new(3.5))]));
+            market.update_prices();
+            assert!(market.prices()[&0].is_none());
+        }
+
+        #[test]
+        #[should_panic(expected = "tatonnement rate has to be positive")]
+        fn tatonnement_non_positive_rate() {
+            TatonnementSolver::new(0.);
+        }
+
+        #[test]
+        fn three_node_saturated_connections() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city 0".to_string()));
+            geography.add_city(City::new(1, "city 1".to_string()));
+            geography.add_city(City::new(2, "city 2".to_string()));
+            geography.add_connection(
+                Connection::new(0, 1, Price::new(1.)).with_capacity(Volume::new(2.)),
+            );
+            geography.add_connection(
+                Connection::new(1, 2, Price::new(1.)).with_capacity(Volume::new(100.)),
+            );
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            for id in 0..3 {
+                market.add_consumer(&Consumer::new(id, make_demand(vec![(0., 8.), (8., 0.)])));
+            }
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (1., 12.)])));
+            market.add_producer(&Producer::new(1, make_supply(vec![(4., 0.), (8., 4.)])));
+            market.add_producer(&Producer::new(2, make_supply(vec![(4., 0.), (8., 4.)])));
+            market.simulate(3);
+            assert_eq!(market.trade_flows().len(), 2);
+            let saturated = market.saturated_connections();
+            assert_eq!(saturated.len(), 1);
+            assert_eq!((saturated[0].0, saturated[0].1), (0, 1));
+            test_eq_value(saturated[0].2, Volume::new(6.75));
+        }
+
+        #[test]
+        fn single_node_price_search_bounds() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 5.)])));
+            market.add_producer(&Producer::new(
+                0,
+                make_supply(vec![(1e12, 0.), (2e12, 10.)]),
+            ));
+
+            market.update_prices();
+            assert!(market.prices()[&0].unwrap() > Price::new(1e12));
+
+            market.set_price_search_bounds(Price::zero(), Price::new(100.));
+            market.update_prices();
+            test_eq_arg(market.prices()[&0].unwrap(), Price::new(100.));
+            test_eq_value(market.demand_volumes()[&0].unwrap(), Volume::new(5.));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::zero());
+        }
+
+        #[test]
+        #[should_panic(expected = "price search bounds are reversed")]
+        fn single_node_price_search_bounds_reversed() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.set_price_search_bounds(Price::new(100.), Price::zero());
+        }
+
+        #[test]
+        fn single_node_price_floor() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+            market.set_price_bound(0, Some(Price::new(3.)), None);
+
+            market.update_prices();
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let supplies = market.supply_volumes();
+            test_eq_arg(prices[&0].unwrap(), Price::new(3.));
+            test_eq_value(demands[&0].unwrap(), Volume::new(1.));
+            test_eq_value(supplies[&0].unwrap(), Volume::new(3.));
+            test_eq_value(
+                market.city_data(0).unwrap().excess_supply().unwrap(),
+                Volume::new(2.),
+            );
+        }
+
+        #[test]
+        fn single_node_excess_supply() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "glut".to_string()));
+            geography.add_city(City::new(1, "shortage".to_string()));
+            geography.add_city(City::new(2, "ceiling".to_string()));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            assert_eq!(market.city_data(0).unwrap().excess_supply(), None);
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 1.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 3.), (4., 4.)])));
+            market.add_consumer(&Consumer::new(1, make_demand(vec![(0., 6.), (4., 5.)])));
+            market.add_producer(&Producer::new(1, make_supply(vec![(0., 1.), (4., 2.)])));
+            market.add_consumer(&Consumer::new(2, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(2, make_supply(vec![(0., 0.), (4., 4.)])));
+            market.set_price_bound(2, None, Some(Price::new(1.)));
+
+            market.update_prices();
+            let excess = |city| market.city_data(city).unwrap().excess_supply().unwrap();
+            assert!(market.city_data(0).unwrap().state().is_glut());
+            test_eq_value(excess(0), Volume::new(2.));
+            assert!(market.city_data(1).unwrap().state().is_shortage());
+            test_eq_value(excess(1), Volume::new(-3.));
+            test_eq_value(excess(2), Volume::new(-2.));
+        }
+
+        #[test]
+        fn single_node_price_ceiling() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+            market.set_price_bound(0, Some(Price::new(0.5)), Some(Price::new(1.)));
+
+            market.update_prices();
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let supplies = market.supply_volumes();
+            test_eq_arg(prices[&0].unwrap(), Price::new(1.));
+            test_eq_value(demands[&0].unwrap(), Volume::new(3.));
+            test_eq_value(supplies[&0].unwrap(), Volume::new(1.));
+        }
+
+        #[test]
+        fn two_nodes_weighted_average_price_ceiling() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city 0".to_string()));
+            geography.add_city(City::new(1, "city 1".to_string()));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            for id in 0..2 {
+                market.add_consumer(&Consumer::new(id, make_demand(vec![(0., 4.), (4., 0.)])));
+                market.add_producer(&Producer::new(id, make_supply(vec![(0., 0.), (4., 4.)])));
+            }
+            market.set_price_bound(0, None, Some(Price::new(1.)));
+
+            /* city 0 only sells 1 of the 3 units demanded at the ceiling */
+            market.update_prices();
+            test_eq_arg(
+                market.weighted_average_price().unwrap(),
+                Price::new((1. * 1. + 2. * 2.) / 3.),
+            );
+        }
+
+        #[test]
+        fn single_node_price_bound_not_binding() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+            market.set_price_bound(0, Some(Price::new(1.)), Some(Price::new(3.)));
+
+            market.update_prices();
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let supplies = market.supply_volumes();
+            test_eq_arg(prices[&0].unwrap(), Price::new(2.));
+            test_eq_value(demands[&0].unwrap(), Volume::new(2.));
+            test_eq_value(supplies[&0].unwrap(), Volume::new(2.));
+        }
+
+        #[test]
+        fn single_node_tax() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+
+            market.update_prices();
+            let volume_untaxed = market.demand_volumes()[&0].unwrap();
+            test_eq_value(volume_untaxed, Volume::new(2.));
+
+            market.set_tax(0, Price::new(1.));
+            market.update_prices();
+            let city = market.cities().get(&0).unwrap();
+            test_eq_arg(city.price().unwrap(), Price::new(2.5));
+            test_eq_arg(city.producer_price().unwrap(), Price::new(1.5));
+            test_eq_value(city.demand_volume().unwrap(), Volume::new(1.5));
+            test_eq_value(city.supply_volume().unwrap(), Volume::new(1.5));
+            assert!(city.demand_volume().unwrap() < volume_untaxed);
+
+            let (demand, supply) = city.volumes_at(city.price().unwrap());
+            test_eq_value(demand, city.demand_volume().unwrap());
+            test_eq_value(supply, city.supply_volume().unwrap());
+            let (demand, supply) = city.volumes_at(Price::new(3.));
+            test_eq_value(demand, Volume::new(1.));
+            test_eq_value(supply, Volume::new(2.));
+        }
+
+        #[test]
+        fn single_node_subsidy() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+
+            market.update_prices();
+            let volume_free = market.demand_volumes()[&0].unwrap();
+            let price_free = market.prices()[&0].unwrap();
+
+            market.set_subsidy(0, Price::new(1.));
+            market.update_prices();
+            let city = market.cities().get(&0).unwrap();
+            test_eq_arg(city.price().unwrap(), Price::new(1.5));
+            test_eq_arg(city.producer_price().unwrap(), Price::new(2.5));
+            test_eq_arg(city.subsidy(), Price::new(1.));
+            test_eq_value(city.demand_volume().unwrap(), Volume::new(2.5));
+            test_eq_value(city.supply_volume().unwrap(), Volume::new(2.5));
+            assert!(city.supply_volume().unwrap() > volume_free);
+            assert!(city.price().unwrap() < price_free);
+            test_eq_revenue(
+                city.subsidy_cost().unwrap(),
+                city.subsidy() * city.supply_volume().unwrap(),
+            );
+            test_eq_revenue(city.subsidy_cost().unwrap(), Revenue::new(2.5));
+        }
+
+        #[test]
+        fn three_node_history() {
+            let mut market = three_node_market();
+            market.update_prices();
+            assert!(market.price_history().is_empty());
+
+            market.enable_history();
+            market.simulate(3);
+            let history = market.price_history();
+            assert_eq!(history.len(), 3);
+            test_eq_arg(history[0][&0].unwrap(), Price::new(2.6249999));
+            test_eq_arg(history[2][&2].unwrap(), Price::new(5.6249999));
+
+            let volumes = market.volume_history();
+            assert_eq!(volumes.len(), history.len());
+            let traded = market
+                .demand_volumes()
+                .into_values()
+                .map(|x| x.unwrap())
+                .fold(Volume::zero(), |acc, x| acc + x);
+            test_eq_value(volumes[2], traded);
+        }
+
+        #[test]
+        fn single_node_history_price_bounds() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+            market.enable_history();
+
+            market.set_price_bound(0, Some(Price::new(3.)), None);
+            market.update_prices();
+            test_eq_value(market.demand_volumes()[&0].unwrap(), Volume::new(1.));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(3.));
+            test_eq_value(market.volume_history()[0], Volume::new(1.));
+
+            market.set_price_bound(0, None, Some(Price::new(1.)));
+            market.update_prices();
+            test_eq_value(market.demand_volumes()[&0].unwrap(), Volume::new(3.));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), Volume::new(1.));
+            test_eq_value(market.volume_history()[1], Volume::new(1.));
+        }
+
+        #[test]
+        fn single_node_welfare() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+
+            market.update_prices();
+            let untaxed = market.welfare_report();
+            test_eq_revenue(untaxed.consumer_surplus, Revenue::new(2.));
+            test_eq_revenue(untaxed.producer_surplus, Revenue::new(2.));
+            test_eq_revenue(untaxed.tax_revenue, Revenue::zero());
+            test_eq_revenue(untaxed.total, Revenue::new(4.));
+            test_eq_revenue(untaxed.deadweight_loss, Revenue::zero());
+
+            market.set_tax(0, Price::new(1.));
+            market.update_prices();
+            let taxed = market.welfare_report();
+            test_eq_revenue(taxed.consumer_surplus, Revenue::new(1.125));
+            test_eq_revenue(taxed.producer_surplus, Revenue::new(1.125));
+            test_eq_revenue(taxed.tax_revenue, Revenue::new(1.5));
+            test_eq_revenue(taxed.total, Revenue::new(3.75));
+            test_eq_revenue(taxed.deadweight_loss, Revenue::new(0.25));
+            test_eq_revenue(taxed.total + taxed.deadweight_loss, untaxed.total);
+        }
+
+        #[test]
+        fn single_node_welfare_price_floor() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+            market.set_price_bound(0, Some(Price::new(3.)), None);
+
+            market.update_prices();
+            let report = market.welfare_report();
+            test_eq_revenue(report.deadweight_loss, Revenue::new(1.));
+            assert!(market.deadweight_loss_range(0).is_some());
+        }
+
+        #[test]
+        fn two_node_welfare_tax_with_trade() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city 0".to_string()));
+            geography.add_city(City::new(1, "city 1".to_string()));
+            geography.add_connection(Connection::new(0, 1, Price::new(1.)));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 8.), (8., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (8., 8.)])));
+            market.add_consumer(&Consumer::new(1, make_demand(vec![(0., 16.), (16., 0.)])));
+            market.add_producer(&Producer::new(1, make_supply(vec![(0., 0.), (16., 4.)])));
+            market.set_tax(0, Price::new(0.5));
+            market.simulate(5);
+
+            /* city 0 exports, so it trades less than its own curves cross */
+            let prices = market.prices();
+            test_eq_arg(prices[&1].unwrap() - prices[&0].unwrap(), Price::new(1.));
+            assert!(market.net_position(0).unwrap() > Volume::zero());
+            assert!(market
+                .city_data(0)
+                .unwrap()
+                .deadweight_loss_range()
+                .is_some());
+            assert_eq!(market.deadweight_loss_range(0), None);
+            test_eq_revenue(market.welfare_report().deadweight_loss, Revenue::zero());
+        }
+
+        #[test]
+        fn three_node_price_dispersion() {
+            let mut market = three_node_market();
+            assert_eq!(market.price_dispersion(), None);
+
+            market.update_prices();
+            let dispersion_1 = market.price_dispersion().unwrap();
+            market.update_prices();
+            let dispersion_2 = market.price_dispersion().unwrap();
+
+            assert!((dispersion_1 - 0.4318).abs() < 0.001);
+            assert!((dispersion_2 - 0.2906).abs() < 0.001);
+            assert!(dispersion_2 < dispersion_1);
+        }
+
+        #[test]
+        fn single_node_price_dispersion() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])));
+            market.add_producer(&Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+
+            market.update_prices();
+            assert_eq!(market.price_dispersion(), None);
+        }
+
+        #[test]
+        fn single_node_monopoly() {
+            let mut geography = Geography::new();
+            geography.add_city(City::new(0, "city".to_string()));
+
+            let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+            let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+            let mut market = Market::new(geography, BTreeMap::new());
+            market.add_consumer(&city_consumption);
+            market.add_producer(&city_production);
+
+            market.update_prices();
+            let price_competitive = market.prices()[&0].unwrap();
+            let volume_competitive = market.demand_volumes()[&0].unwrap();
+
+            market.set_monopoly(0, city_production.supply());
+            market.update_prices();
+            let price_monopoly = market.prices()[&0].unwrap();
+            let volume_monopoly = market.demand_volumes()[&0].unwrap();
+
+            test_eq_arg(price_monopoly, Price::new(2.666666666));
+            test_eq_value(volume_monopoly, Volume::new(1.333333333));
+            test_eq_value(market.supply_volumes()[&0].unwrap(), volume_monopoly);
+            assert!(price_monopoly > price_competitive);
+            assert!(volume_monopoly < volume_competitive);
+        }
+
+        #[test]
+        fn three_node_city_data() {
+            let mut market = three_node_market();
+            market.update_prices();
+
+            let prices = market.prices();
+            let demands = market.demand_volumes();
+            let ids: Vec<CityId> = market.iter_cities().map(|(id, _)| id).collect();
+            assert_eq!(ids, vec![0, 1, 2]);
+            for (id, data) in market.iter_cities() {
+                assert_eq!(data.price(), prices[&id]);
+                assert_eq!(data.demand_volume(), demands[&id]);
+            }
+
+            assert_eq!(market.city_data(1).unwrap().price(), prices[&1]);
+            assert!(market.city_data(3).is_none());
+        }
+
+        #[test]
+        fn three_node_reset_to() {
+            let mut market = three_node_market();
+            market.simulate(2);
+            let prices_original = market.prices();
+
+            market.reset_to(BTreeMap::new());
+            assert!(market.prices().values().all(|x| x.is_none()));
+            market.simulate(2);
+            for (id, price) in market.prices() {
+                test_eq_arg(price.unwrap(), prices_original[&id].unwrap());
+            }
+
+            market.reset_to(BTreeMap::from([(0, Price::new(1.)), (2, Price::new(3.))]));
+            let prices = market.prices();
+            assert_eq!(prices[&0], Some(Price::new(1.)));
+            assert_eq!(prices[&1], None);
+            assert_eq!(prices[&2], Some(Price::new(3.)));
+            assert_eq!(market.demand_volumes()[&0], Some(Volume::zero()));
+        }
+
+        #[test]
+        fn three_node_unchanged_skipped() {
+            let mut market = three_node_market();
+            market.simulate(3);
+            let snapshot = market.snapshot();
+
+            market.update_prices();
+            assert_eq!(market.solved_groups, 0);
+            for (city, before) in market.snapshot().cities.iter().zip(&snapshot.cities) {
+                assert_eq!(city.price, before.price);
+                assert_eq!(city.demand_volume, before.demand_volume);
+                assert_eq!(city.supply_volume, before.supply_volume);
+            }
+
+            let producer = Producer::new(2, make_supply(vec![(0., 1.), (4., 1.)]));
+            market.add_producer(&producer);
+            market.update_prices();
+            assert!(market.solved_groups > 0);
+            assert!(market.prices()[&2].unwrap() < snapshot.city(2).unwrap().price.unwrap());
+        }
+
+        #[test]
+        fn three_node_reproducible() {
+            let solve = || {
+                let mut market = three_node_market();
+                market.set_tax(1, Price::new(0.5));
+                market.simulate(3);
+                market
+                    .prices()
+                    .into_values()
+                    .map(|x| x.unwrap().float().to_bits())
+                    .collect::<Vec<u64>>()
+            };
+            let expected = solve();
+            for _ in 0..50 {
+                assert_eq!(solve(), expected);
+            }
+        }
+
+        #[test]
+        fn three_node_excess_demand_curve() {
+            let mut market = three_node_market();
+            market.set_tax(1, Price::new(0.5));
+            market.simulate(3);
+            let prices = market.prices();
+            let groups = &market.last_groups[&Commodity::default()];
+            for (base, group) in groups.iter().filter(|(_, group)| !group.is_empty()) {
+                let excess = market.excess_demand_curve(*base).unwrap();
+                let price = prices[base].unwrap();
+                test_eq_value(excess.value(price), Volume::zero());
+                assert!(excess.value(price - Price::new(0.5)) > Volume::zero());
+                assert!(excess.value(price + Price::new(0.5)) < Volume::zero());
+                for (city_id, _) in group.iter().filter(|(city_id, _)| city_id != base) {
+                    assert!(market.excess_demand_curve(*city_id).is_none());
+                }
+            }
+            assert!(market
+                .commodity_excess_demand_curve(Commodity(1), 0)
+                .is_none());
+        }
+
+        /// Aggregation of a group by shifting clones of the curves.
+        fn aggregate_group_cloned(market: &Market, group: &[(CityId, Price)]) -> (Demand, Supply) {
+            let mut demand = Demand::zero();
+            let mut supply = Supply::zero();
+            for (city_id, price_diff) in group {
+                let city = market.cities().get(city_id).unwrap();
+                let city_demand = city.demand().shifted_left(*price_diff);
+                let city_supply = city.supply().shifted_left(*price_diff - city.tax());
+                demand.add_function(&city_demand);
+                supply.add_function(&city_supply);
+            }
+            (demand, supply)
+        }
+
+        #[test]
+        fn three_node_aggregate_matches_cloned() {
+            let mut market = three_node_market();
+            market.set_tax(1, Price::new(0.5));
+            for _ in 0..3 {
+                market.update_prices();
+                for group in market.calculate_groups(market.cities()).values() {
+                    let (demand, supply) = Market::aggregate_group(market.cities(), group);
+                    let (demand_cloned, supply_cloned) = aggregate_group_cloned(&market, group);
+                    for x in (0..100).map(|x| Price::new(x as InnerValue / 10.)) {
+                        test_eq_value(demand.value(x), demand_cloned.value(x));
+                        test_eq_value(supply.value(x), supply_cloned.value(x));
+                    }
+                    match (
+                        demand.intersect(&supply),
+                        demand_cloned.intersect(&supply_cloned),
+                    ) {
+                        (
+                            MarketState::Equilibrium(price, _, _, _),
+                            MarketState::Equilibrium(price_cloned, _, _, _),
+                        ) => test_eq_arg(price, price_cloned),
+                        (MarketState::Equilibrium(..), _) | (_, MarketState::Equilibrium(..)) => {
+                            panic!("only one aggregate has an equilibrium")
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod state {
+        use super::*;
+
+        #[test]
+        fn display() {
+            let equilibrium = MarketState::Equilibrium(
+                Price::new(3.),
+                Volume::new(2.5),
+                Volume::new(2.5),
+                Price::new(3.),
+            );
+            assert_eq!(equilibrium.to_string(), "equilibrium @ 3.00, q=2.50");
+            assert_eq!(MarketState::Undefined.to_string(), "undefined");
+            assert_eq!(MarketState::UnderSupply.to_string(), "undersupply");
+            assert_eq!(MarketState::OverSupply.to_string(), "oversupply");
+        }
+
+        #[test]
+        fn predicates() {
+            let equilibrium = MarketState::Equilibrium(
+                Price::new(1.),
+                Volume::new(1.),
+                Volume::new(1.),
+                Price::new(1.),
+            );
+            assert!(equilibrium.is_equilibrium());
+            assert!(!equilibrium.is_shortage() && !equilibrium.is_glut());
+            assert!(MarketState::UnderSupply.is_shortage());
+            assert!(!MarketState::UnderSupply.is_equilibrium());
+            assert!(MarketState::OverSupply.is_glut());
+            assert!(!MarketState::Undefined.is_equilibrium());
+            assert!(!MarketState::Undefined.is_shortage() && !MarketState::Undefined.is_glut());
+        }
     }
 }
@@ -7,10 +7,22 @@ pub mod types;
 
 pub mod function;
 
+pub mod commodity;
+
 pub mod entity;
 
+pub mod error;
+
 pub mod geography;
 
 pub mod market;
 
+pub mod snapshot;
+
 pub mod simulation;
+
+pub mod solver;
+
+pub mod theme;
+
+pub mod welfare;
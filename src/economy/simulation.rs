@@ -1,59 +1,354 @@
 use std::cmp::{max, min};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::num::NonZeroU32;
 use std::path::Path;
 
+use flate2::read::GzDecoder;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::coord::Shift;
 use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use rayon::prelude::*;
 
 use serde::{Deserialize, Serialize};
 
+use crate::economy::entity::AgentId;
 use crate::economy::entity::Consumer;
+use crate::economy::entity::MarketAgent;
 use crate::economy::entity::Producer;
+use crate::economy::error::SimError;
+use crate::economy::function::Demand;
 use crate::economy::function::FunctionAbstract;
+use crate::economy::function::Supply;
 use crate::economy::geography::City;
 use crate::economy::geography::CityId;
 use crate::economy::geography::Connection;
 use crate::economy::geography::Geography;
+use crate::economy::market::CityData;
 use crate::economy::market::Market;
+use crate::economy::snapshot::MarketSnapshot;
+use crate::economy::snapshot::SimulationResults;
+use crate::economy::theme::PlotConfig;
+use crate::economy::theme::PlotTheme;
 use crate::economy::types::{InnerValue, Volume};
+use crate::util::csv_dir::read_csv_dir;
 
 pub type ArgT = crate::economy::types::Price;
 pub type ValueT = crate::economy::types::Volume;
 
-#[derive(Serialize, Deserialize, Debug)]
+/* plot settings */
+const HEAD_SIZE_Y: u32 = 128;
+const PLOT_SIZE_X: u32 = 1024;
+const PLOT_SIZE_Y: u32 = 768;
+const MARGIN: u32 = 20;
+const LABEL_AREA_SIZE: u32 = 50;
+const TITLE_FONT_SIZE: u32 = 60;
+const CAPTION_FONT_SIZE: u32 = 40;
+const MAX_X_LABELS_CNT: usize = 8;
+const MAX_Y_LABELS_CNT: usize = 6;
+const SERIES_STEPS: InnerValue = 128.0;
+const MIN_PLOT_RANGE: InnerValue = 1e-3;
+const DOTTED_STEPS_VERTICAL: InnerValue = 84.0;
+const DOTTED_STEPS_HORIZONTAL: InnerValue = 112.0;
+const SERIES_WIDTH: u32 = 3;
+const EXCHANGE_WIDTH: u32 = 5;
+const LEGEND_WIDTH: u32 = 2;
+/* number of turns over which the largest price change has to decrease */
+const CONVERGENCE_WINDOW: usize = 8;
+
+const GREY: RGBColor = RGBColor(64, 64, 64);
+const SHADE_OPACITY: f64 = 0.5;
+
+/// Polygon enclosed between supply and demand over given range of volumes,
+/// in the coordinates of the plot. Volumes at which any of the functions
+/// can't be inverted are skipped.
+fn area_between(supply: &Supply, demand: &Demand, from: ValueT, to: ValueT) -> Vec<(f64, f64)> {
+    let volumes: Vec<InnerValue> = (0..=SERIES_STEPS as usize)
+        .map(|i| from.float() + (to.float() - from.float()) * i as InnerValue / SERIES_STEPS)
+        .collect();
+    let left = volumes
+        .iter()
+        .filter_map(|v| Some((supply.function().inverse(ValueT::new(*v))?.float(), *v)));
+    let right = volumes
+        .iter()
+        .rev()
+        .filter_map(|v| Some((demand.function().inverse(ValueT::new(*v))?.float(), *v)));
+    left.chain(right).collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SimulationBuilder {
     turns: usize,
     cities: Vec<City>,
     connections: Vec<Connection>,
     initial_prices: Vec<(CityId, ArgT)>,
+    /// Starting price of cities not listed in `initial_prices`.
+    #[serde(default)]
+    default_price: Option<ArgT>,
     producers: Vec<Producer>,
     consumers: Vec<Consumer>,
 }
 
+impl SimulationBuilder {
+    pub fn new(turns: usize) -> SimulationBuilder {
+        SimulationBuilder {
+            turns,
+            cities: vec![],
+            connections: vec![],
+            initial_prices: vec![],
+            default_price: None,
+            producers: vec![],
+            consumers: vec![],
+        }
+    }
+
+    pub fn set_turns(&mut self, turns: usize) {
+        self.turns = turns;
+    }
+
+    pub fn with_city(mut self, city: City) -> SimulationBuilder {
+        self.cities.push(city);
+        self
+    }
+
+    pub fn with_connection(mut self, connection: Connection) -> SimulationBuilder {
+        self.connections.push(connection);
+        self
+    }
+
+    pub fn with_initial_price(mut self, city: CityId, price: ArgT) -> SimulationBuilder {
+        self.initial_prices.push((city, price));
+        self
+    }
+
+    pub fn with_default_price(mut self, price: ArgT) -> SimulationBuilder {
+        self.default_price = Some(price);
+        self
+    }
+
+    pub fn with_producer(mut self, producer: Producer) -> SimulationBuilder {
+        self.producers.push(producer);
+        self
+    }
+
+    pub fn with_consumer(mut self, consumer: Consumer) -> SimulationBuilder {
+        self.consumers.push(consumer);
+        self
+    }
+
+    /// Checks that every producer, consumer, connection and initial price
+    /// refers to an existing city, and that producers can reach their target
+    /// cities. Reports all problems at once.
+    pub fn validate(&self) -> Result<(), Vec<BuilderError>> {
+        let cities: BTreeSet<CityId> = self.cities.iter().map(|x| x.id()).collect();
+        let mut errors = vec![];
+
+        for (index, producer) in self.producers.iter().enumerate() {
+            for city in producer.cities() {
+                if !cities.contains(&city) {
+                    errors.push(BuilderError::Producer { index, city });
+                }
+            }
+        }
+        for (index, consumer) in self.consumers.iter().enumerate() {
+            if !cities.contains(&consumer.city()) {
+                errors.push(BuilderError::Consumer {
+                    index,
+                    city: consumer.city(),
+                });
+            }
+        }
+        for (index, connection) in self.connections.iter().enumerate() {
+            for city in [connection.id_from(), connection.id_to()] {
+                if !cities.contains(&city) {
+                    errors.push(BuilderError::Connection { index, city });
+                }
+            }
+        }
+        for (city, _) in &self.initial_prices {
+            if !cities.contains(city) {
+                errors.push(BuilderError::InitialPrice { city: *city });
+            }
+        }
+
+        let mut geography = Geography::new();
+        for city in &self.cities {
+            geography.add_city(city.clone());
+        }
+        for connection in &self.connections {
+            if cities.contains(&connection.id_from()) && cities.contains(&connection.id_to()) {
+                geography.add_connection(connection.clone());
+            }
+        }
+        for (index, producer) in self.producers.iter().enumerate() {
+            for city in producer.shares().keys() {
+                let known = cities.contains(city) && cities.contains(&producer.city());
+                if known && geography.shortest_cost(producer.city(), *city).is_none() {
+                    errors.push(BuilderError::Unreachable { index, city: *city });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Reference to a city missing from the scenario, or to a target city
+/// a producer has no route to. Indices point into the corresponding list of
+/// the builder.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BuilderError {
+    Producer { index: usize, city: CityId },
+    Consumer { index: usize, city: CityId },
+    Connection { index: usize, city: CityId },
+    InitialPrice { city: CityId },
+    Unreachable { index: usize, city: CityId },
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuilderError::Producer { index, city } => {
+                write!(f, "producer {} refers to unknown city {}", index, city)
+            }
+            BuilderError::Consumer { index, city } => {
+                write!(f, "consumer {} refers to unknown city {}", index, city)
+            }
+            BuilderError::Connection { index, city } => {
+                write!(f, "connection {} refers to unknown city {}", index, city)
+            }
+            BuilderError::InitialPrice { city } => {
+                write!(f, "initial price given for unknown city {}", city)
+            }
+            BuilderError::Unreachable { index, city } => {
+                write!(f, "producer {} has no route to target city {}", index, city)
+            }
+        }
+    }
+}
+
+/// Outcome of running the simulation until prices settle. Every variant
+/// holds the number of turns executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConvergenceReport {
+    /// No price changed by more than the tolerance in the last turn.
+    Converged(usize),
+    /// The largest price change didn't decrease over a window of turns, so
+    /// prices oscillate or diverge instead of settling.
+    DidNotConverge(usize),
+    /// Prices were still settling when the turn limit was reached.
+    TurnLimit(usize),
+}
+
+impl ConvergenceReport {
+    /// Number of turns it took to converge, or an error if prices didn't
+    /// settle.
+    pub fn into_result(self) -> Result<usize, SimError> {
+        match self {
+            ConvergenceReport::Converged(turns) => Ok(turns),
+            ConvergenceReport::DidNotConverge(turns) | ConvergenceReport::TurnLimit(turns) => {
+                Err(SimError::NonConvergence(turns))
+            }
+        }
+    }
+}
+
+/// Summary of a single turn of the simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct TurnMetrics {
+    /// Largest change of price among cities that had a price before and
+    /// after the turn.
+    pub max_price_change: Option<ArgT>,
+    /// Number of cities that reached equilibrium.
+    pub solved_cities: usize,
+}
+
 #[derive(Debug)]
 pub struct Simulation {
     turns: usize,
+    turn: usize,
+    /// Number of times prices are solved in every turn before agents act.
+    equilibrium_iterations: NonZeroU32,
     pub market: Market,
-    producers: Vec<Producer>,
-    consumers: Vec<Consumer>,
+    agents: Vec<Box<dyn MarketAgent>>,
+    theme: PlotTheme,
+    plot_config: PlotConfig,
+}
+
+/// Errors of drawing depend on the backend, so only their message is kept.
+fn plot_error(error: Box<dyn Error>) -> SimError {
+    SimError::Plot(error.to_string())
 }
 
 impl Simulation {
     fn new(turns: usize, geography: Geography, prices: BTreeMap<CityId, ArgT>) -> Simulation {
         Simulation {
             turns,
+            turn: 0,
+            equilibrium_iterations: NonZeroU32::MIN,
             market: Market::new(geography, prices),
-            producers: vec![],
-            consumers: vec![],
+            agents: vec![],
+            theme: PlotTheme::default(),
+            plot_config: PlotConfig::default(),
+        }
+    }
+
+    /// Reads a scenario from a JSON file, decompressing it first if the
+    /// file has the `.gz` extension.
+    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Simulation, SimError> {
+        let gzipped = path.as_ref().extension().is_some_and(|x| x == "gz");
+        let reader = BufReader::new(File::open(path)?);
+        if gzipped {
+            Simulation::read_from_reader(GzDecoder::new(reader))
+        } else {
+            Simulation::read_from_reader(reader)
         }
     }
 
-    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Simulation, Box<dyn Error>> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let simulation_builder: SimulationBuilder = serde_json::from_reader(reader)?;
+    /// Reads a scenario in JSON format from any source. Hand-written
+    /// scenarios may also contain `//` and `/* */` comments and trailing
+    /// commas; errors are reported as for strict JSON.
+    pub fn read_from_reader<R: Read>(mut reader: R) -> Result<Simulation, SimError> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let simulation_builder: SimulationBuilder = match serde_json::from_str(&input) {
+            Ok(builder) => builder,
+            Err(error) => json5::from_str(&input).map_err(|_| error)?,
+        };
+        Simulation::from_builder(simulation_builder)
+    }
+
+    /// Reads a scenario from a directory of CSV tables, see
+    /// `util::csv_dir` for their layout. Runs for one turn by default.
+    pub fn read_from_csv_dir<P: AsRef<Path>>(path: P) -> Result<Simulation, SimError> {
+        Simulation::from_builder(read_csv_dir(path)?)
+    }
+
+    pub fn from_builder(simulation_builder: SimulationBuilder) -> Result<Simulation, SimError> {
+        simulation_builder
+            .validate()
+            .map_err(SimError::Validation)?;
+
+        let mut prices: BTreeMap<CityId, ArgT> =
+            simulation_builder.initial_prices.into_iter().collect();
+        if let Some(price) = simulation_builder.default_price {
+            for city in &simulation_builder.cities {
+                prices.entry(city.id()).or_insert(price);
+            }
+        }
 
         let mut geography = Geography::new();
         for city in simulation_builder.cities {
@@ -63,351 +358,1681 @@ impl Simulation {
             geography.add_connection(connection);
         }
 
-        let mut simulation = Simulation::new(
-            simulation_builder.turns,
-            geography,
-            simulation_builder.initial_prices.into_iter().collect(),
-        );
+        let mut simulation = Simulation::new(simulation_builder.turns, geography, prices);
         for producer in simulation_builder.producers {
-            simulation.add_producer(producer);
+            simulation.add_agent(Box::new(producer));
         }
         for consumer in simulation_builder.consumers {
-            simulation.add_consumer(consumer);
+            simulation.add_agent(Box::new(consumer));
         }
 
         Ok(simulation)
     }
 
-    fn add_producer(&mut self, producer: Producer) {
-        self.market.add_producer(&producer);
-        self.producers.push(producer)
+    /// Runs a copy of the base scenario for every parameter, modified by
+    /// `apply`, and collects the final states of the market. Scenarios are
+    /// run in parallel, results keep the order of parameters.
+    pub fn sweep<P, I, F>(
+        base: &SimulationBuilder,
+        params: I,
+        apply: F,
+    ) -> Result<Vec<MarketSnapshot>, SimError>
+    where
+        P: Sync,
+        I: Iterator<Item = P>,
+        F: Fn(&mut SimulationBuilder, &P) + Sync,
+    {
+        let params: Vec<P> = params.collect();
+        params
+            .par_iter()
+            .map(|param| {
+                let mut builder = base.clone();
+                apply(&mut builder, param);
+                let mut simulation = Simulation::from_builder(builder)?;
+                simulation.run();
+                Ok(simulation.market.snapshot())
+            })
+            .collect()
+    }
+
+    fn add_agent(&mut self, agent: Box<dyn MarketAgent>) {
+        agent.register(&mut self.market);
+        self.agents.push(agent)
+    }
+
+    /// Producers taking part in the simulation, in the order they were added.
+    /// Agents of all kinds are kept in one list, so producers are picked out
+    /// of it lazily instead of being borrowed as a slice.
+    pub fn producers(&self) -> impl Iterator<Item = &Producer> {
+        self.agents.iter().filter_map(|x| x.as_producer())
+    }
+
+    /// Consumers taking part in the simulation, in the order they were
+    /// added, picked out of the agents as in `producers`.
+    pub fn consumers(&self) -> impl Iterator<Item = &Consumer> {
+        self.agents.iter().filter_map(|x| x.as_consumer())
+    }
+
+    /// Removes the producer with given id from the simulation and the
+    /// market. Returns whether such producer was found.
+    pub fn remove_producer(&mut self, id: AgentId) -> bool {
+        self.remove_agent(|agent| agent.producer_id() == Some(id))
+    }
+
+    /// Removes the consumer with given id from the simulation and the
+    /// market. Returns whether such consumer was found.
+    pub fn remove_consumer(&mut self, id: AgentId) -> bool {
+        self.remove_agent(|agent| agent.consumer_id() == Some(id))
+    }
+
+    fn remove_agent<F: Fn(&dyn MarketAgent) -> bool>(&mut self, matches: F) -> bool {
+        match self.agents.iter().position(|agent| matches(agent.as_ref())) {
+            Some(index) => {
+                self.agents.remove(index).unregister(&mut self.market);
+                true
+            }
+            None => false,
+        }
     }
 
-    fn add_consumer(&mut self, consumer: Consumer) {
-        self.market.add_consumer(&consumer);
-        self.consumers.push(consumer)
+    /// Sets how many times prices are solved in every turn, so that they
+    /// reach a tighter fixed point before agents react to them.
+    pub fn set_equilibrium_iterations(&mut self, iterations: NonZeroU32) {
+        self.equilibrium_iterations = iterations;
+    }
+
+    pub fn equilibrium_iterations(&self) -> NonZeroU32 {
+        self.equilibrium_iterations
     }
 
     fn simulate_turn(&mut self) {
-        self.market.simulate(1);
-        for prod in &mut self.producers {
-            prod.update(&mut self.market)
+        self.market.simulate(self.equilibrium_iterations.get());
+        self.turn += 1;
+        for agent in &mut self.agents {
+            agent.update(&mut self.market, self.turn)
         }
-        for cons in &mut self.consumers {
-            cons.update(&mut self.market)
+    }
+
+    /// Advances the simulation by exactly one turn.
+    pub fn step(&mut self) -> TurnMetrics {
+        let before = self.market.prices();
+        self.simulate_turn();
+        let after = self.market.prices();
+
+        let max_price_change = before
+            .iter()
+            .filter_map(|(id, price)| Some((*price)? - after[id]?))
+            .map(|x| x.abs())
+            .max();
+        let solved_cities = after.values().filter(|x| x.is_some()).count();
+        TurnMetrics {
+            max_price_change,
+            solved_cities,
         }
     }
 
+    pub fn set_theme(&mut self, theme: PlotTheme) {
+        self.theme = theme;
+    }
+
+    pub fn set_plot_config(&mut self, config: PlotConfig) {
+        self.plot_config = config;
+    }
+
+    /// Starts the simulation from the state of the market saved in the
+    /// snapshot, e.g. by a previous run, instead of the initial prices.
+    pub fn with_initial_state(&mut self, snapshot: &MarketSnapshot) {
+        self.market.restore(snapshot);
+    }
+
+    /// Number of turns executed by `run`.
+    pub fn turns(&self) -> usize {
+        self.turns
+    }
+
+    /// Overrides the number of turns given in the scenario.
+    pub fn set_turns(&mut self, turns: usize) {
+        self.turns = turns;
+    }
+
     pub fn run(&mut self) {
         for _ in 0..self.turns {
-            self.simulate_turn();
-        }
-    }
-
-    pub fn plot(&mut self, output_file: &str) -> Result<(), Box<dyn Error>> {
-        /* general settings */
-        const HEAD_SIZE_Y: u32 = 128;
-        const PLOT_SIZE_X: u32 = 1024;
-        const PLOT_SIZE_Y: u32 = 768;
-        const MARGIN: u32 = 20;
-        const LABEL_AREA_SIZE: u32 = 50;
-        const TITLE_FONT_SIZE: u32 = 60;
-        const CAPTION_FONT_SIZE: u32 = 40;
-        const MAX_X_LABELS_CNT: usize = 8;
-        const MAX_Y_LABELS_CNT: usize = 6;
-        const SERIES_STEPS: InnerValue = 128.0;
-        const DOTTED_STEPS_VERTICAL: InnerValue = 84.0;
-        const DOTTED_STEPS_HORIZONTAL: InnerValue = 112.0;
-        const SERIES_WIDTH: u32 = 3;
-        const EXCHANGE_WIDTH: u32 = 5;
-        const LEGEND_WIDTH: u32 = 2;
-        const GREY: RGBColor = RGBColor(64, 64, 64);
-        const GREEN_DARK: RGBColor = RGBColor(0, 176, 0);
-
-        /* number of cities to plot for */
-        let plot_count: u32 = self.market.geography().cities().len() as u32;
+            self.step();
+        }
+    }
 
-        /* root plotting area */
-        let root_area = BitMapBackend::new(
-            output_file,
-            (PLOT_SIZE_X, HEAD_SIZE_Y + PLOT_SIZE_Y * plot_count),
+    /// Runs all turns, calling `hook` with the index of the turn and the
+    /// market after each of them.
+    pub fn run_with<F: FnMut(usize, &Market)>(&mut self, mut hook: F) {
+        for _ in 0..self.turns {
+            self.step();
+            hook(self.turn - 1, &self.market);
+        }
+    }
+
+    /// Runs the simulation until no price changes by `tol` or more, but no
+    /// longer than the configured number of turns. Stops early as well when
+    /// none of the largest price changes in the last `CONVERGENCE_WINDOW`
+    /// turns is smaller than the one just before them.
+    pub fn run_until_stable(&mut self, tol: ArgT) -> ConvergenceReport {
+        let mut changes: Vec<ArgT> = vec![];
+        for turn in 1..=self.turns {
+            let change = match self.step().max_price_change {
+                Some(change) => change,
+                None => continue,
+            };
+            if change < tol {
+                return ConvergenceReport::Converged(turn);
+            }
+
+            changes.push(change);
+            if let Some((before, window)) = changes
+                .len()
+                .checked_sub(CONVERGENCE_WINDOW + 1)
+                .map(|start| changes[start..].split_first().unwrap())
+            {
+                if window.iter().all(|x| x >= before) {
+                    return ConvergenceReport::DidNotConverge(turn);
+                }
+            }
+        }
+        ConvergenceReport::TurnLimit(self.turns)
+    }
+
+    pub fn results(&self) -> SimulationResults {
+        SimulationResults {
+            turns: self.turn,
+            market: self.market.snapshot(),
+        }
+    }
+
+    /// Writes the state of every city as a CSV table, one row for each
+    /// traded commodity. Cities without an equilibrium have empty price and
+    /// volumes.
+    pub fn write_csv(&self, path: &str) -> io::Result<()> {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map(|x| x.to_string()).unwrap_or_default()
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "id,name,price,demand_volume,supply_volume,per_capita_consumption,commodity"
+        )?;
+        for city in self.market.snapshot().cities {
+            let name = self.market.geography().cities[&city.id].name();
+            let name = if name.contains([',', '"', '\n']) {
+                format!("\"{}\"", name.replace('"', "\"\""))
+            } else {
+                name.clone()
+            };
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                city.id,
+                name,
+                optional(city.price.map(|x| x.float())),
+                optional(city.demand_volume.map(|x| x.float())),
+                optional(city.supply_volume.map(|x| x.float())),
+                optional(city.per_capita_consumption),
+                city.commodity.0,
+            )?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the results of the simulation as JSON.
+    pub fn write_json(&self, path: &str) -> Result<(), SimError> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &self.results())?;
+        Ok(())
+    }
+
+    /// Ranges of the axes of the plot of a city, as given in the config or
+    /// derived from its curves. Too narrow ranges are widened.
+    fn plot_ranges(city_data: &CityData, config: &PlotConfig) -> ((ArgT, ArgT), (ValueT, ValueT)) {
+        let (min_x, max_x) = config.x_range.unwrap_or_else(|| {
+            (
+                min(
+                    city_data.supply().function().min_arg(),
+                    city_data.demand().function().min_arg(),
+                ),
+                max(
+                    city_data.supply().function().max_arg(),
+                    city_data.demand().function().max_arg(),
+                ),
+            )
+        });
+        let (min_y, max_y) = config.y_range.unwrap_or_else(|| {
+            (
+                Volume::zero(),
+                max(
+                    city_data.supply().function().max_value(),
+                    city_data.demand().function().max_value(),
+                ) * 1.1,
+            )
+        });
+        (
+            (min_x, max_x.max(min_x + ArgT::new(MIN_PLOT_RANGE))),
+            (min_y, max_y.max(min_y + ValueT::new(MIN_PLOT_RANGE))),
         )
-        .into_drawing_area();
-        root_area.fill(&WHITE)?;
-        let mut root_area =
-            root_area.titled("Supplies & Demands", ("sans-serif", TITLE_FONT_SIZE))?;
+    }
 
-        /* main plotting loop */
-        for city in self.market.geography().cities() {
-            let city_data = self.market.cities().get(&city.id).unwrap();
+    /// Draws supply, demand and the exchange of a single city on given area.
+    /// Takes the market, the theme and the config instead of the whole
+    /// simulation, so that cities can be drawn from many threads.
+    fn plot_city<DB: DrawingBackend>(
+        market: &Market,
+        theme: &PlotTheme,
+        config: &PlotConfig,
+        area: &DrawingArea<DB, Shift>,
+        city: &City,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        DB::ErrorType: 'static,
+    {
+        let city_data = market
+            .commodity_data(config.commodity, city.id)
+            .ok_or_else(|| format!("commodity {} is not traded", config.commodity.0))?;
 
-            /* city specific values */
-            let min_x: ArgT = min(
-                city_data.supply().function().min_arg(),
-                city_data.demand().function().min_arg(),
-            );
-            let max_x: ArgT = max(
-                city_data.supply().function().max_arg(),
-                city_data.demand().function().max_arg(),
-            );
-            let min_y: ValueT = Volume::zero();
-            let max_y: ValueT = max(
-                city_data.supply().function().max_value(),
-                city_data.demand().function().max_value(),
-            ) * 1.1;
-            let exchange_min: Option<ValueT> = city_data
-                .supply_volume()
-                .zip(city_data.demand_volume())
-                .map(|(x, y)| min(x, y));
-            let exchange_max: Option<ValueT> = city_data
-                .supply_volume()
-                .zip(city_data.demand_volume())
-                .map(|(x, y)| max(x, y));
-
-            /* steps for specific plots */
-            let series_step: ArgT = (max_x - min_x) / SERIES_STEPS;
-            let exchange_step: Option<ValueT> = exchange_min
-                .zip(exchange_max)
-                .map(|(x, y)| (y - x) / SERIES_STEPS);
-            let dotted_step_horizontal: ArgT = (max_x - min_x) / DOTTED_STEPS_HORIZONTAL;
-            let dotted_step_vertical: ValueT = (max_y - min_y) / DOTTED_STEPS_VERTICAL;
+        /* city specific values */
+        let ((min_x, max_x), (min_y, max_y)) = Simulation::plot_ranges(&city_data, config);
+        let exchange_min: Option<ValueT> = city_data
+            .supply_volume()
+            .zip(city_data.demand_volume())
+            .map(|(x, y)| min(x, y));
+        let exchange_max: Option<ValueT> = city_data
+            .supply_volume()
+            .zip(city_data.demand_volume())
+            .map(|(x, y)| max(x, y));
 
-            /* acquire plotting area for current city */
-            let (current_area, remaining_area) = root_area.split_vertically(PLOT_SIZE_Y);
-            root_area = remaining_area;
+        /* steps for specific plots */
+        let series_step: ArgT = (max_x - min_x) / SERIES_STEPS;
+        let exchange_step: Option<ValueT> = exchange_min
+            .zip(exchange_max)
+            .map(|(x, y)| (y - x) / SERIES_STEPS);
+        let dotted_step_horizontal: ArgT = (max_x - min_x) / DOTTED_STEPS_HORIZONTAL;
+        let dotted_step_vertical: ValueT = (max_y - min_y) / DOTTED_STEPS_VERTICAL;
 
-            /* ranges for x_axis functions and exchange */
-            let x_axis = (min_x.float()..max_x.float()).step(series_step.float());
-            let exchange_line_vertical = exchange_min
-                .zip(exchange_max)
-                .zip(exchange_step)
-                .map(|((min, max), step)| (min.float()..max.float()).step(step.float()));
-
-            /* plot initialization */
-            let mut chart_builder = ChartBuilder::on(&current_area)
-                .margin(MARGIN)
-                .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA_SIZE)
-                .set_label_area_size(LabelAreaPosition::Right, LABEL_AREA_SIZE)
-                .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA_SIZE)
-                .caption(city.name.clone(), ("sans-serif", CAPTION_FONT_SIZE))
-                .build_cartesian_2d(min_x.float()..max_x.float(), min_y.float()..max_y.float())?;
-
-            /* plot configuration */
-            chart_builder
-                .configure_mesh()
-                .x_desc("Price / Unit")
-                .y_desc("Units")
-                .x_labels(MAX_X_LABELS_CNT)
-                .y_labels(MAX_Y_LABELS_CNT)
-                .x_label_formatter(&|v| format!("{:.2}", v))
-                .y_label_formatter(&|v| format!("{:.2}", v))
-                .draw()?;
-
-            /* marking the initial value of x_axis */
-            chart_builder.draw_series(PointSeries::of_element(
-                vec![(min_x.float(), min_y.float())],
-                0,
-                ShapeStyle::from(&BLACK).filled(),
-                &|coord, size: u32, style| {
-                    EmptyElement::at(coord)
-                        + Circle::new((0, 0), size, style)
-                        + Text::new(format!("{:.2}", min_x.float()), (0, 10), ("sans-serif", 12))
-                },
-            ))?;
-
-            /* marking the initial value of y_axis */
-            chart_builder.draw_series(PointSeries::of_element(
-                vec![(min_x.float(), min_y.float())],
-                0,
-                ShapeStyle::from(&BLACK).filled(),
-                &|coord, size: u32, style| {
-                    EmptyElement::at(coord)
-                        + Circle::new((0, 0), size, style)
-                        + Text::new(
-                            format!("{:.2}", min_y.float()),
-                            (-30, -8),
-                            ("sans-serif", 12),
-                        )
-                },
-            ))?;
-
-            /* drawing the supply function */
+        /* ranges for x_axis functions and exchange */
+        let x_axis = (min_x.float()..max_x.float()).step(series_step.float());
+        let exchange_line_vertical = exchange_min
+            .zip(exchange_max)
+            .zip(exchange_step)
+            .map(|((min, max), step)| (min.float()..max.float()).step(step.float()));
+
+        /* plot initialization */
+        let mut chart_builder = ChartBuilder::on(area)
+            .margin(MARGIN)
+            .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA_SIZE)
+            .set_label_area_size(LabelAreaPosition::Right, LABEL_AREA_SIZE)
+            .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA_SIZE)
+            .caption(
+                city.name.clone(),
+                ("sans-serif", CAPTION_FONT_SIZE)
+                    .into_font()
+                    .color(&theme.text),
+            )
+            .build_cartesian_2d(min_x.float()..max_x.float(), min_y.float()..max_y.float())?;
+
+        /* plot configuration */
+        chart_builder
+            .configure_mesh()
+            .bold_line_style(theme.grid.mix(0.2))
+            .light_line_style(theme.grid.mix(0.1))
+            .axis_style(theme.text)
+            .label_style(("sans-serif", 12).into_font().color(&theme.text))
+            .axis_desc_style(("sans-serif", 12).into_font().color(&theme.text))
+            .x_desc("Price / Unit")
+            .y_desc("Units")
+            .x_labels(MAX_X_LABELS_CNT)
+            .y_labels(MAX_Y_LABELS_CNT)
+            .x_label_formatter(&|v| format!("{:.2}", v))
+            .y_label_formatter(&|v| format!("{:.2}", v))
+            .draw()?;
+
+        /* marking the initial value of x_axis */
+        chart_builder.draw_series(PointSeries::of_element(
+            vec![(min_x.float(), min_y.float())],
+            0,
+            ShapeStyle::from(&theme.text).filled(),
+            &|coord, size: u32, style| {
+                EmptyElement::at(coord)
+                    + Circle::new((0, 0), size, style)
+                    + Text::new(format!("{:.2}", min_x.float()), (0, 10), ("sans-serif", 12))
+            },
+        ))?;
+
+        /* marking the initial value of y_axis */
+        chart_builder.draw_series(PointSeries::of_element(
+            vec![(min_x.float(), min_y.float())],
+            0,
+            ShapeStyle::from(&theme.text).filled(),
+            &|coord, size: u32, style| {
+                EmptyElement::at(coord)
+                    + Circle::new((0, 0), size, style)
+                    + Text::new(
+                        format!("{:.2}", min_y.float()),
+                        (-30, -8),
+                        ("sans-serif", 12),
+                    )
+            },
+        ))?;
+
+        /* shading the deadweight loss */
+        if let Some((from, to)) = market.commodity_deadweight_loss_range(config.commodity, city.id)
+        {
+            let style = theme.deadweight_loss.mix(SHADE_OPACITY).filled();
             chart_builder
-                .draw_series(LineSeries::new(
-                    x_axis
-                        .values()
-                        .map(|x| (x, city_data.supply().value(ArgT::new(x)).float())),
-                    Into::<ShapeStyle>::into(&BLUE)
+                .draw_series(std::iter::once(Polygon::new(
+                    area_between(city_data.supply(), city_data.demand(), from, to),
+                    style,
+                )))?
+                .label("Deadweight loss")
+                .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 25, y + 5)], style));
+        }
+
+        /* drawing the supply function */
+        chart_builder
+            .draw_series(LineSeries::new(
+                x_axis
+                    .values()
+                    .map(|x| (x, city_data.supply().value(ArgT::new(x)).float())),
+                Into::<ShapeStyle>::into(&theme.supply)
+                    .filled()
+                    .stroke_width(SERIES_WIDTH),
+            ))?
+            .label("Supply")
+            .legend(|(x, y)| {
+                PathElement::new(
+                    vec![(x, y), (x + 25, y)],
+                    Into::<ShapeStyle>::into(&theme.supply)
                         .filled()
-                        .stroke_width(SERIES_WIDTH),
-                ))?
-                .label("Supply")
-                .legend(|(x, y)| {
-                    PathElement::new(
-                        vec![(x, y), (x + 25, y)],
-                        Into::<ShapeStyle>::into(&BLUE)
-                            .filled()
-                            .stroke_width(LEGEND_WIDTH),
-                    )
-                });
+                        .stroke_width(LEGEND_WIDTH),
+                )
+            });
+
+        /* drawing the demand function */
+        chart_builder
+            .draw_series(LineSeries::new(
+                x_axis
+                    .values()
+                    .map(|x| (x, city_data.demand().value(ArgT::new(x)).float())),
+                Into::<ShapeStyle>::into(&theme.demand)
+                    .filled()
+                    .stroke_width(SERIES_WIDTH),
+            ))?
+            .label("Demand")
+            .legend(|(x, y)| {
+                PathElement::new(
+                    vec![(x, y), (x + 25, y)],
+                    Into::<ShapeStyle>::into(&theme.demand)
+                        .filled()
+                        .stroke_width(LEGEND_WIDTH),
+                )
+            });
 
-            /* drawing the demand function */
+        /* drawing the exchange */
+        if let Some(ex) = exchange_line_vertical {
             chart_builder
                 .draw_series(LineSeries::new(
-                    x_axis
-                        .values()
-                        .map(|x| (x, city_data.demand().value(ArgT::new(x)).float())),
-                    Into::<ShapeStyle>::into(&RED)
+                    ex.values().map(|y| (min_x.float(), y)),
+                    Into::<ShapeStyle>::into(&theme.exchange)
                         .filled()
-                        .stroke_width(SERIES_WIDTH),
+                        .stroke_width(EXCHANGE_WIDTH),
                 ))?
-                .label("Demand")
+                .label("Exchange")
                 .legend(|(x, y)| {
                     PathElement::new(
                         vec![(x, y), (x + 25, y)],
-                        Into::<ShapeStyle>::into(&RED)
+                        Into::<ShapeStyle>::into(&theme.exchange)
                             .filled()
                             .stroke_width(LEGEND_WIDTH),
                     )
                 });
+        };
 
-            /* drawing the exchange */
-            if let Some(ex) = exchange_line_vertical {
-                chart_builder
-                    .draw_series(LineSeries::new(
-                        ex.values().map(|y| (min_x.float(), y)),
-                        Into::<ShapeStyle>::into(&GREEN_DARK)
-                            .filled()
-                            .stroke_width(EXCHANGE_WIDTH),
-                    ))?
-                    .label("Exchange")
-                    .legend(|(x, y)| {
-                        PathElement::new(
-                            vec![(x, y), (x + 25, y)],
-                            Into::<ShapeStyle>::into(&GREEN_DARK)
-                                .filled()
-                                .stroke_width(LEGEND_WIDTH),
-                        )
-                    });
-            };
+        /* drawing the chart legend */
+        chart_builder
+            .configure_series_labels()
+            .border_style(theme.text)
+            .background_style(theme.background)
+            .label_font(("sans-serif", 12).into_font().color(&theme.text))
+            .draw()?;
 
-            /* drawing the chart legend */
-            chart_builder
-                .configure_series_labels()
-                .border_style(&BLACK)
-                .background_style(&WHITE)
-                .draw()?;
-
-            /* three main interest points of the plot */
-            let intersection: Option<(ArgT, ValueT)> = city_data
-                .supply()
-                .function()
-                .intersect(city_data.demand().function());
-            let local_supply: Option<(ArgT, ValueT)> =
-                city_data.price().zip(city_data.supply_volume());
-            let local_demand: Option<(ArgT, ValueT)> =
-                city_data.price().zip(city_data.demand_volume());
-
-            let mut interest_points: Option<Vec<((ArgT, ValueT), String)>> =
-                local_supply.zip(local_demand).map(|(sup, dem)| {
-                    vec![
-                        (sup, String::from("current supply")),
-                        (dem, String::from("current demand")),
-                    ]
-                });
-            if let Some(..) = intersection {
-                interest_points = interest_points.map(|mut x| {
-                    x.push((intersection.unwrap(), String::from("no exchange")));
-                    x
-                });
-            }
+        /* annotating cities without an equilibrium */
+        if city_data.price().is_none() {
+            chart_builder.draw_series(std::iter::once(Text::new(
+                String::from("no equilibrium"),
+                (
+                    (min_x.float() + max_x.float()) / 2.,
+                    (min_y.float() + max_y.float()) / 2.,
+                ),
+                ("sans-serif", CAPTION_FONT_SIZE)
+                    .into_font()
+                    .color(&GREY)
+                    .pos(Pos::new(HPos::Center, VPos::Center)),
+            )))?;
+        }
 
-            /* loop for marking the interest points on the plot */
-            if let Some(points) = interest_points {
-                for (point, description) in points {
-                    /* ranges for drawing dotted lines between points */
-                    let dotted_line_vertical =
-                        (min_y.float()..point.1.float()).step(dotted_step_vertical.float());
-                    let dotted_line_horizontal =
-                        (min_x.float()..point.0.float()).step(dotted_step_horizontal.float());
+        /* three main interest points of the plot */
+        let intersection: Option<(ArgT, ValueT)> = city_data
+            .supply()
+            .function()
+            .intersect(city_data.demand().function());
+        let local_supply: Option<(ArgT, ValueT)> = city_data.price().zip(city_data.supply_volume());
+        let local_demand: Option<(ArgT, ValueT)> = city_data.price().zip(city_data.demand_volume());
 
-                    /* point on the plot */
-                    chart_builder.draw_series(PointSeries::of_element(
-                        vec![(point.0.float(), point.1.float())],
-                        5,
-                        ShapeStyle::from(&GREY).filled(),
-                        &|coord, size: u32, style| {
-                            EmptyElement::at(coord)
-                                + Circle::new((0, 0), size, style)
-                                + Text::new(description.clone(), (5, -18), ("arial", 20, "Bold"))
-                        },
-                    ))?;
+        let mut interest_points: Option<Vec<((ArgT, ValueT), String)>> =
+            local_supply.zip(local_demand).map(|(sup, dem)| {
+                vec![
+                    (sup, String::from("current supply")),
+                    (dem, String::from("current demand")),
+                ]
+            });
+        if intersection.is_some() {
+            interest_points = interest_points.map(|mut x| {
+                x.push((intersection.unwrap(), String::from("no exchange")));
+                x
+            });
+        }
+
+        /* loop for marking the interest points on the plot */
+        if let Some(points) = interest_points {
+            for (point, description) in points {
+                /* ranges for drawing dotted lines between points */
+                let dotted_line_vertical =
+                    (min_y.float()..point.1.float()).step(dotted_step_vertical.float());
+                let dotted_line_horizontal =
+                    (min_x.float()..point.0.float()).step(dotted_step_horizontal.float());
+
+                /* point on the plot */
+                chart_builder.draw_series(PointSeries::of_element(
+                    vec![(point.0.float(), point.1.float())],
+                    5,
+                    ShapeStyle::from(&GREY).filled(),
+                    &|coord, size: u32, style| {
+                        EmptyElement::at(coord)
+                            + Circle::new((0, 0), size, style)
+                            + Text::new(description.clone(), (5, -18), ("arial", 20, "Bold"))
+                    },
+                ))?;
+
+                /* corresponding point on the x_axis */
+                chart_builder.draw_series(PointSeries::of_element(
+                    vec![(point.0.float(), min_y.float())],
+                    2,
+                    ShapeStyle::from(&GREY).filled(),
+                    &|coord, size: u32, style| {
+                        EmptyElement::at(coord)
+                            + Circle::new((0, 0), size, style)
+                            + Text::new(
+                                format!("{:.2}", point.0.float()),
+                                (5, -16),
+                                ("sans-serif", 18),
+                            )
+                    },
+                ))?;
+
+                /* dotted line connecting plot point and x_axis point */
+                chart_builder.draw_series(PointSeries::of_element(
+                    dotted_line_vertical.values().map(|y| (point.0.float(), y)),
+                    1,
+                    ShapeStyle::from(&GREY).filled(),
+                    &|coord, size: u32, style| {
+                        EmptyElement::at(coord) + Circle::new((0, 0), size, style)
+                    },
+                ))?;
 
-                    /* corresponding point on the x_axis */
+                if description != "no exchange" {
+                    /* corresponding point on the y_axis */
                     chart_builder.draw_series(PointSeries::of_element(
-                        vec![(point.0.float(), min_y.float())],
+                        vec![(min_x.float(), point.1.float())],
                         2,
                         ShapeStyle::from(&GREY).filled(),
                         &|coord, size: u32, style| {
                             EmptyElement::at(coord)
                                 + Circle::new((0, 0), size, style)
                                 + Text::new(
-                                    format!("{:.2}", point.0.float()),
-                                    (5, -16),
+                                    format!("{:.2}", point.1.float()),
+                                    (5, -18),
                                     ("sans-serif", 18),
                                 )
                         },
                     ))?;
 
-                    /* dotted line connecting plot point and x_axis point */
+                    /* dotted line connecting plot point and y_axis point */
                     chart_builder.draw_series(PointSeries::of_element(
-                        dotted_line_vertical.values().map(|y| (point.0.float(), y)),
+                        dotted_line_horizontal
+                            .values()
+                            .map(|x| (x, point.1.float())),
                         1,
                         ShapeStyle::from(&GREY).filled(),
                         &|coord, size: u32, style| {
                             EmptyElement::at(coord) + Circle::new((0, 0), size, style)
                         },
                     ))?;
-
-                    if description != "no exchange" {
-                        /* corresponding point on the y_axis */
-                        chart_builder.draw_series(PointSeries::of_element(
-                            vec![(min_x.float(), point.1.float())],
-                            2,
-                            ShapeStyle::from(&GREY).filled(),
-                            &|coord, size: u32, style| {
-                                EmptyElement::at(coord)
-                                    + Circle::new((0, 0), size, style)
-                                    + Text::new(
-                                        format!("{:.2}", point.1.float()),
-                                        (5, -18),
-                                        ("sans-serif", 18),
-                                    )
-                            },
-                        ))?;
-
-                        /* dotted line connecting plot point and y_axis point */
-                        chart_builder.draw_series(PointSeries::of_element(
-                            dotted_line_horizontal
-                                .values()
-                                .map(|x| (x, point.1.float())),
-                            1,
-                            ShapeStyle::from(&GREY).filled(),
-                            &|coord, size: u32, style| {
-                                EmptyElement::at(coord) + Circle::new((0, 0), size, style)
-                            },
-                        ))?;
-                    }
                 }
             }
         }
 
+        Ok(())
+    }
+
+    fn check_plottable(&self) -> Result<(), SimError> {
+        if self.market.geography().cities().is_empty() {
+            return Err(SimError::Geography(String::from("scenario has no cities")));
+        }
+        Ok(())
+    }
+
+    fn plot_size(&self) -> (u32, u32) {
+        let plot_count: u32 = self.market.geography().cities().len() as u32;
+        (PLOT_SIZE_X, HEAD_SIZE_Y + PLOT_SIZE_Y * plot_count)
+    }
+
+    pub fn plot(&mut self, output_file: &str) -> Result<(), SimError> {
+        self.check_plottable()?;
+        let root_area = BitMapBackend::new(output_file, self.plot_size()).into_drawing_area();
+        self.plot_on(root_area).map_err(plot_error)
+    }
+
+    /// Same plot as `plot`, saved as a vector image.
+    pub fn plot_svg(&mut self, output_file: &str) -> Result<(), SimError> {
+        self.check_plottable()?;
+        let root_area = SVGBackend::new(output_file, self.plot_size()).into_drawing_area();
+        self.plot_on(root_area).map_err(plot_error)
+    }
+
+    /// Same plot as `plot`, returned as PNG encoded bytes instead of being
+    /// written to a file.
+    pub fn render_png(&mut self) -> Result<Vec<u8>, SimError> {
+        self.check_plottable()?;
+        let (width, height) = self.plot_size();
+        let mut pixels = vec![0; (width * height * 3) as usize];
+        {
+            let root_area =
+                BitMapBackend::with_buffer(&mut pixels, (width, height)).into_drawing_area();
+            self.plot_on(root_area).map_err(plot_error)?;
+        }
+
+        let mut bytes = vec![];
+        PngEncoder::new(&mut bytes)
+            .write_image(&pixels, width, height, ColorType::Rgb8)
+            .map_err(|x| SimError::Plot(x.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn plot_on<DB: DrawingBackend>(
+        &self,
+        root_area: DrawingArea<DB, Shift>,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        DB::ErrorType: 'static,
+    {
+        /* root plotting area */
+        root_area.fill(&self.theme.background)?;
+        let mut root_area = root_area.titled(
+            "Supplies & Demands",
+            ("sans-serif", TITLE_FONT_SIZE)
+                .into_font()
+                .color(&self.theme.text),
+        )?;
+
+        /* main plotting loop */
+        for city in self.market.geography().cities() {
+            /* acquire plotting area for current city */
+            let (current_area, remaining_area) = root_area.split_vertically(PLOT_SIZE_Y);
+            root_area = remaining_area;
+
+            Simulation::plot_city(
+                &self.market,
+                &self.theme,
+                &self.plot_config,
+                &current_area,
+                city,
+            )?;
+        }
+
         /* final error check before return */
-        root_area.present().expect(
-            "Unable to save the results. Please make sure that the target
-        directory exists under current directory and that target file has appropriate extension",
-        );
-        println!("Results have been saved to {}", output_file);
+        root_area.present()?;
         Ok(())
     }
+
+    /// Plots every city to a separate file `<dir>/<city id>_<name>.png`.
+    /// Characters of the name that could break the path are replaced with
+    /// underscores. Cities are drawn in parallel.
+    pub fn plot_per_city(&mut self, dir: &str) -> Result<(), SimError> {
+        self.check_plottable()?;
+        let (market, theme, config) = (&self.market, &self.theme, &self.plot_config);
+        market
+            .geography()
+            .cities()
+            .par_iter()
+            .map(|city| {
+                Simulation::plot_city_to_file(market, theme, config, dir, city).map_err(plot_error)
+            })
+            .collect()
+    }
+
+    fn plot_city_to_file(
+        market: &Market,
+        theme: &PlotTheme,
+        config: &PlotConfig,
+        dir: &str,
+        city: &City,
+    ) -> Result<(), Box<dyn Error>> {
+        let name: String = city
+            .name
+            .chars()
+            .map(|x| {
+                if x.is_alphanumeric() || x == '-' {
+                    x
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = Path::new(dir).join(format!("{}_{}.png", city.id, name));
+
+        let area = BitMapBackend::new(&path, (PLOT_SIZE_X, PLOT_SIZE_Y)).into_drawing_area();
+        area.fill(&theme.background)?;
+        Simulation::plot_city(market, theme, config, &area, city)?;
+        area.present()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::Write;
+    use std::num::NonZeroU32;
+    use std::path::Path;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use crate::economy::commodity::Commodity;
+    use crate::economy::entity::Consumer;
+    use crate::economy::entity::MarketAgent;
+    use crate::economy::entity::Producer;
+    use crate::economy::entity::ProducerAlgorithm;
+    use crate::economy::error::SimError;
+    use crate::economy::function::Supply;
+    use crate::economy::geography::City;
+    use crate::economy::geography::Connection;
+    use crate::economy::geography::Geography;
+    use crate::economy::market::Market;
+    use crate::economy::market::MarketState;
+    use crate::economy::simulation::BuilderError;
+    use crate::economy::simulation::ConvergenceReport;
+    use crate::economy::simulation::Simulation;
+    use crate::economy::simulation::SimulationBuilder;
+    use crate::economy::simulation::{HEAD_SIZE_Y, PLOT_SIZE_X, PLOT_SIZE_Y, SHADE_OPACITY};
+    use crate::economy::snapshot::SimulationResults;
+    use crate::economy::theme::PlotConfig;
+    use crate::economy::theme::PlotTheme;
+    use crate::economy::types::Price;
+    use crate::economy::types::Volume;
+    use crate::util::testing::make_demand;
+    use crate::util::testing::make_supply;
+    use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_value;
+
+    #[test]
+    fn plot_over_supply() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "over supply".to_string()));
+        geography.add_city(City::new(1, "empty".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 1.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 2.), (4., 4.)]),
+        )));
+        simulation.run();
+        assert!(matches!(
+            simulation.market.cities().get(&0).unwrap().state(),
+            MarketState::OverSupply
+        ));
+
+        let output = std::env::temp_dir().join("global_market_plot_over_supply.png");
+        simulation.plot(output.to_str().unwrap()).unwrap();
+        assert!(output.is_file());
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn mixed_agents() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let agents: Vec<Box<dyn MarketAgent>> = vec![
+            Box::new(
+                Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]))
+                    .with_seasonality(vec![1., 2.]),
+            ),
+            Box::new(Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))),
+            Box::new(
+                Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]))
+                    .with_algorithm(ProducerAlgorithm::Regular(1.))
+                    .with_production(Volume::new(0.)),
+            ),
+        ];
+        let mut simulation = Simulation::new(2, geography, BTreeMap::new());
+        for agent in agents {
+            simulation.add_agent(agent);
+        }
+
+        simulation.simulate_turn();
+        test_eq_arg(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+
+        // Demand doubles and the regular producer now sells 2.
+        simulation.simulate_turn();
+        test_eq_arg(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+    }
+
+    #[test]
+    fn sweep_turns() {
+        let base = SimulationBuilder::new(1)
+            .with_city(City::new(0, "city".to_string()))
+            .with_consumer(
+                Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]))
+                    .with_seasonality(vec![1., 2.]),
+            )
+            .with_producer(Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])));
+
+        let snapshots =
+            Simulation::sweep(&base, 1..=4, |builder, turns| builder.set_turns(*turns)).unwrap();
+        assert_eq!(snapshots.len(), 4);
+        for (snapshot, expected) in snapshots.iter().zip([2., 8. / 3., 2., 8. / 3.]) {
+            test_eq_arg(
+                snapshot.city(0).unwrap().price.unwrap(),
+                Price::new(expected),
+            );
+        }
+
+        let invalid = Simulation::sweep(&base, 0..2, |builder, city| {
+            *builder = builder
+                .clone()
+                .with_producer(Producer::new(*city, make_supply(vec![(0., 1.)])))
+        });
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn write_csv() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()).with_population(4));
+        geography.add_city(City::new(1, "empty, far".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+        simulation.run();
+
+        let output = std::env::temp_dir().join("global_market_write_csv.csv");
+        simulation.write_csv(output.to_str().unwrap()).unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        let lines: Vec<Vec<&str>> = content.lines().map(|x| x.split(',').collect()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            vec![
+                "id",
+                "name",
+                "price",
+                "demand_volume",
+                "supply_volume",
+                "per_capita_consumption",
+                "commodity"
+            ]
+        );
+        assert_eq!(lines[1][..2], ["0", "city"]);
+        for value in &lines[1][2..5] {
+            assert!((value.parse::<f64>().unwrap() - 2.).abs() < 1e-5);
+        }
+        assert!((lines[1][5].parse::<f64>().unwrap() - 0.5).abs() < 1e-5);
+        assert_eq!(lines[1][6], "0");
+        assert_eq!(
+            lines[2],
+            vec!["1", "\"empty", " far\"", "", "", "", "", "0"]
+        );
+    }
+
+    #[test]
+    fn write_json_round_trip() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        geography.add_city(City::new(1, "empty".to_string()));
+
+        let mut simulation = Simulation::new(3, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+        simulation.run();
+
+        let output = std::env::temp_dir().join("global_market_write_json.json");
+        simulation.write_json(output.to_str().unwrap()).unwrap();
+        let file = std::fs::File::open(&output).unwrap();
+        let results: SimulationResults = serde_json::from_reader(file).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        assert_eq!(results.turns, 3);
+        assert_eq!(results.market.cities.len(), 2);
+        let city = results.market.city(0).unwrap();
+        test_eq_arg(city.price.unwrap(), Price::new(2.));
+        assert!(matches!(city.state, MarketState::Equilibrium(..)));
+        assert!(results.market.city(1).unwrap().price.is_none());
+    }
+
+    #[test]
+    fn remove_producer() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(
+            Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])).with_id(0),
+        ));
+        for id in [1, 2] {
+            simulation.add_agent(Box::new(
+                Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).with_id(id),
+            ));
+        }
+        simulation.run();
+        test_eq_arg(
+            simulation.market.prices()[&0].unwrap(),
+            Price::new(1.333333333),
+        );
+
+        assert!(!simulation.remove_producer(0));
+        assert!(!simulation.remove_consumer(1));
+        assert!(simulation.remove_producer(2));
+        assert!(!simulation.remove_producer(2));
+        simulation.run();
+        test_eq_arg(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+    }
+
+    #[test]
+    fn agents_view() {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("simulation-tests/two-nodes-1.json");
+        let mut simulation = Simulation::read_from_file(input).unwrap();
+        simulation.add_agent(Box::new(
+            Producer::new(1, make_supply(vec![(0., 0.), (4., 4.)])).with_id(7),
+        ));
+        assert_eq!(simulation.consumers().count(), 2);
+        assert_eq!(
+            simulation
+                .producers()
+                .map(|x| x.city())
+                .collect::<Vec<usize>>(),
+            vec![0, 1, 1]
+        );
+
+        assert!(simulation.remove_producer(7));
+        assert_eq!(simulation.producers().count(), 2);
+    }
+
+    #[test]
+    fn set_turns() {
+        let mut simulation = three_node_simulation(3);
+        assert_eq!(simulation.turns(), 3);
+        simulation.set_turns(5);
+        assert_eq!(simulation.turns(), 5);
+
+        let mut executed = 0;
+        simulation.run_with(|_, _| executed += 1);
+        assert_eq!(executed, 5);
+        assert_eq!(simulation.results().turns, 5);
+    }
+
+    fn three_node_simulation(turns: usize) -> Simulation {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city 0".to_string()));
+        geography.add_city(City::new(1, "city 1".to_string()));
+        geography.add_city(City::new(2, "city 2".to_string()));
+        geography.add_connection(Connection::new(0, 1, Price::new(2.)));
+        geography.add_connection(Connection::new(1, 2, Price::new(1.)));
+
+        let mut simulation = Simulation::new(turns, geography, BTreeMap::new());
+        let agents: Vec<Box<dyn MarketAgent>> = vec![
+            Box::new(Consumer::new(
+                0,
+                make_demand(vec![(0., 8.), (1., 7.), (3., 3.), (5., 1.)]),
+            )),
+            Box::new(Producer::new(
+                0,
+                make_supply(vec![(0., 2.), (1., 3.), (3., 7.), (5., 8.)]),
+            )),
+            Box::new(Consumer::new(
+                1,
+                make_demand(vec![(3., 8.), (4., 6.), (5., 3.), (7., 2.)]),
+            )),
+            Box::new(Producer::new(
+                1,
+                make_supply(vec![(2., 1.), (4., 3.), (5., 5.), (6., 6.)]),
+            )),
+            Box::new(Consumer::new(
+                2,
+                make_demand(vec![(5., 6.), (6., 5.), (7., 3.), (9., 1.)]),
+            )),
+            Box::new(Producer::new(
+                2,
+                make_supply(vec![(3., 1.), (6., 3.), (8., 5.), (10., 6.)]),
+            )),
+        ];
+        for agent in agents {
+            simulation.add_agent(agent);
+        }
+        simulation
+    }
+
+    #[test]
+    fn step_three_node() {
+        let mut simulation = three_node_simulation(3);
+
+        let metrics = simulation.step();
+        assert!(metrics.max_price_change.is_none());
+        assert_eq!(metrics.solved_cities, 3);
+
+        let metrics = simulation.step();
+        test_eq_arg(metrics.max_price_change.unwrap(), Price::new(1.041666666));
+        assert_eq!(metrics.solved_cities, 3);
+
+        let metrics = simulation.step();
+        test_eq_arg(metrics.max_price_change.unwrap(), Price::zero());
+        let prices = simulation.market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(2.6249999));
+        test_eq_arg(prices[&1].unwrap(), Price::new(4.6249999));
+        test_eq_arg(prices[&2].unwrap(), Price::new(5.6249999));
+    }
+
+    #[test]
+    fn equilibrium_iterations() {
+        let mut converged = three_node_simulation(3);
+        converged.run();
+        let expected = converged.market.prices();
+
+        let distance = |iterations: u32| {
+            let iterations = NonZeroU32::new(iterations).unwrap();
+            let mut simulation = three_node_simulation(1);
+            simulation.set_equilibrium_iterations(iterations);
+            assert_eq!(simulation.equilibrium_iterations(), iterations);
+            simulation.run();
+            simulation
+                .market
+                .prices()
+                .iter()
+                .map(|(id, price)| (price.unwrap() - expected[id].unwrap()).abs())
+                .max()
+                .unwrap()
+        };
+        let once = distance(1);
+        let twice = distance(2);
+        assert!(once > Price::new(1.));
+        assert!(twice < once);
+        test_eq_arg(twice, Price::zero());
+    }
+
+    #[test]
+    fn warm_start() {
+        let mut cold = three_node_simulation(10);
+        let cold_turns = match cold.run_until_stable(Price::new(1e-6)) {
+            ConvergenceReport::Converged(turns) => turns,
+            report => panic!("unexpected {:?}", report),
+        };
+        let snapshot = cold.market.snapshot();
+
+        let mut warm = three_node_simulation(10);
+        warm.with_initial_state(&snapshot);
+        let warm_turns = match warm.run_until_stable(Price::new(1e-6)) {
+            ConvergenceReport::Converged(turns) => turns,
+            report => panic!("unexpected {:?}", report),
+        };
+        assert!(warm_turns < cold_turns);
+        for (city, before) in warm.market.snapshot().cities.iter().zip(&snapshot.cities) {
+            test_eq_arg(city.price.unwrap(), before.price.unwrap());
+        }
+    }
+
+    #[test]
+    fn run_until_stable_single_node() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let prices = BTreeMap::from([(0, Price::new(2.))]);
+        let mut simulation = Simulation::new(10, geography, prices);
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+
+        assert_eq!(
+            simulation.run_until_stable(Price::new(1e-6)),
+            ConvergenceReport::Converged(1)
+        );
+        test_eq_arg(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+    }
+
+    /// Agent switching between two supplies every turn.
+    #[derive(Debug)]
+    struct Oscillator {
+        supplies: [Supply; 2],
+        turn: usize,
+    }
+
+    impl MarketAgent for Oscillator {
+        fn register(&self, market: &mut Market) {
+            market.add_supply(0, &self.supplies[self.turn % 2]);
+        }
+
+        fn unregister(&self, market: &mut Market) {
+            market.remove_supply(0, &self.supplies[self.turn % 2]);
+        }
+
+        fn update(&mut self, market: &mut Market, turn: usize) {
+            self.unregister(market);
+            self.turn = turn;
+            self.register(market);
+        }
+    }
+
+    #[test]
+    fn run_until_stable_oscillating() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let mut simulation = Simulation::new(100, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Oscillator {
+            supplies: [
+                make_supply(vec![(0., 0.), (4., 4.)]),
+                make_supply(vec![(0., 1.), (4., 5.)]),
+            ],
+            turn: 0,
+        }));
+
+        match simulation.run_until_stable(Price::new(1e-6)) {
+            ConvergenceReport::DidNotConverge(turns) => assert!(turns < 100),
+            report => panic!("unexpected {:?}", report),
+        }
+    }
+
+    #[test]
+    fn run_with_hook() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let mut simulation = Simulation::new(5, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+
+        let mut turns = vec![];
+        simulation.run_with(|turn, market| {
+            turns.push(turn);
+            assert!(market.prices()[&0].is_some());
+        });
+        assert_eq!(turns, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn from_builder_two_cities() {
+        let builder = SimulationBuilder::new(2)
+            .with_city(City::new(0, "first".to_string()))
+            .with_city(City::new(1, "second".to_string()))
+            .with_connection(Connection::new(0, 1, Price::new(1.)))
+            .with_consumer(Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])))
+            .with_producer(Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])))
+            .with_consumer(Consumer::new(1, make_demand(vec![(0., 8.), (8., 0.)])))
+            .with_producer(Producer::new(1, make_supply(vec![(0., 0.), (8., 8.)])));
+        let mut simulation = Simulation::from_builder(builder).unwrap();
+        simulation.run();
+
+        let prices = simulation.market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(2.5));
+        test_eq_arg(prices[&1].unwrap(), Price::new(3.5));
+    }
+
+    #[test]
+    fn from_builder_unknown_city() {
+        let builder = SimulationBuilder::new(1)
+            .with_city(City::new(0, "city".to_string()))
+            .with_consumer(Consumer::new(3, make_demand(vec![(0., 4.), (4., 0.)])));
+        match Simulation::from_builder(builder) {
+            Err(SimError::Validation(errors)) => {
+                assert_eq!(errors, vec![BuilderError::Consumer { index: 0, city: 3 }])
+            }
+            _ => panic!("scenario should be invalid"),
+        }
+    }
+
+    fn valid_builder() -> SimulationBuilder {
+        SimulationBuilder::new(1)
+            .with_city(City::new(0, "first".to_string()))
+            .with_city(City::new(1, "second".to_string()))
+            .with_connection(Connection::new(0, 1, Price::new(1.)))
+            .with_initial_price(0, Price::new(1.))
+            .with_consumer(Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)])))
+            .with_producer(Producer::new(1, make_supply(vec![(0., 0.), (4., 4.)])))
+    }
+
+    #[test]
+    fn validate_valid() {
+        assert_eq!(valid_builder().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_producer_city() {
+        let builder = valid_builder()
+            .with_producer(Producer::new(5, make_supply(vec![(0., 0.), (4., 4.)])))
+            .with_producer(
+                Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).with_targets(vec![0, 6]),
+            );
+        assert_eq!(
+            builder.validate(),
+            Err(vec![
+                BuilderError::Producer { index: 1, city: 5 },
+                BuilderError::Producer { index: 2, city: 6 },
+            ])
+        );
+    }
+
+    #[test]
+    fn validate_unreachable_target() {
+        let builder = valid_builder()
+            .with_city(City::new(2, "third".to_string()))
+            .with_city(City::new(3, "island".to_string()))
+            .with_connection(Connection::new(1, 2, Price::new(1.)))
+            .with_producer(
+                Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)])).with_targets(vec![2, 3]),
+            );
+        assert_eq!(
+            builder.validate(),
+            Err(vec![BuilderError::Unreachable { index: 1, city: 3 }])
+        );
+    }
+
+    #[test]
+    fn validate_consumer_city() {
+        let builder =
+            valid_builder().with_consumer(Consumer::new(7, make_demand(vec![(0., 4.), (4., 0.)])));
+        assert_eq!(
+            builder.validate(),
+            Err(vec![BuilderError::Consumer { index: 1, city: 7 }])
+        );
+    }
+
+    #[test]
+    fn validate_connection_city() {
+        let builder = valid_builder().with_connection(Connection::new(8, 9, Price::new(1.)));
+        assert_eq!(
+            builder.validate(),
+            Err(vec![
+                BuilderError::Connection { index: 1, city: 8 },
+                BuilderError::Connection { index: 1, city: 9 },
+            ])
+        );
+    }
+
+    #[test]
+    fn validate_initial_price_city() {
+        let builder = valid_builder().with_initial_price(4, Price::new(1.));
+        assert_eq!(
+            builder.validate(),
+            Err(vec![BuilderError::InitialPrice { city: 4 }])
+        );
+    }
+
+    #[test]
+    fn read_gzipped_file() {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("simulation-tests/two-nodes-1.json");
+        let output = std::env::temp_dir().join("global_market_two_nodes_1.json.gz");
+        let mut encoder = GzEncoder::new(File::create(&output).unwrap(), Compression::default());
+        encoder.write_all(&std::fs::read(&input).unwrap()).unwrap();
+        encoder.finish().unwrap();
+
+        let mut plain = Simulation::read_from_file(&input).unwrap();
+        let mut gzipped = Simulation::read_from_file(&output).unwrap();
+        plain.run();
+        gzipped.run();
+        let plain_prices = plain.market.prices();
+        let gzipped_prices = gzipped.market.prices();
+        assert_eq!(plain_prices.len(), gzipped_prices.len());
+        for (city, price) in plain_prices {
+            test_eq_arg(gzipped_prices[&city].unwrap(), price.unwrap());
+        }
+    }
+
+    #[test]
+    fn default_price() {
+        let builder = SimulationBuilder::new(1)
+            .with_city(City::new(0, "city".to_string()))
+            .with_city(City::new(1, "town".to_string()))
+            .with_city(City::new(2, "village".to_string()))
+            .with_default_price(Price::new(3.));
+        let simulation = Simulation::from_builder(builder.clone()).unwrap();
+        for price in simulation.market.prices().into_values() {
+            test_eq_arg(price.unwrap(), Price::new(3.));
+        }
+
+        let builder = builder.with_initial_price(1, Price::new(5.));
+        let prices = Simulation::from_builder(builder).unwrap().market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(3.));
+        test_eq_arg(prices[&1].unwrap(), Price::new(5.));
+        test_eq_arg(prices[&2].unwrap(), Price::new(3.));
+    }
+
+    #[test]
+    fn partial_initial_prices() {
+        let scenario = r#"{
+            "turns": 3,
+            "cities": [{"id": 0, "name": "city"}, {"id": 1, "name": "town"}, {"id": 2, "name": "village"}],
+            "connections": [{"id_from": 0, "id_to": 1, "cost": 1}],
+            "initial_prices": [[0, 2]],
+            "producers": [
+                {"city": 0, "production_costs": {"function": [[0, 0], [4, 4]]}},
+                {"city": 1, "production_costs": {"function": [[0, 0], [4, 4]]}}
+            ],
+            "consumers": [
+                {"city": 0, "usefulness": {"function": [[0, 4], [4, 0]]}},
+                {"city": 1, "usefulness": {"function": [[0, 6], [6, 0]]}}
+            ]
+        }"#;
+        let mut simulation = Simulation::read_from_reader(scenario.as_bytes()).unwrap();
+        let prices = simulation.market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(2.));
+        assert!(prices[&1].is_none());
+        assert!(prices[&2].is_none());
+
+        simulation.run();
+        let prices = simulation.market.prices();
+        assert!(prices[&0].is_some());
+        assert!(prices[&1].is_some());
+        assert!(prices[&2].is_none());
+        assert!(!simulation.render_png().unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_from_str() {
+        let scenario = r#"{
+            "turns": 1,
+            "cities": [{"id": 0, "name": "city"}],
+            "connections": [],
+            "initial_prices": [],
+            "producers": [{"city": 0, "production_costs": {"function": [[0, 0], [4, 4]]}}],
+            "consumers": [{"city": 0, "usefulness": {"function": [[0, 4], [4, 0]]}}]
+        }"#;
+        let mut simulation = Simulation::read_from_reader(scenario.as_bytes()).unwrap();
+        simulation.run();
+        test_eq_arg(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+
+        assert!(Simulation::read_from_reader("{}".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_with_comments() {
+        let scenario = r#"{
+            // a single market
+            "turns": 1,
+            "cities": [{"id": 0, "name": "city",},],
+            "connections": [],
+            "initial_prices": [],
+            /* linear curves crossing at 2 */
+            "producers": [{"city": 0, "production_costs": {"function": [[0, 0], [4, 4],]}}],
+            "consumers": [{"city": 0, "usefulness": {"function": [[0, 4], [4, 0]]}}],
+        }"#;
+        let mut simulation = Simulation::read_from_reader(scenario.as_bytes()).unwrap();
+        simulation.run();
+        test_eq_arg(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+
+        assert!(matches!(
+            Simulation::read_from_reader("{ // unterminated".as_bytes()),
+            Err(SimError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn read_errors() {
+        let missing = std::env::temp_dir().join("global_market_missing/scenario.json");
+        assert!(matches!(
+            Simulation::read_from_file(missing),
+            Err(SimError::Io(_))
+        ));
+        assert!(matches!(
+            Simulation::read_from_reader("{}".as_bytes()),
+            Err(SimError::Parse(_))
+        ));
+        assert!(matches!(
+            Simulation::read_from_reader(r#"{"turns": 1, "cities": [], "connections": [], "initial_prices": [[0, 1]], "producers": [], "consumers": []}"#.as_bytes()),
+            Err(SimError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn run_and_plot_errors() {
+        let mut simulation = three_node_simulation(1);
+        assert!(matches!(
+            simulation.run_until_stable(Price::new(1e-6)).into_result(),
+            Err(SimError::NonConvergence(1))
+        ));
+
+        let output = std::env::temp_dir().join("global_market_missing/plot.png");
+        assert!(matches!(
+            simulation.plot(output.to_str().unwrap()),
+            Err(SimError::Plot(_))
+        ));
+
+        let mut simulation = Simulation::new(1, Geography::new(), BTreeMap::new());
+        assert!(matches!(
+            simulation.render_png(),
+            Err(SimError::Geography(_))
+        ));
+    }
+
+    #[test]
+    fn read_non_monotone_demand() {
+        let scenario = r#"{
+            "turns": 1,
+            "cities": [{"id": 0, "name": "city"}],
+            "connections": [],
+            "initial_prices": [],
+            "producers": [],
+            "consumers": [{"city": 0, "usefulness": {"function": [[0, 4], [2, 1], [4, 2]]}}]
+        }"#;
+        let error = Simulation::read_from_reader(scenario.as_bytes())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid demand"), "{}", error);
+        assert!(error.contains("price 4"), "{}", error);
+    }
+
+    #[test]
+    fn read_empty_scenario() {
+        let scenario = r#"{
+            "turns": 3,
+            "cities": [],
+            "connections": [],
+            "initial_prices": [],
+            "producers": [],
+            "consumers": []
+        }"#;
+        let mut simulation = Simulation::read_from_reader(scenario.as_bytes()).unwrap();
+        simulation.run();
+        assert!(simulation.market.prices().is_empty());
+        assert_eq!(simulation.results().market.cities.len(), 0);
+
+        let output = std::env::temp_dir().join("global_market_empty.png");
+        let error = simulation.plot(output.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "scenario has no cities");
+        assert!(!output.exists());
+        assert!(simulation.render_png().is_err());
+    }
+
+    #[test]
+    fn read_city_metadata() {
+        let scenario = r#"{
+            "turns": 1,
+            "cities": [
+                {"id": 0, "name": "capital", "population": 1800000, "region": "central"},
+                {"id": 1, "name": "village"}
+            ],
+            "connections": [],
+            "initial_prices": [],
+            "producers": [],
+            "consumers": []
+        }"#;
+        let simulation = Simulation::read_from_reader(scenario.as_bytes()).unwrap();
+        let cities = &simulation.market.geography().cities;
+        assert_eq!(cities[&0].population(), Some(1800000));
+        assert_eq!(cities[&0].region(), Some("central"));
+        assert_eq!(cities[&1].population(), None);
+        assert_eq!(cities[&1].region(), None);
+
+        let city = City::new(2, "town".to_string())
+            .with_population(5000)
+            .with_region("north".to_string());
+        assert_eq!(city.population(), Some(5000));
+        assert_eq!(city.region(), Some("north"));
+    }
+
+    #[test]
+    fn plot_per_city() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "first city".to_string()));
+        geography.add_city(City::new(1, "second".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+        simulation.run();
+
+        let dir = std::env::temp_dir().join("global_market_plot_per_city");
+        std::fs::create_dir_all(&dir).unwrap();
+        simulation.plot_per_city(dir.to_str().unwrap()).unwrap();
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["0_first_city.png", "1_second.png"]);
+
+        let sequential = std::env::temp_dir().join("global_market_plot_per_city_sequential");
+        std::fs::create_dir_all(&sequential).unwrap();
+        for city in simulation.market.geography().cities() {
+            Simulation::plot_city_to_file(
+                &simulation.market,
+                &simulation.theme,
+                &simulation.plot_config,
+                sequential.to_str().unwrap(),
+                city,
+            )
+            .unwrap();
+        }
+        for file in files {
+            assert_eq!(
+                std::fs::read(dir.join(&file)).unwrap(),
+                std::fs::read(sequential.join(&file)).unwrap()
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(sequential).unwrap();
+    }
+
+    #[test]
+    fn plot_theme() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+        simulation.run();
+
+        let output = std::env::temp_dir().join("global_market_plot_theme.png");
+        let render = |simulation: &mut Simulation| {
+            simulation.plot(output.to_str().unwrap()).unwrap();
+            std::fs::read(&output).unwrap()
+        };
+        let default = render(&mut simulation);
+        simulation.set_theme(PlotTheme::light());
+        assert_eq!(render(&mut simulation), default);
+        simulation.set_theme(PlotTheme::dark());
+        assert_ne!(render(&mut simulation), default);
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn render_png() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        geography.add_city(City::new(1, "town".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            1,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+        simulation.run();
+
+        let bytes = simulation.render_png().unwrap();
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let image = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(image.width(), PLOT_SIZE_X);
+        assert_eq!(image.height(), HEAD_SIZE_Y + 2 * PLOT_SIZE_Y);
+    }
+
+    #[test]
+    fn plot_config_ranges() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        geography.add_city(City::new(1, "town".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            1,
+            make_supply(vec![(1., 0.), (9., 2.)]),
+        )));
+        simulation.run();
+
+        let derived = PlotConfig::default();
+        let city = simulation.market.city_data(0).unwrap();
+        let ((min_x, max_x), (min_y, max_y)) = Simulation::plot_ranges(&city, &derived);
+        test_eq_arg(min_x, Price::zero());
+        test_eq_arg(max_x, Price::new(4.));
+        test_eq_value(min_y, Volume::zero());
+        test_eq_value(max_y, Volume::new(4.4));
+        drop(city);
+
+        let forced = PlotConfig {
+            x_range: Some((Price::new(-1.), Price::new(10.))),
+            y_range: Some((Volume::zero(), Volume::new(5.))),
+            ..PlotConfig::default()
+        };
+        for id in [0, 1] {
+            let city = simulation.market.city_data(id).unwrap();
+            let ((min_x, max_x), (min_y, max_y)) = Simulation::plot_ranges(&city, &forced);
+            assert_eq!((min_x, max_x), (Price::new(-1.), Price::new(10.)));
+            assert_eq!((min_y, max_y), (Volume::zero(), Volume::new(5.)));
+        }
+
+        let derived_png = simulation.render_png().unwrap();
+        simulation.set_plot_config(forced);
+        let forced_png = simulation.render_png().unwrap();
+        assert_ne!(derived_png, forced_png);
+
+        simulation.set_plot_config(PlotConfig {
+            commodity: Commodity(1),
+            ..PlotConfig::default()
+        });
+        assert!(matches!(simulation.render_png(), Err(SimError::Plot(_))));
+    }
+
+    #[test]
+    fn render_png_flat_curves() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+        geography.add_city(City::new(1, "town".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(0, make_demand(vec![(2., 3.)]))));
+        simulation.add_agent(Box::new(Producer::new(0, make_supply(vec![(2., 3.)]))));
+        simulation.add_agent(Box::new(Consumer::new(1, make_demand(vec![(1., 0.)]))));
+        simulation.add_agent(Box::new(Producer::new(1, make_supply(vec![(1., 0.)]))));
+        simulation.run();
+
+        let bytes = simulation.render_png().unwrap();
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn plot_deadweight_loss() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let mut simulation = Simulation::new(1, geography, BTreeMap::new());
+        simulation.add_agent(Box::new(Consumer::new(
+            0,
+            make_demand(vec![(0., 4.), (4., 0.)]),
+        )));
+        simulation.add_agent(Box::new(Producer::new(
+            0,
+            make_supply(vec![(0., 0.), (4., 4.)]),
+        )));
+
+        /* pixels of the shade blended over the white background */
+        let shade = PlotTheme::default().deadweight_loss;
+        let blend = |c: u8| (c as f64 * SHADE_OPACITY + 255. * (1. - SHADE_OPACITY)) as i32;
+        let expected = [blend(shade.0), blend(shade.1), blend(shade.2)];
+        let shaded_pixels = |simulation: &mut Simulation| {
+            simulation.run();
+            let bytes = simulation.render_png().unwrap();
+            let image = image::load_from_memory(&bytes).unwrap().to_rgb8();
+            image
+                .pixels()
+                .filter(|pixel| {
+                    pixel
+                        .0
+                        .iter()
+                        .zip(expected)
+                        .all(|(x, y)| (*x as i32 - y).abs() <= 2)
+                })
+                .count()
+        };
+
+        assert_eq!(shaded_pixels(&mut simulation), 0);
+        simulation.market.set_tax(0, Price::new(1.));
+        assert!(shaded_pixels(&mut simulation) > 1000);
+    }
 }
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use crate::economy::commodity::Commodity;
+use crate::economy::geography::CityId;
+use crate::economy::market::MarketState;
+use crate::economy::types::Price;
+use crate::economy::types::Volume;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CitySnapshot {
+    pub id: CityId,
+    #[serde(default)]
+    pub commodity: Commodity,
+    pub price: Option<Price>,
+    pub demand_volume: Option<Volume>,
+    pub supply_volume: Option<Volume>,
+    #[serde(default)]
+    pub per_capita_consumption: Option<f64>,
+    pub state: MarketState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarketSnapshot {
+    pub cities: Vec<CitySnapshot>,
+}
+
+impl MarketSnapshot {
+    /// State of the city for the default commodity.
+    pub fn city(&self, id: CityId) -> Option<&CitySnapshot> {
+        self.commodity_city(Commodity::default(), id)
+    }
+
+    pub fn commodity_city(&self, commodity: Commodity, id: CityId) -> Option<&CitySnapshot> {
+        self.cities
+            .iter()
+            .find(|x| x.commodity == commodity && x.id == id)
+    }
+}
+
+/// Results of a simulation, the state of the market after given number of
+/// turns.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimulationResults {
+    pub turns: usize,
+    pub market: MarketSnapshot,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::economy::entity::Consumer;
+    use crate::economy::entity::Producer;
+    use crate::economy::geography::City;
+    use crate::economy::geography::Geography;
+    use crate::economy::market::Market;
+    use crate::economy::market::MarketState;
+    use crate::economy::snapshot::MarketSnapshot;
+    use crate::economy::types::Price;
+    use crate::economy::types::Volume;
+    use crate::util::testing::make_demand;
+    use crate::util::testing::make_supply;
+    use crate::util::testing::test_eq_arg;
+    use crate::util::testing::test_eq_value;
+
+    #[test]
+    fn single_node_serialization() {
+        let mut geography = Geography::new();
+        geography.add_city(City::new(0, "city".to_string()));
+
+        let city_consumption = Consumer::new(0, make_demand(vec![(0., 4.), (4., 0.)]));
+        let city_production = Producer::new(0, make_supply(vec![(0., 0.), (4., 4.)]));
+
+        let mut market = Market::new(geography, BTreeMap::new());
+        market.add_consumer(&city_consumption);
+        market.add_producer(&city_production);
+        market.simulate(1);
+
+        let json = serde_json::to_string(&market.snapshot()).unwrap();
+        let snapshot: MarketSnapshot = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(snapshot.cities.len(), 1);
+        let city = snapshot.city(0).unwrap();
+        test_eq_arg(city.price.unwrap(), Price::new(2.));
+        test_eq_value(city.demand_volume.unwrap(), Volume::new(2.));
+        test_eq_value(city.supply_volume.unwrap(), Volume::new(2.));
+        assert!(matches!(city.state, MarketState::Equilibrium(..)));
+    }
+}
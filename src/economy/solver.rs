@@ -0,0 +1,154 @@
+use std::fmt::Debug;
+
+use crate::economy::function::Demand;
+use crate::economy::function::FunctionAbstract;
+use crate::economy::function::Supply;
+use crate::economy::function::DEFAULT_TOLERANCE;
+use crate::economy::market::MarketState;
+use crate::economy::types::InnerValue;
+use crate::economy::types::Price;
+use crate::economy::types::Volume;
+
+/// Precision of equilibrium prices relative to the scale of prices.
+const RELATIVE_TOLERANCE: InnerValue = 1e-9;
+
+/* limit of price adjustments made by the tatonnement in one turn */
+const TATONNEMENT_STEPS: usize = 10_000;
+
+/// Method of finding the equilibrium of the aggregated demand and supply of
+/// a price group. Groups are solved in parallel, so the strategy has to be
+/// shareable between threads.
+pub trait SolverStrategy: Debug + Send + Sync {
+    /// `start` is the price of the group found in the last turn, if any.
+    /// `crossings` are the prices and volumes at which the curves cross, as
+    /// found by `Demand::intersections`.
+    fn solve(
+        &self,
+        demand: &Demand,
+        supply: &Supply,
+        start: Option<Price>,
+        crossings: &[(Price, Volume)],
+    ) -> MarketState;
+}
+
+/// Intersection of the curves found by bisection, with the precision
+/// relative to the largest price at which any of the curves changes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IntersectionSolver;
+
+impl IntersectionSolver {
+    fn tolerance(demand: &Demand, supply: &Supply) -> Price {
+        let scale = [
+            demand.function().min_arg(),
+            demand.function().max_arg(),
+            supply.function().min_arg(),
+            supply.function().max_arg(),
+        ]
+        .into_iter()
+        .map(|x| x.abs())
+        .max()
+        .unwrap();
+        if scale == Price::zero() {
+            Price::new(DEFAULT_TOLERANCE)
+        } else {
+            scale * RELATIVE_TOLERANCE
+        }
+    }
+
+    /// Precision of volumes relative to the largest volume of the curves.
+    fn volume_tolerance(demand: &Demand, supply: &Supply) -> Volume {
+        let scale = [
+            demand.function().min_value(),
+            demand.function().max_value(),
+            supply.function().min_value(),
+            supply.function().max_value(),
+        ]
+        .into_iter()
+        .map(|x| x.abs())
+        .max()
+        .unwrap();
+        if scale == Volume::zero() {
+            Volume::new(DEFAULT_TOLERANCE)
+        } else {
+            scale * RELATIVE_TOLERANCE
+        }
+    }
+}
+
+impl SolverStrategy for IntersectionSolver {
+    fn solve(
+        &self,
+        demand: &Demand,
+        supply: &Supply,
+        _start: Option<Price>,
+        crossings: &[(Price, Volume)],
+    ) -> MarketState {
+        let tol = IntersectionSolver::tolerance(demand, supply);
+        demand.intersect_with_crossings(supply, tol, crossings)
+    }
+}
+
+/// Walrasian tatonnement. Starting from the last price of the group, the
+/// price is raised proportionally to the excess demand, or lowered when
+/// supply exceeds demand, until the markets clear. Prices are kept between
+/// the lowest and the highest price at which any of the curves changes;
+/// an excess left at one of these ends means there is no equilibrium.
+/// A price that does not settle within the step limit is undefined.
+#[derive(Clone, Copy, Debug)]
+pub struct TatonnementSolver {
+    rate: InnerValue,
+}
+
+impl TatonnementSolver {
+    /// Solver changing the price by `rate` per unit of excess demand. Too
+    /// large rates make the price oscillate instead of converging.
+    ///
+    /// Panics if `rate` is not a positive number.
+    pub fn new(rate: InnerValue) -> TatonnementSolver {
+        assert!(
+            rate.is_finite() && rate > 0.,
+            "tatonnement rate has to be positive, got {}",
+            rate
+        );
+        TatonnementSolver { rate }
+    }
+}
+
+impl SolverStrategy for TatonnementSolver {
+    fn solve(
+        &self,
+        demand: &Demand,
+        supply: &Supply,
+        start: Option<Price>,
+        _crossings: &[(Price, Volume)],
+    ) -> MarketState {
+        let min_price = demand.function().min_arg().min(supply.function().min_arg());
+        let max_price = demand.function().max_arg().max(supply.function().max_arg());
+        let tol = IntersectionSolver::tolerance(demand, supply);
+        let volume_tol = IntersectionSolver::volume_tolerance(demand, supply);
+
+        let mut price = start
+            .unwrap_or((min_price + max_price) / 2.)
+            .clamp(min_price, max_price);
+        for _ in 0..TATONNEMENT_STEPS {
+            let excess = demand.value(price) - supply.value(price);
+            let next = (price + Price::new(excess.float() * self.rate)).clamp(min_price, max_price);
+            if excess.abs() < volume_tol || (next - price).abs() < tol {
+                if excess > Volume::zero() && price == max_price {
+                    return MarketState::UnderSupply;
+                }
+                if excess < Volume::zero() && price == min_price {
+                    return MarketState::OverSupply;
+                }
+                return MarketState::Equilibrium(
+                    price,
+                    demand.value(price),
+                    supply.value(price),
+                    price,
+                );
+            }
+            price = next;
+        }
+        MarketState::Undefined
+    }
+}
@@ -0,0 +1,66 @@
+use plotters::style::RGBColor;
+
+use crate::economy::commodity::Commodity;
+use crate::economy::types::Price;
+use crate::economy::types::Volume;
+
+/// Layout of the plots of cities. Ranges left unset are derived from the
+/// curves of every city separately; set ones are shared by all cities, so
+/// that they can be compared on the same scale. Only one commodity is
+/// plotted, the default one unless set otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlotConfig {
+    /// Lowest and highest price on the horizontal axis.
+    pub x_range: Option<(Price, Price)>,
+    /// Lowest and highest volume on the vertical axis.
+    pub y_range: Option<(Volume, Volume)>,
+    pub commodity: Commodity,
+}
+
+/// Colors used when plotting the results of a simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlotTheme {
+    pub supply: RGBColor,
+    pub demand: RGBColor,
+    pub exchange: RGBColor,
+    pub deadweight_loss: RGBColor,
+    pub background: RGBColor,
+    pub grid: RGBColor,
+    pub text: RGBColor,
+}
+
+impl PlotTheme {
+    /// Light theme with blue supply, red demand, green exchange and orange
+    /// deadweight loss.
+    pub fn light() -> PlotTheme {
+        PlotTheme {
+            supply: RGBColor(0, 0, 255),
+            demand: RGBColor(255, 0, 0),
+            exchange: RGBColor(0, 176, 0),
+            deadweight_loss: RGBColor(255, 165, 0),
+            background: RGBColor(255, 255, 255),
+            grid: RGBColor(0, 0, 0),
+            text: RGBColor(0, 0, 0),
+        }
+    }
+
+    /// Dark theme using the Okabe-Ito palette, which stays distinguishable
+    /// for colorblind viewers.
+    pub fn dark() -> PlotTheme {
+        PlotTheme {
+            supply: RGBColor(86, 180, 233),
+            demand: RGBColor(230, 159, 0),
+            exchange: RGBColor(0, 158, 115),
+            deadweight_loss: RGBColor(204, 121, 167),
+            background: RGBColor(32, 32, 32),
+            grid: RGBColor(224, 224, 224),
+            text: RGBColor(240, 240, 240),
+        }
+    }
+}
+
+impl Default for PlotTheme {
+    fn default() -> Self {
+        PlotTheme::light()
+    }
+}
@@ -1,7 +1,34 @@
 mod price;
+mod revenue;
 mod volume;
 
 pub use price::Price;
+pub use revenue::Revenue;
 pub use volume::Volume;
 
 pub type InnerValue = f64;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_div() {
+        assert_eq!(Price::new(3.).checked_div(2.), Some(Price::new(1.5)));
+        assert_eq!(Price::new(3.).checked_div(0.), None);
+        assert_eq!(Volume::zero().checked_div(0.), None);
+        assert_eq!(Revenue::new(-1.).checked_div(-0.), None);
+    }
+
+    #[test]
+    fn div_by_zero() {
+        assert_eq!(Volume::new(2.) / 0., Volume::new(InnerValue::INFINITY));
+        assert_eq!(Price::new(-2.) / 0., Price::new(InnerValue::NEG_INFINITY));
+    }
+
+    #[test]
+    #[should_panic]
+    fn div_zero_by_zero() {
+        let _ = Revenue::zero() / 0.;
+    }
+}
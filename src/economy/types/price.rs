@@ -4,6 +4,8 @@ use ordered_float::NotNan;
 use serde::{Deserialize, Serialize};
 
 use super::InnerValue;
+use super::Revenue;
+use super::Volume;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Price {
@@ -17,7 +19,6 @@ impl Price {
     pub fn max() -> Price {
         Price::new(InnerValue::MAX)
     }
-    #[allow(dead_code)]
     pub fn zero() -> Price {
         Price::new(0.)
     }
@@ -40,6 +41,16 @@ impl Price {
         self.value.into_inner()
     }
 
+    /// Division that is `None` instead of infinite or undefined when the
+    /// divisor is zero.
+    pub fn checked_div(self, rhs: InnerValue) -> Option<Price> {
+        if rhs == 0. {
+            None
+        } else {
+            Some(self / rhs)
+        }
+    }
+
     pub fn notnan(&self) -> NotNan<InnerValue> {
         self.value
     }
@@ -101,6 +112,16 @@ impl Mul<InnerValue> for Price {
     }
 }
 
+impl Mul<Volume> for Price {
+    type Output = Revenue;
+
+    fn mul(self, rhs: Volume) -> Self::Output {
+        Revenue::from_notnan(self.notnan() * rhs.notnan())
+    }
+}
+
+/// Dividing by zero gives an infinity, and panics if the dividend is zero
+/// as well. Use `checked_div` when the divisor may be zero.
 impl Div<InnerValue> for Price {
     type Output = Self;
 
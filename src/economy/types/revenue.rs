@@ -0,0 +1,149 @@
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use ordered_float::NotNan;
+use serde::{Deserialize, Serialize};
+
+use super::InnerValue;
+use super::Price;
+use super::Volume;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Revenue {
+    value: NotNan<InnerValue>,
+}
+
+impl Revenue {
+    pub fn min() -> Revenue {
+        Revenue::new(InnerValue::MIN)
+    }
+    pub fn max() -> Revenue {
+        Revenue::new(InnerValue::MAX)
+    }
+    pub fn zero() -> Revenue {
+        Revenue::new(0.)
+    }
+
+    pub fn new(value: InnerValue) -> Revenue {
+        Revenue::from_float(value)
+    }
+
+    pub fn from_float(value: InnerValue) -> Revenue {
+        Revenue {
+            value: NotNan::new(value).unwrap(),
+        }
+    }
+
+    pub fn from_notnan(value: NotNan<InnerValue>) -> Revenue {
+        Revenue { value }
+    }
+
+    pub fn float(&self) -> InnerValue {
+        self.value.into_inner()
+    }
+
+    /// Division that is `None` instead of infinite or undefined when the
+    /// divisor is zero.
+    pub fn checked_div(self, rhs: InnerValue) -> Option<Revenue> {
+        if rhs == 0. {
+            None
+        } else {
+            Some(self / rhs)
+        }
+    }
+
+    pub fn notnan(&self) -> NotNan<InnerValue> {
+        self.value
+    }
+
+    pub fn abs(&self) -> Self {
+        if self.float() > 0. {
+            *self
+        } else {
+            -*self
+        }
+    }
+}
+
+impl AddAssign for Revenue {
+    fn add_assign(&mut self, rhs: Self) {
+        self.value += rhs.notnan();
+    }
+}
+
+impl Add for Revenue {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let mut res = self;
+        res.add_assign(rhs);
+        res
+    }
+}
+
+impl SubAssign for Revenue {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.value -= rhs.notnan();
+    }
+}
+
+impl Sub for Revenue {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut res = self;
+        res.sub_assign(rhs);
+        res
+    }
+}
+
+impl Neg for Revenue {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::from_notnan(-self.notnan())
+    }
+}
+
+impl Mul<InnerValue> for Revenue {
+    type Output = Self;
+
+    fn mul(self, rhs: InnerValue) -> Self::Output {
+        Revenue::from_notnan(self.notnan() * rhs)
+    }
+}
+
+/// Dividing by zero gives an infinity, and panics if the dividend is zero
+/// as well. Use `checked_div` when the divisor may be zero.
+impl Div<InnerValue> for Revenue {
+    type Output = Self;
+
+    fn div(self, rhs: InnerValue) -> Self::Output {
+        Revenue::from_notnan(self.notnan() / rhs)
+    }
+}
+
+impl Div<Volume> for Revenue {
+    type Output = Price;
+
+    fn div(self, rhs: Volume) -> Self::Output {
+        Price::from_notnan(self.notnan() / rhs.notnan())
+    }
+}
+
+impl Serialize for Revenue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.float().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Revenue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        InnerValue::deserialize(deserializer).map(Revenue::new)
+    }
+}
@@ -11,11 +11,9 @@ pub struct Volume {
 }
 
 impl Volume {
-    #[allow(dead_code)]
     pub fn min() -> Volume {
         Volume::new(InnerValue::MIN)
     }
-    #[allow(dead_code)]
     pub fn max() -> Volume {
         Volume::new(InnerValue::MAX)
     }
@@ -41,11 +39,20 @@ impl Volume {
         self.value.into_inner()
     }
 
+    /// Division that is `None` instead of infinite or undefined when the
+    /// divisor is zero.
+    pub fn checked_div(self, rhs: InnerValue) -> Option<Volume> {
+        if rhs == 0. {
+            None
+        } else {
+            Some(self / rhs)
+        }
+    }
+
     pub fn notnan(&self) -> NotNan<InnerValue> {
         self.value
     }
 
-    #[allow(dead_code)]
     pub fn abs(&self) -> Self {
         if self.float() > 0. {
             *self
@@ -103,6 +110,8 @@ impl Mul<InnerValue> for Volume {
     }
 }
 
+/// Dividing by zero gives an infinity, and panics if the dividend is zero
+/// as well. Use `checked_div` when the divisor may be zero.
 impl Div<InnerValue> for Volume {
     type Output = Self;
 
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+use crate::economy::types::Revenue;
+
+/// Welfare summed over all cities in an equilibrium.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct WelfareReport {
+    pub consumer_surplus: Revenue,
+    pub producer_surplus: Revenue,
+    /// Negative when subsidies exceed taxes.
+    pub tax_revenue: Revenue,
+    /// Sum of surpluses and tax revenue.
+    pub total: Revenue,
+    /// Welfare lost in cities with a tax or a price bound, estimated from
+    /// their own supply and demand. Only cities alone in their price group
+    /// are counted, see `Market::deadweight_loss_range`.
+    pub deadweight_loss: Revenue,
+}
@@ -0,0 +1,7 @@
+//! Simulation of prices on a market where transport between cities is
+//! costly. The binary reads a scenario and saves the results; the library
+//! exposes the same tools to other programs and to integration tests.
+
+pub mod economy;
+
+pub mod util;
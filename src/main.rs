@@ -1,34 +1,87 @@
-mod economy;
-mod util;
+mod cli;
 
 use std::path::Path;
 
-use crate::economy::simulation::Simulation;
+use clap::Parser;
+
+use global_market::economy::error::SimError;
+use global_market::economy::simulation::Simulation;
+
+use crate::cli::{Args, Format};
 
 fn main() {
     /* get command line arguments from user */
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: <input file path> <output file path>\n");
-        std::process::exit(1);
-    }
-
-    /* convert input string into file path */
-    let input_path = Path::new(&args[1]);
-    let output_path = Path::new(&args[2]);
+    let args = Args::parse();
+    let input_path = args.input.as_path();
+    let output_path = args.output.as_path();
 
     /* check if files exist */
-    assert!(input_path.is_file());
+    if !input_path.is_file() {
+        eprintln!("could not open {}: no such file", input_path.display());
+        std::process::exit(1);
+    }
+    let output_dir = match output_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !output_dir.is_dir() {
+        eprintln!(
+            "could not write {}: directory {} does not exist",
+            output_path.display(),
+            output_dir.display()
+        );
+        std::process::exit(1);
+    }
+    let output_file = match output_path.to_str() {
+        Some(output_file) => output_file,
+        None => {
+            eprintln!(
+                "could not write {}: path is not valid UTF-8",
+                output_path.display()
+            );
+            std::process::exit(1);
+        }
+    };
 
     /* load the simulation */
-    let mut simulation = match Simulation::read_from_file(&input_path) {
-        Err(why) => {
+    let mut simulation = match Simulation::read_from_file(input_path) {
+        Err(SimError::Io(why)) => {
             eprintln!("could not open {}: {}", input_path.display(), why);
             std::process::exit(1);
         }
+        Err(SimError::Validation(errors)) => {
+            let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            eprintln!(
+                "invalid scenario {}: {}",
+                input_path.display(),
+                errors.join(", ")
+            );
+            std::process::exit(1);
+        }
+        Err(why) => {
+            eprintln!("invalid scenario {}: {}", input_path.display(), why);
+            std::process::exit(1);
+        }
         Ok(result) => result,
     };
+    if let Some(turns) = args.turns {
+        simulation.set_turns(turns);
+    }
 
     simulation.run();
-    simulation.plot(output_path.to_str().unwrap()).unwrap();
+
+    /* save the results */
+    let result = match args.format {
+        Format::Png => simulation.plot(output_file),
+        Format::Svg => simulation.plot_svg(output_file),
+        Format::Csv => simulation.write_csv(output_file).map_err(|x| x.into()),
+        Format::Json => simulation.write_json(output_file),
+    };
+    if let Err(why) = result {
+        eprintln!("could not write {}: {}", output_path.display(), why);
+        std::process::exit(1);
+    }
+    if !args.quiet {
+        println!("Results have been saved to {}", output_file);
+    }
 }
@@ -0,0 +1,230 @@
+//! Importer of scenarios given as a directory of CSV tables.
+//!
+//! Every table has a header row. The directory holds:
+//!
+//! ```text
+//! cities.csv          id,name[,population,region]
+//! connections.csv     id_from,id_to,cost[,capacity]
+//! initial_prices.csv  city,price                      (optional)
+//! producers.csv       id,city,price,quantity
+//! consumers.csv       id,city,price,quantity
+//! ```
+//!
+//! Producers and consumers are given point by point: rows sharing an id
+//! form one curve, in the order of the first row of each id. The scenario
+//! is run for a single turn, unless changed with `set_turns`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::economy::entity::AgentId;
+use crate::economy::entity::Consumer;
+use crate::economy::entity::Producer;
+use crate::economy::error::SimError;
+use crate::economy::function::Demand;
+use crate::economy::function::FunctionError;
+use crate::economy::function::Supply;
+use crate::economy::geography::City;
+use crate::economy::geography::CityId;
+use crate::economy::geography::Connection;
+use crate::economy::simulation::SimulationBuilder;
+use crate::economy::types::Price;
+use crate::economy::types::Volume;
+
+#[derive(Deserialize)]
+struct PriceRow {
+    city: CityId,
+    price: Price,
+}
+
+#[derive(Deserialize)]
+struct PointRow {
+    id: AgentId,
+    city: CityId,
+    price: Price,
+    quantity: Volume,
+}
+
+/// Rows of the table, or `None` if the file doesn't exist.
+fn read_table<T: DeserializeOwned>(dir: &Path, name: &str) -> Result<Option<Vec<T>>, SimError> {
+    let path = dir.join(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let error = |x: csv::Error| SimError::Csv(format!("{}: {}", name, x));
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(File::open(path)?)
+        .deserialize()
+        .collect::<Result<Vec<T>, csv::Error>>()
+        .map(Some)
+        .map_err(error)
+}
+
+fn read_required<T: DeserializeOwned>(dir: &Path, name: &str) -> Result<Vec<T>, SimError> {
+    read_table(dir, name)?.ok_or_else(|| SimError::Csv(format!("{}: missing", name)))
+}
+
+struct CurveRows {
+    id: AgentId,
+    city: CityId,
+    points: Vec<(Price, Volume)>,
+}
+
+/// Points of every curve with its city, by id in the order of appearance.
+fn curves(rows: Vec<PointRow>, name: &str) -> Result<Vec<CurveRows>, SimError> {
+    let mut curves: Vec<CurveRows> = vec![];
+    let mut index: BTreeMap<AgentId, usize> = BTreeMap::new();
+    for row in rows {
+        let curve = match index.get(&row.id) {
+            Some(i) => &mut curves[*i],
+            None => {
+                index.insert(row.id, curves.len());
+                curves.push(CurveRows {
+                    id: row.id,
+                    city: row.city,
+                    points: vec![],
+                });
+                curves.last_mut().unwrap()
+            }
+        };
+        if curve.city != row.city {
+            return Err(SimError::Csv(format!(
+                "{}: id {} is in cities {} and {}",
+                name, row.id, curve.city, row.city
+            )));
+        }
+        curve.points.push((row.price, row.quantity));
+    }
+    Ok(curves)
+}
+
+pub fn read_csv_dir<P: AsRef<Path>>(path: P) -> Result<SimulationBuilder, SimError> {
+    let dir = path.as_ref();
+    let mut builder = SimulationBuilder::new(1);
+    for city in read_required::<City>(dir, "cities.csv")? {
+        builder = builder.with_city(city);
+    }
+    for connection in read_required::<Connection>(dir, "connections.csv")? {
+        builder = builder.with_connection(connection);
+    }
+    for row in read_table::<PriceRow>(dir, "initial_prices.csv")?.unwrap_or_default() {
+        builder = builder.with_initial_price(row.city, row.price);
+    }
+
+    let invalid = |name: &str, id: AgentId, error: FunctionError| {
+        SimError::Csv(format!("{}: curve of id {}: {}", name, id, error))
+    };
+    let rows = read_required(dir, "producers.csv")?;
+    for curve in curves(rows, "producers.csv")? {
+        let supply = Supply::try_new(curve.points.into_iter())
+            .map_err(|x| invalid("producers.csv", curve.id, x))?;
+        builder = builder.with_producer(Producer::new(curve.city, supply).with_id(curve.id));
+    }
+    let rows = read_required(dir, "consumers.csv")?;
+    for curve in curves(rows, "consumers.csv")? {
+        let demand = Demand::try_new(curve.points.into_iter())
+            .map_err(|x| invalid("consumers.csv", curve.id, x))?;
+        builder = builder.with_consumer(Consumer::new(curve.city, demand).with_id(curve.id));
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use crate::economy::error::SimError;
+    use crate::economy::simulation::Simulation;
+    use crate::util::testing::test_eq_arg;
+
+    fn write_dir(name: &str, tables: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, content) in tables {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn two_nodes_like_json() {
+        let dir = write_dir(
+            "global_market_csv_two_nodes",
+            &[
+                ("cities.csv", "id,name\n0,Warsaw\n1,Cracow\n"),
+                ("connections.csv", "id_from,id_to,cost\n0,1,4\n"),
+                ("initial_prices.csv", "city,price\n0,0\n1,0\n"),
+                (
+                    "producers.csv",
+                    "id,city,price,quantity\n\
+                     0,0,1,0\n0,0,2,1\n0,0,3,3\n0,0,5,4\n\
+                     1,1,6,0\n1,1,8,2\n1,1,9,5\n1,1,10,6\n",
+                ),
+                (
+                    "consumers.csv",
+                    "id, city, price, quantity\n\
+                     0,0,0,6\n0,0,1,5\n0,0,2,3\n0,0,3,2\n0,0,4,0\n\
+                     1,1,5,9\n1,1,7,7\n1,1,8,4\n1,1,9,2\n1,1,11,1\n",
+                ),
+            ],
+        );
+        let json = Path::new(env!("CARGO_MANIFEST_DIR")).join("simulation-tests/two-nodes-1.json");
+        let mut expected = Simulation::read_from_file(json).unwrap();
+        let mut simulation = Simulation::read_from_csv_dir(&dir).unwrap();
+        assert_eq!(simulation.turns(), 1);
+        simulation.set_turns(expected.turns());
+
+        expected.run();
+        simulation.run();
+        let expected = expected.market.prices();
+        let prices = simulation.market.prices();
+        assert_eq!(prices.len(), expected.len());
+        for (city, price) in expected {
+            test_eq_arg(prices[&city].unwrap(), price.unwrap());
+        }
+    }
+
+    #[test]
+    fn invalid_tables() {
+        let cities = ("cities.csv", "id,name\n0,Warsaw\n");
+        let connections = ("connections.csv", "id_from,id_to,cost\n");
+        let consumers = ("consumers.csv", "id,city,price,quantity\n");
+
+        let dir = write_dir("global_market_csv_missing", &[cities, connections]);
+        assert!(matches!(
+            Simulation::read_from_csv_dir(dir),
+            Err(SimError::Csv(_))
+        ));
+
+        let producers = ("producers.csv", "id,city,price,quantity\n0,0,1,x\n");
+        let dir = write_dir(
+            "global_market_csv_malformed",
+            &[cities, connections, producers, consumers],
+        );
+        assert!(matches!(
+            Simulation::read_from_csv_dir(dir),
+            Err(SimError::Csv(_))
+        ));
+
+        let producers = (
+            "producers.csv",
+            "id,city,price,quantity\n0,0,1,2\n0,0,2,1\n",
+        );
+        let dir = write_dir(
+            "global_market_csv_not_monotone",
+            &[cities, connections, producers, consumers],
+        );
+        assert!(matches!(
+            Simulation::read_from_csv_dir(dir),
+            Err(SimError::Csv(_))
+        ));
+    }
+}
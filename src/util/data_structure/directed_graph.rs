@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Graph with directed edges labelled with values of type `E`. Neighbours of
+/// a node are kept in the order in which edges were added.
+#[derive(Clone, Debug)]
+pub struct DirectedGraph<N, E> {
+    edges: BTreeMap<N, Vec<(N, E)>>,
+}
+
+impl<N: Ord + Copy, E> DirectedGraph<N, E> {
+    pub fn new() -> DirectedGraph<N, E> {
+        DirectedGraph {
+            edges: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, node: N) {
+        self.edges.entry(node).or_default();
+    }
+
+    /// Adds an edge, adding its endpoints if they are not in the graph yet.
+    pub fn add_edge(&mut self, from: N, to: N, edge: E) {
+        self.add_node(to);
+        self.edges.entry(from).or_default().push((to, edge));
+    }
+
+    pub fn contains(&self, node: N) -> bool {
+        self.edges.contains_key(&node)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = N> + '_ {
+        self.edges.keys().copied()
+    }
+
+    /// Outgoing edges of given node, empty if there is no such node.
+    pub fn neighbors(&self, node: N) -> &[(N, E)] {
+        self.edges.get(&node).map(|x| x.as_slice()).unwrap_or(&[])
+    }
+
+    /// Nodes reachable from `start` in breadth-first order.
+    pub fn bfs(&self, start: N) -> Bfs<'_, N, E> {
+        Bfs {
+            graph: self,
+            visited: BTreeSet::from([start]),
+            queue: VecDeque::from([start]),
+        }
+    }
+
+    /// Nodes reachable from `start` in depth-first preorder.
+    pub fn dfs(&self, start: N) -> Dfs<'_, N, E> {
+        Dfs {
+            tree: self.dfs_tree(start),
+        }
+    }
+
+    /// Same as `dfs`, with every node given together with the node and the
+    /// edge it was reached by, `None` for `start`.
+    pub fn dfs_tree(&self, start: N) -> DfsTree<'_, N, E> {
+        DfsTree {
+            graph: self,
+            visited: BTreeSet::new(),
+            stack: vec![(start, None)],
+        }
+    }
+}
+
+impl<N: Ord + Copy, E> Default for DirectedGraph<N, E> {
+    fn default() -> Self {
+        DirectedGraph::new()
+    }
+}
+
+pub struct Bfs<'a, N, E> {
+    graph: &'a DirectedGraph<N, E>,
+    visited: BTreeSet<N>,
+    queue: VecDeque<N>,
+}
+
+impl<N: Ord + Copy, E> Iterator for Bfs<'_, N, E> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        let node = self.queue.pop_front()?;
+        for (next, _) in self.graph.neighbors(node) {
+            if self.visited.insert(*next) {
+                self.queue.push_back(*next);
+            }
+        }
+        Some(node)
+    }
+}
+
+pub struct Dfs<'a, N, E> {
+    tree: DfsTree<'a, N, E>,
+}
+
+impl<N: Ord + Copy, E> Iterator for Dfs<'_, N, E> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        self.tree.next().map(|(node, _)| node)
+    }
+}
+
+pub struct DfsTree<'a, N, E> {
+    graph: &'a DirectedGraph<N, E>,
+    visited: BTreeSet<N>,
+    stack: Vec<(N, Option<(N, &'a E)>)>,
+}
+
+impl<'a, N: Ord + Copy, E> Iterator for DfsTree<'a, N, E> {
+    type Item = (N, Option<(N, &'a E)>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, parent)) = self.stack.pop() {
+            if !self.visited.insert(node) {
+                continue;
+            }
+            /* reversed, so that the first neighbour is visited first */
+            for (next, edge) in self.graph.neighbors(node).iter().rev() {
+                if !self.visited.contains(next) {
+                    self.stack.push((*next, Some((node, edge))));
+                }
+            }
+            return Some((node, parent));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::data_structure::directed_graph::DirectedGraph;
+
+    fn sample_graph() -> DirectedGraph<usize, f64> {
+        let mut graph = DirectedGraph::new();
+        graph.add_edge(0, 1, 1.);
+        graph.add_edge(0, 2, 2.);
+        graph.add_edge(1, 3, 3.);
+        graph.add_edge(2, 3, 4.);
+        graph.add_edge(3, 0, 5.);
+        graph.add_node(4);
+        graph
+    }
+
+    #[test]
+    fn insertion() {
+        let graph = sample_graph();
+        assert_eq!(graph.nodes().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(graph.neighbors(0), &[(1, 1.), (2, 2.)]);
+        assert_eq!(graph.neighbors(3), &[(0, 5.)]);
+        assert!(graph.neighbors(4).is_empty());
+        assert!(graph.neighbors(7).is_empty());
+        assert!(graph.contains(4));
+        assert!(!graph.contains(7));
+    }
+
+    #[test]
+    fn bfs_order() {
+        let graph = sample_graph();
+        assert_eq!(graph.bfs(0).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(graph.bfs(2).collect::<Vec<_>>(), vec![2, 3, 0, 1]);
+        assert_eq!(graph.bfs(4).collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn dfs_order() {
+        let graph = sample_graph();
+        assert_eq!(graph.dfs(0).collect::<Vec<_>>(), vec![0, 1, 3, 2]);
+        assert_eq!(graph.dfs(2).collect::<Vec<_>>(), vec![2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn dfs_tree_edges() {
+        let graph = sample_graph();
+        assert_eq!(
+            graph.dfs_tree(0).collect::<Vec<_>>(),
+            vec![
+                (0, None),
+                (1, Some((0, &1.))),
+                (3, Some((1, &3.))),
+                (2, Some((0, &2.)))
+            ]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Generic graphs used to describe connections between cities.
+
+pub mod directed_graph;
+
+pub mod weighted_graph;
+
+pub mod weighted_directed_graph;
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Directed graph whose edge weights may be negative.
+#[derive(Clone, Debug)]
+pub struct WeightedDirectedGraph<N> {
+    nodes: BTreeMap<N, Vec<(N, f64)>>,
+}
+
+/// Some cycle reachable from the source has negative total weight, so
+/// the shortest paths are not defined.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NegativeCycle;
+
+impl fmt::Display for NegativeCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "graph contains a negative cycle")
+    }
+}
+
+impl std::error::Error for NegativeCycle {}
+
+impl<N: Ord + Copy> WeightedDirectedGraph<N> {
+    pub fn new() -> WeightedDirectedGraph<N> {
+        WeightedDirectedGraph {
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, node: N) {
+        self.nodes.entry(node).or_default();
+    }
+
+    /// Adds an edge, adding its endpoints if they are not in the graph yet.
+    pub fn add_edge(&mut self, from: N, to: N, weight: f64) {
+        self.add_node(to);
+        self.nodes.entry(from).or_default().push((to, weight));
+    }
+
+    pub fn neighbors(&self, node: N) -> &[(N, f64)] {
+        self.nodes.get(&node).map(|x| x.as_slice()).unwrap_or(&[])
+    }
+
+    /// Length of the shortest path from given node to every node reachable
+    /// from it, computed with the Bellman-Ford algorithm.
+    pub fn shortest_paths(&self, from: N) -> Result<BTreeMap<N, f64>, NegativeCycle> {
+        let mut distances: BTreeMap<N, f64> = BTreeMap::from([(from, 0.)]);
+
+        for _ in 1..self.nodes.len() {
+            if !self.relax(&mut distances) {
+                return Ok(distances);
+            }
+        }
+        if self.relax(&mut distances) {
+            Err(NegativeCycle)
+        } else {
+            Ok(distances)
+        }
+    }
+
+    /// Relaxes every edge once. Returns whether any distance decreased.
+    fn relax(&self, distances: &mut BTreeMap<N, f64>) -> bool {
+        let mut changed = false;
+        for (node, edges) in &self.nodes {
+            let distance = match distances.get(node) {
+                Some(distance) => *distance,
+                None => continue,
+            };
+            for (next, weight) in edges {
+                let next_distance = distance + weight;
+                if distances.get(next).is_none_or(|x| next_distance < *x) {
+                    distances.insert(*next, next_distance);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl<N: Ord + Copy> Default for WeightedDirectedGraph<N> {
+    fn default() -> Self {
+        WeightedDirectedGraph::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::data_structure::weighted_directed_graph::NegativeCycle;
+    use crate::util::data_structure::weighted_directed_graph::WeightedDirectedGraph;
+
+    #[test]
+    fn shortest_paths() {
+        let mut graph = WeightedDirectedGraph::new();
+        graph.add_edge(0, 1, 4.);
+        graph.add_edge(0, 2, 5.);
+        graph.add_edge(2, 1, -3.);
+        graph.add_edge(1, 3, 2.);
+        graph.add_edge(3, 0, 1.);
+        graph.add_node(4);
+
+        let distances = graph.shortest_paths(0).unwrap();
+        assert_eq!(distances[&0], 0.);
+        assert_eq!(distances[&1], 2.);
+        assert_eq!(distances[&2], 5.);
+        assert_eq!(distances[&3], 4.);
+        assert!(!distances.contains_key(&4));
+
+        /* edges are directed */
+        let distances = graph.shortest_paths(1).unwrap();
+        assert_eq!(distances[&2], 8.);
+    }
+
+    #[test]
+    fn negative_cycle() {
+        let mut graph = WeightedDirectedGraph::new();
+        graph.add_edge(0, 1, 1.);
+        graph.add_edge(1, 2, -2.);
+        graph.add_edge(2, 1, 1.);
+        graph.add_edge(3, 4, -5.);
+
+        assert_eq!(graph.shortest_paths(0), Err(NegativeCycle));
+        /* cycle unreachable from the source doesn't matter */
+        assert!(graph.shortest_paths(3).is_ok());
+    }
+}
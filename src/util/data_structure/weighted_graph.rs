@@ -0,0 +1,85 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+use ordered_float::NotNan;
+
+/// Undirected graph with non-negative edge weights.
+#[derive(Clone, Debug)]
+pub struct WeightedGraph<N> {
+    edges: BTreeMap<N, Vec<(N, f64)>>,
+}
+
+impl<N: Ord + Copy> WeightedGraph<N> {
+    pub fn new() -> WeightedGraph<N> {
+        WeightedGraph {
+            edges: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, node: N) {
+        self.edges.entry(node).or_default();
+    }
+
+    /// Connects two nodes in both directions, adding them if needed.
+    pub fn add_edge(&mut self, a: N, b: N, weight: f64) {
+        self.edges.entry(a).or_default().push((b, weight));
+        self.edges.entry(b).or_default().push((a, weight));
+    }
+
+    pub fn neighbors(&self, node: N) -> &[(N, f64)] {
+        self.edges.get(&node).map(|x| x.as_slice()).unwrap_or(&[])
+    }
+
+    /// Length of the shortest path from given node to every node reachable
+    /// from it.
+    pub fn shortest_paths(&self, from: N) -> BTreeMap<N, f64> {
+        let mut distances: BTreeMap<N, f64> = BTreeMap::new();
+        let mut heap = BinaryHeap::new();
+        heap.push(Reverse((NotNan::new(0.).unwrap(), from)));
+
+        while let Some(Reverse((distance, node))) = heap.pop() {
+            if distances.contains_key(&node) {
+                continue;
+            }
+            distances.insert(node, distance.into_inner());
+            for (next, weight) in self.neighbors(node) {
+                if !distances.contains_key(next) {
+                    let next_distance = distance + NotNan::new(*weight).unwrap();
+                    heap.push(Reverse((next_distance, *next)));
+                }
+            }
+        }
+        distances
+    }
+}
+
+impl<N: Ord + Copy> Default for WeightedGraph<N> {
+    fn default() -> Self {
+        WeightedGraph::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::data_structure::weighted_graph::WeightedGraph;
+
+    #[test]
+    fn shortest_paths() {
+        let mut graph = WeightedGraph::new();
+        graph.add_edge(0, 1, 4.);
+        graph.add_edge(0, 2, 1.);
+        graph.add_edge(2, 1, 2.);
+        graph.add_edge(1, 3, 5.);
+        graph.add_edge(2, 3, 8.);
+        graph.add_node(4);
+
+        let distances = graph.shortest_paths(0);
+        assert_eq!(distances[&0], 0.);
+        assert_eq!(distances[&1], 3.);
+        assert_eq!(distances[&2], 1.);
+        assert_eq!(distances[&3], 8.);
+        assert!(!distances.contains_key(&4));
+
+        assert_eq!(graph.shortest_paths(3)[&2], 7.);
+    }
+}
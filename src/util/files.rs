@@ -0,0 +1,162 @@
+//! Importer of scenarios written in the legacy text format.
+//!
+//! The format is a sequence of whitespace separated tokens:
+//!
+//! ```text
+//! <turns>
+//! <number of cities>       then for each: <id> <name>
+//! <number of connections>  then for each: <from> <to> <cost>
+//! <number of prices>       then for each: <city> <price>
+//! <number of producers>    then for each: <city> <points> <price> <volume> ...
+//! <number of consumers>    then for each: <city> <points> <price> <volume> ...
+//! ```
+//!
+//! City names can not contain whitespace.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::economy::entity::Consumer;
+use crate::economy::entity::Producer;
+use crate::economy::function::Demand;
+use crate::economy::function::FunctionError;
+use crate::economy::function::Supply;
+use crate::economy::geography::City;
+use crate::economy::geography::CityId;
+use crate::economy::geography::Connection;
+use crate::economy::simulation::SimulationBuilder;
+use crate::economy::types::Price;
+use crate::economy::types::Volume;
+
+#[derive(Debug)]
+pub enum LegacyFormatError {
+    UnexpectedEnd,
+    InvalidToken(String),
+    InvalidFunction(FunctionError),
+}
+
+impl fmt::Display for LegacyFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegacyFormatError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            LegacyFormatError::InvalidToken(token) => write!(f, "invalid token '{}'", token),
+            LegacyFormatError::InvalidFunction(why) => write!(f, "invalid function: {}", why),
+        }
+    }
+}
+
+impl Error for LegacyFormatError {}
+
+struct Tokens<'a> {
+    tokens: std::str::SplitWhitespace<'a>,
+}
+
+impl Tokens<'_> {
+    fn next<T: FromStr>(&mut self) -> Result<T, LegacyFormatError> {
+        let token = self.tokens.next().ok_or(LegacyFormatError::UnexpectedEnd)?;
+        token
+            .parse()
+            .map_err(|_| LegacyFormatError::InvalidToken(token.to_string()))
+    }
+
+    fn points(&mut self) -> Result<Vec<(Price, Volume)>, LegacyFormatError> {
+        let count: usize = self.next()?;
+        (0..count)
+            .map(|_| Ok((Price::new(self.next()?), Volume::new(self.next()?))))
+            .collect()
+    }
+}
+
+pub fn read_legacy<R: Read>(mut reader: R) -> Result<SimulationBuilder, Box<dyn Error>> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    let mut tokens = Tokens {
+        tokens: input.split_whitespace(),
+    };
+
+    let mut builder = SimulationBuilder::new(tokens.next()?);
+    for _ in 0..tokens.next::<usize>()? {
+        builder = builder.with_city(City::new(tokens.next()?, tokens.next()?));
+    }
+    for _ in 0..tokens.next::<usize>()? {
+        let from: CityId = tokens.next()?;
+        let to: CityId = tokens.next()?;
+        builder = builder.with_connection(Connection::new(from, to, Price::new(tokens.next()?)));
+    }
+    for _ in 0..tokens.next::<usize>()? {
+        let city: CityId = tokens.next()?;
+        builder = builder.with_initial_price(city, Price::new(tokens.next()?));
+    }
+    for _ in 0..tokens.next::<usize>()? {
+        let city: CityId = tokens.next()?;
+        let supply = Supply::try_new(tokens.points()?.into_iter())
+            .map_err(LegacyFormatError::InvalidFunction)?;
+        builder = builder.with_producer(Producer::new(city, supply));
+    }
+    for _ in 0..tokens.next::<usize>()? {
+        let city: CityId = tokens.next()?;
+        let demand = Demand::try_new(tokens.points()?.into_iter())
+            .map_err(LegacyFormatError::InvalidFunction)?;
+        builder = builder.with_consumer(Consumer::new(city, demand));
+    }
+    Ok(builder)
+}
+
+pub fn read_legacy_file<P: AsRef<Path>>(path: P) -> Result<SimulationBuilder, Box<dyn Error>> {
+    read_legacy(File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::economy::simulation::Simulation;
+    use crate::economy::types::Price;
+    use crate::util::files::read_legacy;
+    use crate::util::testing::test_eq_arg;
+
+    #[test]
+    fn two_cities() {
+        let input = "
+            2
+            2
+            0 Warsaw
+            1 Cracow
+            1
+            0 1 1
+            0
+            2
+            0 2 0 0 4 4
+            1 2 0 0 8 8
+            2
+            0 2 0 4 4 0
+            1 2 0 8 8 0
+        ";
+        let builder = read_legacy(input.as_bytes()).unwrap();
+        let mut simulation = Simulation::from_builder(builder).unwrap();
+        simulation.run();
+
+        let prices = simulation.market.prices();
+        test_eq_arg(prices[&0].unwrap(), Price::new(2.5));
+        test_eq_arg(prices[&1].unwrap(), Price::new(3.5));
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(read_legacy("1 1 0".as_bytes()).is_err());
+        assert!(read_legacy("1 1 zero city".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn invalid_function() {
+        let input = "1 1 0 Warsaw 0 0 1 0 2 0 4 4 0 0";
+        let why = read_legacy(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            why.to_string(),
+            "invalid function: value changes in the wrong direction at price 4"
+        );
+        assert!(read_legacy("1 1 0 Warsaw 0 0 0 1 0 0".as_bytes()).is_err());
+    }
+}
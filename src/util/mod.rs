@@ -1,2 +1,10 @@
-#[cfg(test)]
+pub mod csv_dir;
+
+pub mod data_structure;
+
+pub mod files;
+
+/// Helpers for tests, also available to integration tests with the
+/// `testing` feature.
+#[cfg(any(test, feature = "testing"))]
 pub mod testing;
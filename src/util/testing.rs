@@ -1,8 +1,16 @@
 use crate::economy::{
     function::{ArgT, Demand, FunctionNullable, Supply, ValueT},
-    types::InnerValue,
+    types::{InnerValue, Revenue},
 };
 
+/// Largest difference relative to the larger of the compared numbers
+/// accepted by the `_rel` assertions.
+const RELATIVE_TOLERANCE: InnerValue = 1e-6;
+
+/// Differences below this are accepted by the `_rel` assertions even when
+/// both numbers are close to zero.
+const ABSOLUTE_FLOOR: InnerValue = 1e-12;
+
 fn prepare_values_for_function(values: Vec<(InnerValue, InnerValue)>) -> Vec<(ArgT, ValueT)> {
     values
         .iter()
@@ -15,27 +23,79 @@ pub fn make_function(values: Vec<(InnerValue, InnerValue)>) -> FunctionNullable
 }
 
 pub fn make_demand(values: Vec<(InnerValue, InnerValue)>) -> Demand {
-    Demand::new(prepare_values_for_function(values).into_iter())
+    Demand::from_rows(&values)
 }
 
 pub fn make_supply(values: Vec<(InnerValue, InnerValue)>) -> Supply {
-    Supply::new(prepare_values_for_function(values).into_iter())
+    Supply::from_rows(&values)
 }
 
 pub fn test_eq_arg(a: ArgT, b: ArgT) {
     let tolerance = ArgT::new(0.1);
-    if (a - b).abs() < tolerance {
-    } else {
-        print!("Assertion failed: {} != {}\n", a.float(), b.float());
-        assert!(false)
+    if (a - b).abs() >= tolerance {
+        panic!("Assertion failed: {} != {}", a.float(), b.float());
     }
 }
 
 pub fn test_eq_value(a: ValueT, b: ValueT) {
     let tolerance = ValueT::new(0.1);
-    if (a - b).abs() < tolerance {
-    } else {
-        print!("Assertion failed: {} != {}\n", a.float(), b.float());
-        assert!(false)
+    if (a - b).abs() >= tolerance {
+        panic!("Assertion failed: {} != {}", a.float(), b.float());
+    }
+}
+
+pub fn test_eq_revenue(a: Revenue, b: Revenue) {
+    let tolerance = Revenue::new(0.1);
+    if (a - b).abs() >= tolerance {
+        panic!("Assertion failed: {} != {}", a.float(), b.float());
+    }
+}
+
+fn eq_rel(a: InnerValue, b: InnerValue) -> bool {
+    let scale = a.abs().max(b.abs());
+    (a - b).abs() <= (scale * RELATIVE_TOLERANCE).max(ABSOLUTE_FLOOR)
+}
+
+/// Same as `test_eq_arg`, but with the tolerance relative to the compared
+/// prices, so it is tight for small ones and loose enough for large ones.
+pub fn test_eq_arg_rel(a: ArgT, b: ArgT) {
+    if !eq_rel(a.float(), b.float()) {
+        panic!("Assertion failed: {} != {}", a.float(), b.float());
+    }
+}
+
+/// Same as `test_eq_value`, with the tolerance as in `test_eq_arg_rel`.
+pub fn test_eq_value_rel(a: ValueT, b: ValueT) {
+    if !eq_rel(a.float(), b.float()) {
+        panic!("Assertion failed: {} != {}", a.float(), b.float());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_small_values() {
+        test_eq_arg_rel(ArgT::new(1e-9), ArgT::new(1e-9 + 1e-16));
+        test_eq_value_rel(ValueT::zero(), ValueT::new(1e-13));
+        assert!(!eq_rel(1e-3, 2e-3));
+        /* within the absolute tolerance, but clearly different */
+        assert!(
+            std::panic::catch_unwind(|| test_eq_arg_rel(ArgT::new(0.01), ArgT::new(0.05))).is_err()
+        );
+    }
+
+    #[test]
+    fn relative_large_values() {
+        test_eq_arg_rel(ArgT::new(1e9), ArgT::new(1e9 + 100.));
+        test_eq_value_rel(ValueT::new(-1e12), ValueT::new(-1e12 - 1e5));
+        assert!(!eq_rel(1e9, 1e9 + 1e4));
+    }
+
+    #[test]
+    #[should_panic]
+    fn relative_mismatch() {
+        test_eq_value_rel(ValueT::new(1e6), ValueT::new(1e6 + 10.));
     }
 }
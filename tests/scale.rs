@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use global_market::economy::geography::City;
+use global_market::economy::geography::Geography;
+use global_market::economy::market::Market;
+use global_market::economy::simulation::Simulation;
+use global_market::economy::types::InnerValue;
+use global_market::economy::types::Price;
+use global_market::economy::types::Volume;
+use global_market::util::testing::make_demand;
+use global_market::util::testing::make_supply;
+use global_market::util::testing::test_eq_arg_rel;
+use global_market::util::testing::test_eq_value_rel;
+
+fn single_node_at_scale(scale: InnerValue) -> Market {
+    let mut geography = Geography::new();
+    geography.add_city(City::new(0, "city".to_string()));
+
+    let mut market = Market::new(geography, BTreeMap::new());
+    market.add_demand(0, &make_demand(vec![(0., 4. * scale), (4. * scale, 0.)]));
+    market.add_supply(0, &make_supply(vec![(0., 0.), (4. * scale, 4. * scale)]));
+    market.simulate(1);
+    market
+}
+
+#[test]
+fn prices_and_volumes_across_scales() {
+    for scale in [1e-9, 1e-3, 1., 1e3, 1e12] {
+        let market = single_node_at_scale(scale);
+        test_eq_arg_rel(market.prices()[&0].unwrap(), Price::new(2. * scale));
+        test_eq_value_rel(
+            market.demand_volumes()[&0].unwrap(),
+            Volume::new(2. * scale),
+        );
+    }
+}
+
+#[test]
+fn scenario_file() {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("simulation-tests/single-node-1.json");
+    let mut simulation = Simulation::read_from_file(input).unwrap();
+    simulation.run();
+    test_eq_arg_rel(simulation.market.prices()[&0].unwrap(), Price::new(2.));
+}
@@ -0,0 +1,20 @@
+use std::fs;
+use std::path::Path;
+
+use global_market::economy::simulation::Simulation;
+
+#[test]
+fn every_scenario_loads_and_runs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("simulation-tests");
+    let mut count = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let mut simulation = match Simulation::read_from_file(&path) {
+            Ok(simulation) => simulation,
+            Err(why) => panic!("could not load {}: {}", path.display(), why),
+        };
+        simulation.run();
+        count += 1;
+    }
+    assert!(count > 0);
+}